@@ -14,7 +14,7 @@ use bindings::{
     exports::supabase::wrappers::routines::Guest,
     supabase::wrappers::{
         http, time, jwt,
-        types::{Cell, Context, FdwError, FdwResult, OptionsType, Row, TypeOid},
+        types::{Cell, Column, Context, FdwError, FdwResult, OptionsType, Row, TypeOid, Value as QualValue},
         utils,
     },
 };
@@ -26,7 +26,7 @@ use std::error::Error;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Builder;
 use hmac::{Hmac, Mac}; // `NewMac` は不要
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 use base64::{Engine as _, engine::general_purpose, engine::GeneralPurposeConfig};
 #[cfg(feature = "use_asn1")]
 use simple_asn1::{to_der, from_der, ASN1Block, ASN1Class, Tag};
@@ -42,10 +42,446 @@ use subtle::ConstantTimeEq;
 #[derive(Debug, Default)]
 struct SpreadsheetsFdw {
     base_url: String, // APIのベースURL。
+    // gviz_path サーバーオプション（既定 "gviz/tq"）。base_urlとID/クエリ文字列の間に挟む
+    // パスセグメントで、プロキシ経由でGoogleを叩く場合やGoogle側の将来的なパス変更に
+    // 追随するために差し替え可能にする。base_urlのテンプレート機構とは独立に併用できる。
+    gviz_path: String,
     src_rows: Vec<JsonValue>, // 取得したデータのJSON配列。
     src_idx: usize, // 現在のスキャン位置を示すインデックス。
+    // introspect = 'columns' が指定された場合の出力行（label / gviz型 / 推定Postgres型）。
+    // Some の間は iter_scan が通常のデータ行ではなくこちらを返す。
+    introspect_cols: Option<Vec<ColumnInfo>>,
+    // introspect = 'meta' が指定された場合の出力行（生のcolsメタデータ + シート単位のstatus/warnings）。
+    // Some の間は iter_scan が通常のデータ行ではなくこちらを返す。
+    introspect_meta: Option<Vec<ColumnMetaInfo>>,
+    // introspect = 'spreadsheet' が指定された場合の出力行（スプレッドシート単位のtitle/locale/
+    // timeZone/sheet_names）。常に1行だけを返す。Some の間は iter_scan が通常のデータ行ではなく
+    // こちらを返す。
+    introspect_spreadsheet: Option<SpreadsheetMetaInfo>,
+    // introspect = 'stats' が指定された場合の出力行（begin_scan開始時点のStatsシングルトンの
+    // スナップショット）。常に1行だけを返す。Some の間は iter_scan が通常のデータ行ではなく
+    // こちらを返す。
+    introspect_stats: Option<Stats>,
+    // introspect = 'version' が指定された場合にtrueになる。Some/Noneで情報を運ぶ必要が無い
+    // （固定のビルド時定数のみを返すため）ので、他のintrospect_*と違い単純なboolで持つ。
+    // trueの間は iter_scan が通常のデータ行ではなくビルド情報を1行だけ返す。
+    introspect_version: bool,
+    // introspect = 'developer_metadata' が指定された場合の出力行（アプリがシート/範囲に付与した
+    // key/valueメタデータ）。gvizのtqレスポンスには含まれずv4 APIでしか取得できない。
+    // Some の間は iter_scan が通常のデータ行ではなくこちらを返す。
+    introspect_developer_metadata: Option<Vec<DeveloperMetadataInfo>>,
+    // introspect = 'protected_ranges' が指定された場合の出力行（シートオーナーが設定した
+    // 保護範囲）。gvizのtqレスポンスには含まれずv4 APIでしか取得できない。
+    // Some の間は iter_scan が通常のデータ行ではなくこちらを返す。
+    introspect_protected_ranges: Option<Vec<ProtectedRangeInfo>>,
+    // introspect = 'rowcount' が指定された場合の出力行（データ行数を1件のbigintで返す）。
+    // COUNT(*)プッシュダウンとは別物で、行データそのものを取得せずにシートの行数だけを
+    // 安く知りたい（進捗バーや容量チェック向け）というユースケースに応える。
+    // Some の間は iter_scan が通常のデータ行ではなくこちらを返す。
+    introspect_rowcount: Option<i64>,
+    // サーバーオプションの http_headers をパースしたもの（テーブル側でのマージのベースになる）。
+    server_headers: Vec<(String, String)>,
+    // サーバーオプションの query_params をパースしたもの（テーブル側でのマージのベースになる）。
+    server_query_params: Vec<(String, String)>,
+    // begin_scanでserver_query_paramsとテーブルのquery_paramsをマージした最終形。iter_scan側の
+    // v4ページング（fetch_v4_page）のように、begin_scan後の別のタイミングでも同じ値が必要になる
+    // 呼び出し元向けに保持しておく。
+    query_params: Vec<(String, String)>,
+    // number_as_text_columns テーブルオプションで指定された列名の一覧。
+    // Column resourceには列単位のオプションを取得する手段がないため、テーブルオプション側で
+    // 対象列名をカンマ区切りで指定してもらう方式を取る（modified_columnと同じ発想）。
+    number_as_text_columns: Vec<String>,
+    // required_columns テーブルオプションで指定された、nullを許容しない列名の一覧。
+    required_columns: Vec<String>,
+    // required_columns違反時の振る舞い: "error"（既定, エラーで中断） / "null"（無視して通す） / "skip"（その行を読み飛ばす）。
+    on_row_error: String,
+    // allowed_values テーブルオプション（"col=v1:v2:v3,col2=v4:v5"形式）で指定された、列ごとの
+    // 許可値セット。String列の値がここに無ければon_row_errorポリシーに従って処理する
+    // （required_columnsと同じポリシーを共有するが、null値そのものは対象外で別判定）。
+    allowed_values: Vec<(String, Vec<String>)>,
+    // max_row_errors テーブルオプション（既定0=無制限）。on_row_error = 'skip' によって読み飛ばされた
+    // 行数がこの件数を超えたら、iter_scanをエラーで打ち切る。壊れたシート全体を'skip'で
+    // 読み進めた結果「クエリが黙って0行返ってきた」ように見えてしまうのを防ぐための安全弁。
+    // 0のままだと従来通り無制限になるが、begin_scanで一度report_warningを出す。
+    max_row_errors: usize,
+    // on_row_error = 'skip' によって実際に読み飛ばされた行数の累計。begin_scanのたびに0へリセットする。
+    rows_skipped_for_errors: usize,
+    // allowed_values_case_sensitive テーブルオプション（既定true）。falseならallowed_valuesとの
+    // 比較時に大文字小文字を無視する。
+    allowed_values_case_sensitive: bool,
+    // keyvalue = 'true' が指定された場合の出力行（key, value）。Someの間はiter_scanが通常の行の代わりにこちらを返す。
+    keyvalue_rows: Option<Vec<(String, String)>>,
+    // unpivot = 'true' が指定された場合の出力行（id列の値 + variable + value、ワイド→ロング変換後）。
+    // Someの間はiter_scanが通常の行の代わりにこちらを返す。
+    unpivot_rows: Option<Vec<UnpivotRow>>,
+    // source_format = 'csv' が指定された場合のソース行（フィールドは全て文字列のまま保持する）。
+    // Someの間はiter_scanが通常のgviz経由の行の代わりにこちらを返す。
+    csv_rows: Option<Vec<Vec<String>>>,
+    // spread_sheet_ids（複数ファイル）指定時、src_rowsの各行がどのspread_sheet_id由来かを
+    // 同じ添字で保持する。単一spread_sheet_idの場合は空のまま（_spread_sheet_id列は使えない）。
+    row_spread_sheet_ids: Vec<String>,
+    // _synthetic_key 疑似カラムのために、begin_scan解決時点のspread_sheet_idとsheet_id（gid）を
+    // 保持する。単一spread_sheet_idの場合に参照する（複数ファイル時は行ごとにrow_spread_sheet_idsの
+    // 方を優先する）。sheet_id未指定時は空文字のままにする。
+    synthetic_key_spread_sheet_id: String,
+    synthetic_key_sheet_id: String,
+    // gvizの各列（ordinal順）のpattern（例: "$#,##0.00"）。無い列はNone。
+    // F64列を"f"（表示用文字列）から復元する際の通貨記号・桁区切りの正規化に使う。
+    column_patterns: Vec<Option<String>>,
+    // rows_pointer テーブル/サーバーオプション（既定"/table/rows"）。select_gviz_table正規化後の
+    // レスポンスから行配列を取り出す際に使うJSON Pointer。gvizのレスポンスを別のエンベロープへ
+    // 包み直すプロキシ越しに使う場合の逃げ道。
+    rows_pointer: String,
+    // cell_value_pointer テーブル/サーバーオプション（既定"/c/{i}/v"）。"{i}"をソース列インデックスへ
+    // 置換して、各セルの生の値を取り出すJSON Pointerを組み立てるテンプレート。
+    cell_value_pointer: String,
+    // cell_value_pointerの末尾"/v"を"/f"に置き換えて導出した、表示用文字列側のポインタテンプレート
+    // （begin_scanで一度だけ計算しておく。gviz_cell_error/number_as_text_columns等の"f"参照はこちらを使う）。
+    cell_formatted_pointer: String,
+    // localeテーブルオプション。列にpatternが無い場合の桁区切り規則のフォールバックに使う。
+    locale: String,
+    // preserve_source_order テーブルオプション（既定true）。trueの間は、ページネーションや
+    // spread_sheet_idsによる複数ソース結合を導入しても、出力順が常にソースの読み取り順と
+    // 一致することを保証する契約になる。falseなら将来のパフォーマンス最適化のために
+    // 並べ替える余地を残す。現状は単一パスでの逐次取得のみなので、どちらでも出力順は同じ。
+    preserve_source_order: bool,
+    // api_mode: "gviz"（既定）または "v4"。v4はGoogle Sheets API v4を使い、noteやhyperlinkなど
+    // gvizのvalueレスポンスには出てこないメタデータへアクセスできる。
+    api_mode: String,
+    // サーバーオプション service_account（サービスアカウント鍵のJSON文字列）。v4モードの認証に使う。
+    service_account_json: Option<String>,
+    // api_mode = 'v4' のときのソース行。
+    v4_rows: Vec<Vec<V4Cell>>,
+    // note_of_columns テーブルオプションで指定された、値の代わりにメモ(note)を返す列名の一覧（v4モード限定）。
+    note_of_columns: Vec<String>,
+    // hyperlink_of テーブルオプションで指定された、(この列名, 参照先列名) のペアの一覧（v4モード限定）。
+    // この列は自身の位置の値ではなく、参照先列と同じソース列の hyperlink を返す。
+    hyperlink_of: Vec<(String, String)>,
+    // color_of テーブルオプションで指定された、(この列名, 参照先列名) のペアの一覧（v4モード限定）。
+    // この列は自身の位置の値ではなく、参照先列と同じソース列の背景色を"#rrggbb"形式で返す。
+    // 明示的な書式設定が無いセルはnullになる。
+    color_of: Vec<(String, String)>,
+    // validation_of テーブルオプションで指定された、(この列名, 参照先列名) のペアの一覧（v4モード限定）。
+    // この列は自身の位置の値ではなく、参照先列と同じソース列のdataValidationルール（プルダウンの
+    // 選択肢、数値範囲等）をjsonbとして返す。ルールが設定されていないセルはnullになる。
+    validation_of: Vec<(String, String)>,
+    // format_of テーブルオプションで指定された、(この列名, 参照先列名) のペアの一覧。
+    // gviz/v4どちらのモードでも使え、この列は自身の位置の値ではなく、参照先列と同じソース列の
+    // 数値表示形式パターン（通貨/パーセント等、gvizならcolsのpattern、v4ならuserEnteredFormat.
+    // numberFormat.pattern）を返す。列単位のメタデータなので全行で同じ値になる。明示的な書式
+    // 設定が無ければnullになる。TypeOid::Stringの列で指定する必要がある。
+    format_of: Vec<(String, String)>,
+    // value_render テーブルオプション（既定"formatted"、v4モード限定）。"formula"なら数式テキスト
+    // （例"=SUM(A1:A9)"）を、"unformatted"なら表示書式を適用する前の生の値を返す。定数セルを
+    // "formula"で読んだ場合など、該当データがセルに無ければformatted_valueへフォールバックする。
+    value_render: String,
+    // null_strings テーブルオプションで指定された、SQLのNULLとして扱う文字列セル値の一覧
+    // （例: "N/A", "-"）。大文字小文字を区別せず、前後の空白を除いた上で比較する。
+    // 型変換より前に判定するため、数値/日付列に対しても機能する。
+    null_strings: Vec<String>,
+    // page_size テーブルオプション（api_mode = 'v4' 限定）。0（既定）ならv4_rowsに全行を
+    // バッファする従来通りの挙動。1以上なら、begin_scanは最初のページのみ取得し、
+    // iter_scanはsrc_idxがページ境界を跨ぐ度にrangesで絞り込んだ次のページをオンデマンドで
+    // 取得する。メモリ使用量を1ページ分に抑える代わりにHTTPリクエスト回数が増えるトレードオフ。
+    v4_page_size: usize,
+    // ページングモードで次のページを取得するために保持する状態。
+    v4_access_token: String,
+    v4_page_spread_sheet_id: String,
+    v4_sheet_title: String,
+    // 現在メモリに保持しているページの先頭が、返却する行の並び（= src_idxと同じ0始まりの
+    // 論理フレーム）で何行目に当たるか。skip_frozen_rowsの有無に関わらず常に0から始まる。
+    v4_page_start: usize,
+    // 論理フレーム（v4_page_start）とシート上の物理行番号とのオフセット。skip_frozen_rows
+    // 指定時はfrozenRowCount、それ以外は0。fetch_v4_pageへ渡す範囲の計算にのみ使い、
+    // src_idx/v4_page_startの算術からは独立させることでusizeアンダーフローを避ける。
+    v4_physical_row_offset: usize,
+    // 現在メモリに保持しているページの行（最大page_size行）。
+    v4_page_rows: Vec<Vec<V4Cell>>,
+    // auth_max_retries サーバーオプション（既定3）。トークンエンドポイントへのリクエストが
+    // 一時的に失敗した場合、指数バックオフで最大この回数までリトライする。データ取得側の
+    // リトライ（gvizのtqクエリ拒否時のフォールバック）とは独立した設定。
+    auth_max_retries: u32,
+    // 直近に取得したアクセストークンのキャッシュ（epoch秒での有効期限つき）。flakyな認証
+    // エンドポイントがスキャンのたびにブロックしないよう、有効期限内は再利用する。
+    cached_access_token: Option<String>,
+    cached_access_token_expiry: i64,
+    // limit_rows テーブルオプション（既定0=無制限）。SQLのLIMITとは別の、テーブル定義側の
+    // ハード上限。iter_scanはこの件数を出力し終えたらOk(None)を返してスキャンを打ち切る。
+    limit_rows: usize,
+    // 今回のスキャンで既に出力した行数。begin_scanのたびに0へリセットする。
+    rows_emitted: usize,
+    // pushdown_limit = 'true' のとき、begin_scanがgvizのtq句へ実際にプッシュダウンしたLIMIT件数。
+    // end_scanで実際に出力した行数（rows_emitted）と突き合わせ、ちょうど境界で止まっていれば
+    // 「Postgres側が結合等でこのLIMITより多い行を必要としていたかもしれない」警告を出す材料にする。
+    pushed_limit: Option<i64>,
+    // verbose テーブルオプション。begin_scan内のプッシュダウン診断だけでなく、end_scanでの
+    // pushed_limit警告でも使うため構造体フィールドに昇格している。
+    verbose: bool,
+    // scale / offset テーブルオプションで指定された、(列名, 値) のペアの一覧。数値列に対して
+    // value * scale + offset を適用し、単位変換（例: セント⇄ドル）をFDW側で吸収する。
+    // 指定の無い列はscale=1.0, offset=0.0（no-op）として扱う。
+    scale_columns: Vec<(String, f64)>,
+    offset_columns: Vec<(String, f64)>,
+    // round_to テーブルオプションで指定された、(列名, 桁数) のペアの一覧。numeric型の列に対して
+    // half-to-even丸めを適用し、numeric(p,s)のsを超える桁でのスケールオーバーフローを防ぐ。
+    // 指定の無い列はno-op（丸めなし）として扱う。
+    round_to_columns: Vec<(String, u32)>,
+    // range_columns テーブルオプションで指定された、(range_columns内の範囲文字列, 列名一覧) の
+    // ペアの一覧。ranges（api_mode = 'v4' 限定）で読む各範囲を、対象スキーマのどの列へ位置対応
+    // させるかを決める。rangesに含まれていてもここに対応が無い範囲は位置対応（範囲の1列目→
+    // 対象スキーマの1列目…）にフォールバックする。begin_scan内でrangesの連結処理に使うだけで
+    // iter_scan側では参照しないため、値そのものはbegin_scanが呼ばれるたびに作り直される。
+    range_columns: Vec<(String, Vec<String>)>,
+    // source_letters テーブルオプションで指定された、(この列名, (開始, 終了)) の一覧。
+    // 隣接する複数のソース列（範囲は0始まりインデックス、両端含む）をまとめてこの列に格納する。
+    // WITのcell variantにはネイティブな配列型が無いため、jsonb配列として表現する
+    // （この列はTypeOid::Jsonで定義する必要がある）。範囲内で欠けているセルはJSON nullになる。
+    source_letters: Vec<(String, (usize, usize))>,
+    // timezone テーブルオプション（既定 "+00:00"）。datetime_as = 'epoch_ms' でgvizのDate(...)
+    // リテラルをエポックミリ秒に変換する際に使う固定UTCオフセット。ホストAPIにタイムゾーン
+    // データベースが無いため、IANAタイムゾーン名ではなく "+09:00" のような固定オフセット表記のみ対応する。
+    timezone: String,
+    // column_timezones テーブルオプション（"col=+09:00,col2=+00:00"形式）で指定された、列ごとの
+    // timezone上書き。Column resourceには列単位のオプションを取得する手段が無いため、他の
+    // 列単位設定（scale/offset/strip_prefix等）と同じくテーブルオプション側で列名付きリストとして
+    // 指定してもらう方式を取る。日付/日時列のパース時、この一覧→timezoneテーブルオプション→
+    // という優先順で解決する。
+    column_timezones: Vec<(String, String)>,
+    // datetime_as テーブルオプションで指定された、(この列名, モード) のペアの一覧。
+    // 現状サポートするモードは "epoch_ms" のみで、gvizのDate(...)値をtimezoneのもとで
+    // Unixエポックミリ秒に変換し、I64セルとして返す（bigint列向け）。
+    datetime_as: Vec<(String, String)>,
+    // duration_as テーブルオプションで指定された、(この列名, モード) のペアの一覧。
+    // 現状サポートするモードは "total_seconds" のみ。WITのtype-oidにはinterval型が無いため、
+    // `[h]:mm:ss`（24時間を超えられる経過時間書式）で入力されたセルを総秒数のI64として返す
+    // （bigint列向け。呼び出し側でPostgresのinterval型へキャストする想定）。
+    duration_as: Vec<(String, String)>,
+    // strict_column_bounds テーブルオプション（既定false）。trueの場合、列のマッピング先インデックスが
+    // シートの総列数を超えていたらエラーで中断する。falseの場合はreport_infoで一度だけ警告する。
+    strict_column_bounds: bool,
+    // 「列のマッピング先インデックスがシートの総列数を超えている」警告を既に出した列名の一覧。
+    // 行ごとに警告が繰り返されないよう、begin_scanのたびにリセットして列単位で一度だけ出す。
+    oob_reported_columns: Vec<String>,
+    // lenient_text テーブルオプション（既定false）。trueの場合、text列に対応するgviz値の型が
+    // 文字列でなくても（数値・真偽値）nullにせず文字列化して返す。探索的クエリでのnull地獄を避けるための
+    // 「とりあえず文字列で全部くれ」というエスケープハッチ。number_as_text_columnsと異なり列を
+    // 個別指定する必要が無い代わりに、text列の型の厳密さがテーブル全体で失われる。
+    lenient_text: bool,
+    // nonfinite テーブルオプション（既定"null"）。数値列に対応するgviz値がNaN/Infinity等の非有限値に
+    // なった場合の扱い。"null"ならその列をNULLにして行自体はスキャンを継続し、"error"ならその列で
+    // iter_scanをエラー終了させる。壊れた数式が混ざったシートでスキャン全体が失敗しないよう既定はnull。
+    nonfinite: String,
+    // on_cell_error テーブルオプション（既定"null"）。#REF!/#DIV/0!等のgviz数式エラーセルの扱い。
+    // "null"ならNULLにして継続、"string"ならエラー文字列をそのままCell::Stringとして返す
+    // （text列で使う想定）、"error"なら列名と行番号を添えてiter_scanをエラー終了させる。
+    on_cell_error: String,
+    // bool_true_values/bool_false_values テーブルオプション（カンマ区切り、大文字小文字を
+    // 区別しない、既定でtrue/false・yes/no・on/offを認識）。TypeOid::Boolの列の文字列セルを
+    // 真偽値へ解決する際に使う受理トークン集合。小文字化して保持し、比較もparse_bool_token側で
+    // 小文字化してから行う。どちらの集合にも一致しない値はon_cell_errorポリシーに従う。
+    bool_true_values: Vec<String>,
+    bool_false_values: Vec<String>,
+    // collect_errors テーブルオプション（既定false）。trueの場合、本来ならiter_scanを即座に
+    // エラー終了させていたセル単位の型変換失敗（桁区切り整数のパース失敗等）をon_row_error
+    // （'null'/'skip'）ポリシーに委ねてNULL扱いにしつつ、この一覧に記録しておく。on_row_error =
+    // 'error'（既定）の場合はcollect_errors = 'true'でも従来通り即座にエラーで中断する
+    // （「エラーを握りつぶして集計する」モードと「即座に失敗する」モードが同時に有効だと
+    // どちらを優先したのか分かりにくくなるため、明示的にnull/skipを選んだ場合のみ収集する）。
+    collect_errors: bool,
+    // collect_errors = 'true' のとき蓄積される、1件ごとの変換エラーの説明文。
+    // MAX_COLLECTED_CELL_ERRORS件を超えた分はcell_errors_droppedでカウントするだけにし、
+    // 壊れたデータが大量にあるシートでメモリが際限なく膨らむのを防ぐ。
+    collected_cell_errors: Vec<String>,
+    // 件数上限に達した後、記録されずに数だけ数えられたセルエラーの件数。
+    cell_errors_dropped: u64,
+    // date_format テーブルオプション（既定""、未設定）。TypeOid::Date/Timestamp/Timestamptz列の値が
+    // gvizのDate(...)リテラルではなく素のテキスト（人間が手入力した"15/01/2023"等）だった場合に、
+    // それを解釈するためのstrftime風パターン（%Y, %y, %m, %d, %H, %M, %S, %b, %B に対応）。
+    // パースできなかった値の扱いはon_cell_errorポリシーに従う。
+    date_format: String,
+    // constant テーブルオプション（"col=value,col2=value2"形式）で指定された、(この列名, 生の値文字列) の
+    // 一覧。csv/gviz/v4の各スキャンモードで、対象列はシートの値を一切見ずに毎回この値を返す
+    // （defaultと違い、シート側に同名列があっても常にこちらが優先される）。複数テーブルを
+    // UNION ALLする際のタグ列（例 region = 'us'）に使う想定。
+    constant_columns: Vec<(String, String)>,
+    // default テーブルオプション（"col=value,col2=value2"形式）で指定された、(この列名, 生の値文字列) の
+    // 一覧。constantと異なりソース側のセルが欠損/nullの場合にのみ効く（値がある場合はそのまま使う）。
+    // 下のdefault_number/default_text/default_boolより優先される（列単位の指定なので、テーブル全体の
+    // 型別デフォルトより具体的な設定を優先するのが自然）。
+    default_columns: Vec<(String, String)>,
+    // default_number/default_text/default_bool テーブルオプション（既定None=未設定）。default_columnsに
+    // 一致する列指定が無く、かつソース側のセルが欠損/nullの場合に、対象列のtype_oidに応じてこの中の
+    // いずれかを適用する（I64/F64/Numericはdefault_number、Stringはdefault_text、Boolはdefault_bool）。
+    // NOT NULL制約のある外部テーブルへスパースなシートを取り込む際の簡易な穴埋め用途。
+    default_number: Option<String>,
+    default_text: Option<String>,
+    default_bool: Option<String>,
+    // on_short_row テーブルオプション（'null'（既定）| 'skip' | 'error'）。ソース行の"c"配列が
+    // マップ対象列の要求する幅より短い（raggedな）場合の扱いを決める。'null'は従来通り欠けている
+    // 列をnullで埋め、'skip'はその行を丸ごと読み飛ばし（on_row_error = 'skip'と同じ
+    // rows_skipped_for_errors/max_row_errorsの仕組みに相乗りする）、'error'は行インデックスと
+    // 期待/実際の幅を添えて即座にエラーにする。個々のセルの型変換エラー（on_cell_error）とは
+    // 別レイヤーの、行の構造的な短さに対する設定。
+    on_short_row: String,
+    // strip_prefix / strip_suffix テーブルオプション（"col=affix,col2=affix2"形式）で指定された、
+    // (この列名, 取り除く接頭辞/接尾辞) の一覧。対象の接頭辞/接尾辞が実際にその位置にある場合のみ
+    // 取り除く（決め打ちのtrimとは違い、一致しない値は無加工で通す）。text列ではそのまま、numeric列
+    // （F64のpattern付き列）では通貨記号の除去や数値パースより前に適用する。
+    strip_prefix_columns: Vec<(String, String)>,
+    strip_suffix_columns: Vec<(String, String)>,
+    // strip_leading_apostrophe テーブルオプション（既定false）。trueなら、Googleが「テキストとして
+    // 強制」した値の先頭に付く単一のアポストロフィ（例: '007）を、String列への型変換時に取り除く。
+    // strip_prefix_columnsとは独立したオプションで、全String列に一律で効く（strip_prefixのように
+    // 列単位の指定は不要な単純さのため）。両方指定した場合は、こちらを先に適用してからstrip_prefix
+    // /strip_suffixを適用する。
+    strip_leading_apostrophe: bool,
+    // use_formatted テーブルオプションで指定された列名の一覧。TypeOid::I64列のうち、ここに
+    // 含まれる列はv(f64)ではなく表示用文字列"f"（桁区切り付きの"1,234,567"等）を読み、
+    // パターン/localeの桁区切り規則を取り除いてから整数としてパースする。
+    use_formatted_columns: Vec<String>,
+    // column_order テーブルオプション（列アルファベットのカンマ区切りリスト）で指定された、
+    // 宣言済みPostgres列の並び（1始まりの序数）ごとの読み取り先ソース列インデックス（0始まり）。
+    // 空なら従来通りtgt_col_num - 1（宣言順そのまま）を使う。CSV/exportのような位置ベースの
+    // マッピングが支配的な形式で、シート側の列順を変えずにPostgres側の列順を選び直す用途。
+    // api_mode = 'v4'（note_of_columns/hyperlink_of/color_ofが別途ソース列参照を行う）とは
+    // begin_scanで併用を拒否する。
+    column_order: Vec<usize>,
+    // partial_ok テーブルオプション（既定false）。trueの場合、v4モードのpage_sizeページングが
+    // 次ページの取得中にscan_deadline_msの超過やHTTPエラーで打ち切られても、エラー終了する代わりに
+    // それまでに取得済みの行だけを返してスキャンを終える。
+    partial_ok: bool,
+    // include_header_row テーブルオプション（既定false）。trueの場合、iter_scanの最初の1回だけ
+    // 実データの代わりにsource labelから成るヘッダー行を返す。CSVのようなヘッダー行を前提とする
+    // 消費者へパイプする用途向け。
+    include_header_row: bool,
+    // include_header_row = 'true' のとき、既にヘッダー行を返し終えたかどうか。begin_scan/end_scanで
+    // falseにリセットする。
+    header_row_emitted: bool,
+    // gvizの各列（ordinal順）のlabel。include_header_row用にcolumn_patternsと並行して保持する。
+    column_labels: Vec<String>,
+    // debug = 'raw_body' モードの結果。パースを一切通さない生レスポンスの1行だけを返す。
+    debug_raw_body: Option<RawBodyDebug>,
 }
 
+// v4 API使用時の1セルぶんの情報。formattedValueが表示用の値、noteはセルのメモ、
+// hyperlinkは=HYPERLINK()関数が張るリンク先URL。
+#[derive(Debug, Clone, Default)]
+struct V4Cell {
+    formatted_value: Option<String>,
+    note: Option<String>,
+    hyperlink: Option<String>,
+    // セルの背景色。明示的な書式設定が無ければNone（APIはbackgroundColorそのものを省略する）。
+    // "#rrggbb"形式の16進数文字列として保持する。
+    background_color: Option<String>,
+    // value_render = 'formula' 用。セルが数式でなければNone（APIはformulaValueそのものを省略する）。
+    formula: Option<String>,
+    // value_render = 'unformatted' 用。表示用の書式（桁区切りや通貨記号等）を適用する前の生の値を
+    // 文字列化したもの。数値/文字列/真偽値のいずれでもない、または値が無いセルはNone。
+    unformatted_value: Option<String>,
+    // validation_of 用。セルのdataValidationルール（プルダウンの選択肢、数値範囲等）をv4の
+    // レスポンスそのままの構造で保持する。ルールが設定されていないセルはNone。
+    data_validation: Option<JsonValue>,
+    // format_of 用。セルのuserEnteredFormat.numberFormat.pattern（通貨/パーセント等の表示形式）。
+    // 明示的な書式設定が無ければNone。
+    number_format_pattern: Option<String>,
+}
+
+// introspectモードで1カラムぶんの情報を表す。
+#[derive(Debug, Clone, Default)]
+struct ColumnInfo {
+    ordinal: i64,
+    label: String,
+    gviz_type: String,
+    pg_type: String,
+    // ヘッダーセルのメモ(note)。api_mode = 'v4'でのみ取得できる（gvizのcolsメタデータには
+    // メモが含まれない）。
+    header_note: Option<String>,
+    // IMPORT FOREIGN SCHEMAでのCOMMENT ON COLUMNの元ネタとして使う想定の文字列。元のシートの
+    // labelを常に含み、header_noteがあれば併記する。列名がPostgres側で正規化されて元のラベルが
+    // 読み取れなくなっても、`\d+`の出力から出どころを追えるようにするためのもの。
+    column_comment: String,
+}
+
+// labelと（v4モードのみ）ヘッダーセルのメモから、column_commentの値を組み立てる。
+fn build_column_comment(label: &str, header_note: Option<&str>) -> String {
+    match header_note {
+        Some(note) if !note.is_empty() => format!("{} ({})", label, note),
+        _ => label.to_owned(),
+    }
+}
+
+// introspect = 'meta' モードで1カラムぶんの情報を表す。gvizレスポンスの生メタデータ
+// （id/label/type/pattern）に加え、シート単位のstatus/warningsをそのまま持たせる。
+#[derive(Debug, Clone, Default)]
+struct ColumnMetaInfo {
+    ordinal: i64,
+    id: String,
+    label: String,
+    gviz_type: String,
+    pattern: JsonValue,
+    status: JsonValue,
+    warnings: JsonValue,
+}
+
+// introspect = 'spreadsheet' モードで返す、スプレッドシート全体のメタデータ。v4 APIの
+// properties(title,locale,timeZone)とsheets[].properties.titleの一覧から組み立てる
+// （gvizのtqレスポンスにはスプレッドシート単位のこれらの情報が含まれないため）。
+#[derive(Debug, Clone, Default)]
+struct SpreadsheetMetaInfo {
+    title: String,
+    locale: String,
+    time_zone: String,
+    sheet_names: Vec<String>,
+}
+
+// introspect = 'developer_metadata' モードで返す、developerMetadataリソース1件分の情報。
+// locationはスプレッドシート単位/シート単位/次元単位のいずれかで形が変わるため、加工せず
+// そのままjsonbとして返す（ColumnMetaInfoのpattern/status/warningsと同じ扱い）。
+#[derive(Debug, Clone, Default)]
+struct DeveloperMetadataInfo {
+    id: i64,
+    key: String,
+    value: Option<String>,
+    location: JsonValue,
+    visibility: String,
+}
+
+// introspect = 'protected_ranges' モードで返す、protectedRangeリソース1件分の情報。
+// editorsはusers/groups/domainUsersCanEditの形が状況により変わるため、加工せずそのまま
+// jsonbとして返す（DeveloperMetadataInfo.locationと同じ扱い）。
+#[derive(Debug, Clone, Default)]
+struct ProtectedRangeInfo {
+    id: i64,
+    sheet_title: String,
+    // gridRangeをA1形式に変換したもの（protected_range_to_a1）。シート全体が保護されている
+    // 場合はrangeが省略されるため、その場合はシートタイトルのみを返す。
+    range: String,
+    description: String,
+    editors: JsonValue,
+}
+
+// unpivot = 'true' モード（ワイド形式→ロング形式変換）の出力行1件分。unpivot_value_columnsに
+// 指定した列1つにつき元の行から1つ生成されるため、元の行数 × melt対象列数の行数になる。
+#[derive(Debug, Clone, Default)]
+struct UnpivotRow {
+    // unpivot_id_columnsで指定した各ソース列ラベルと、この行におけるその値。
+    id_values: Vec<(String, Option<String>)>,
+    // メルトされた元のソース列ラベル（"variable"列の値）。
+    variable: String,
+    // メルトされた列のこの行における値（"value"列の値）。セルが空ならNone
+    // （空文字と未回答を区別するため、keyvalueモードと異なりNULLとして返す）。
+    value: Option<String>,
+}
+
+// debug = 'raw_body' モードの結果1件分。パースを一切通さず、Googleから返ってきた生のレスポンスを
+// そのままstatus_code/bodyとして返す、パーサーがレスポンスを拒否した時の最終手段の診断用出力。
+#[derive(Debug, Clone, Default)]
+struct RawBodyDebug {
+    status_code: u16,
+    // debug_max_bytesを超える場合はUTF-8境界を壊さない範囲で切り詰めた本文。
+    body: String,
+    truncated: bool,
+}
 
 // INSTANCEは、↑の構造体のシングルトンインスタンスを指すポインタです。unsafeブロックでアクセスされるため、スレッドセーフではありません。
 static mut INSTANCE: *mut SpreadsheetsFdw = std::ptr::null_mut::<SpreadsheetsFdw>();
@@ -64,162 +500,7161 @@ impl SpreadsheetsFdw {
     fn this_mut() -> &'static mut Self {
         unsafe { &mut (*INSTANCE) }
     }
-}
-
-// SpreadsheetsFdw構造体に対してGuestトレイトを実装しています。
-// GuestトレイトはFDWの各種操作に対応するためのインターフェースを提供しており、
-// これにより外部データソースをPostgreSQLに統合するための機能を定義します。以下に、各メソッドの説明を示します。
-impl Guest for SpreadsheetsFdw {
-    fn host_version_requirement() -> String {
-        // semver expression for Wasm FDW host version requirement
-        // ref: https://docs.rs/semver/latest/semver/enum.Op.html
-        "^0.1.0".to_string()
-    }
 
-    // 初期化
-    fn init(ctx: &Context) -> FdwResult {
-        Self::init_instance();
+    // limit_rows によるハード上限のチェックを除いた、実際のモード別スキャン処理。
+    fn iter_scan_inner(ctx: &Context, row: &Row) -> Result<Option<u32>, FdwError> {
         let this = Self::this_mut();
-        // 外部サーバーオプションからAPI URLを取得する（指定されている場合）
-        let opts = ctx.get_options(OptionsType::Server);
 
-        // let service_account = opts.require("service_account")?;
-    
-        this.base_url = opts.require_or("base_url", "https://docs.google.com/spreadsheets/d");
-        Ok(())
-    }
+        // debug = 'raw_body' モード: パース済みデータは一切無く、status_code/bodyの固定スキーマで
+        // 生レスポンス1行だけを返す。
+        if let Some(debug) = this.debug_raw_body.clone() {
+            if this.src_idx >= 1 {
+                return Ok(None);
+            }
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "status_code" => Some(Cell::I64(debug.status_code as i64)),
+                    "body" => Some(Cell::String(debug.body.clone())),
+                    "truncated" => Some(Cell::Bool(debug.truncated)),
+                    other => return Err(format!("unknown debug column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-    // データスキャンの開始時に行う準備作業を担当します。具体的には、ソースデータの取得や初期化処理などを行います。
-    fn begin_scan(ctx: &Context) -> FdwResult {
-        let this = Self::this_mut();
-         // ↓ SQLのスキーマで渡されたoptionの値を読み込む。
-         let opts = ctx.get_options(OptionsType::Table);
+        // csvモード: 列位置ベースでフィールドを対応する型のCellに変換する。
+        if let Some(csv_rows) = this.csv_rows.clone() {
+            if this.src_idx >= csv_rows.len() {
+                return Ok(None);
+            }
+            let src_row = &csv_rows[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let (tgt_col_num, tgt_col_name) = (tgt_col.num(), tgt_col.name());
+                let src_col_idx = resolve_source_col_index(tgt_col_num, &this.column_order);
+                let cell = if let Some(c) = constant_cell_for_column(&tgt_col, &this.constant_columns)? {
+                    Some(c)
+                } else {
+                    let field = src_row.get(src_col_idx).map(String::as_str);
+                    let field = field.filter(|v| !is_null_sentinel(v, &this.null_strings));
+                    match tgt_col.type_oid() {
+                        TypeOid::String => field.map(|v| Cell::String(v.to_owned())),
+                        TypeOid::I64 => field.and_then(|v| v.parse::<i64>().ok()).map(Cell::I64),
+                        _ => {
+                            return Err(format!(
+                                "column {} data type is not supported in csv mode",
+                                tgt_col_name
+                            ));
+                        }
+                    }
+                };
+                let cell = apply_default_cell(
+                    cell,
+                    &tgt_col,
+                    &this.default_columns,
+                    this.default_number.as_deref(),
+                    this.default_text.as_deref(),
+                    this.default_bool.as_deref(),
+                )?;
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-         let spread_sheet_id = opts.require("spread_sheet_id")?;
-         let sheet_id = opts.get("sheet_id");
+        // api_mode = 'v4' の場合、gvizとは別に保持しているV4Cellの行から値を取り出す。
+        // note_of_columnsに含まれる列は値の代わりにセルのメモ(note)を返す。
+        if this.api_mode == "v4" {
+            if this.v4_page_size > 0 {
+                // ページングモード: src_idxが現在のページを超えたら、次のページを
+                // オンデマンドで取得する。取得したページがpage_size未満なら最終ページ。
+                // 現在のページ内の行はこの下でそのまま即座にpush_v4_rowされるため、後続ページの
+                // フェッチは「今のページを使い切った時」にしか起きない = 先頭ページの行を待たせない。
+                // 次ページの取得がここでエラーになれば、そのままErr(..)としてこの呼び出し（＝スキャン
+                // 途中のiter_scan呼び出し）から返る。呼び出し元はそれまでに返した行を破棄しないため、
+                // 「1ページ目は正常に返ったのに、2ページ目の途中でスキャン全体がエラー終了する」
+                // という挙動になる（partial_ok = 'true'ならエラーにせずそこで打ち切る）。
+                let local_idx = this.src_idx - this.v4_page_start;
+                if local_idx >= this.v4_page_rows.len() {
+                    if this.v4_page_rows.len() < this.v4_page_size {
+                        return Ok(None);
+                    }
+                    this.v4_page_start += this.v4_page_size;
+                    this.v4_physical_row_offset += this.v4_page_size;
+                    if let Err(e) = check_scan_deadline() {
+                        if this.partial_ok {
+                            report_info(&format!(
+                                "partial_ok: stopping pagination early after {} row(s) because {}",
+                                this.src_idx, e
+                            ));
+                            return Ok(None);
+                        }
+                        return Err(e);
+                    }
+                    this.v4_page_rows = match fetch_v4_page(
+                        &this.v4_page_spread_sheet_id,
+                        &this.v4_access_token,
+                        &this.v4_sheet_title,
+                        this.v4_physical_row_offset,
+                        this.v4_page_size,
+                        &this.query_params,
+                    ) {
+                        Ok(rows) => rows,
+                        Err(e) if this.partial_ok => {
+                            report_info(&format!(
+                                "partial_ok: stopping pagination early after {} row(s) because the next page failed to fetch: {}",
+                                this.src_idx, e
+                            ));
+                            return Ok(None);
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    if this.v4_page_rows.is_empty() {
+                        return Ok(None);
+                    }
+                }
+                let local_idx = this.src_idx - this.v4_page_start;
+                let src_row = this.v4_page_rows[local_idx].clone();
+                push_v4_row(ctx, row, this, &src_row)?;
+                this.src_idx += 1;
+                return Ok(Some(0));
+            }
+            if this.src_idx >= this.v4_rows.len() {
+                return Ok(None);
+            }
+            let src_row = this.v4_rows[this.src_idx].clone();
+            push_v4_row(ctx, row, this, &src_row)?;
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-         // URLを組み立てる。
-         let url = format!("{}/{}/gviz/tq?tqx=out:json", this.base_url, spread_sheet_id,);
+        // introspect = 'meta' モード: 固定スキーマ（ordinal/column_id/column_label/gviz_type/
+        // pattern/status/warnings）で1列ずつ返す。pattern/status/warningsは複合値になり得るためjsonbで返す。
+        if let Some(cols) = this.introspect_meta.clone() {
+            if this.src_idx >= cols.len() {
+                return Ok(None);
+            }
+            let col_info = &cols[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "ordinal" => Some(Cell::I64(col_info.ordinal)),
+                    "column_id" => Some(Cell::String(col_info.id.clone())),
+                    "column_label" => Some(Cell::String(col_info.label.clone())),
+                    "gviz_type" => Some(Cell::String(col_info.gviz_type.clone())),
+                    "pattern" => Some(Cell::Json(col_info.pattern.to_string())),
+                    "status" => Some(Cell::Json(col_info.status.to_string())),
+                    "warnings" => Some(Cell::Json(col_info.warnings.to_string())),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-        // sheet_idが定義されている場合のURLを組み立てる。
-         let url = match sheet_id {
-            Some(sheet_id) => format!(
-                "{}/{}/gviz/tq?gid={}&tqx=out:json",
-                this.base_url, spread_sheet_id, sheet_id,
-            ),
-            None => format!("{}/{}/gviz/tq?tqx=out:json", this.base_url, spread_sheet_id,),
-        };
+        // introspect = 'spreadsheet' モード: スプレッドシート単位のtitle/locale/time_zone/sheet_names
+        // （jsonb配列）を1行だけ返す。
+        if let Some(meta) = this.introspect_spreadsheet.clone() {
+            if this.src_idx >= 1 {
+                return Ok(None);
+            }
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "title" => Some(Cell::String(meta.title.clone())),
+                    "locale" => Some(Cell::String(meta.locale.clone())),
+                    "time_zone" => Some(Cell::String(meta.time_zone.clone())),
+                    "sheet_names" => Some(Cell::Json(JsonValue::from(meta.sheet_names.clone()).to_string())),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-        // API通信のためのヘッダーを定義
-        let headers: Vec<(String, String)> = vec![
-            ("user-agent".to_owned(), "Sheets FDW".to_owned()),
-            // header to make JSON response more cleaner
-            ("x-datasource-auth".to_owned(), "true".to_owned()),
-        ];
+        // introspect = 'developer_metadata' モード: シート/範囲に付与されたdeveloperMetadataを
+        // 固定スキーマ（id/key/value/location/visibility）で1件ずつ返す。アプリがバージョンや
+        // 設定をシートに書き込んで、FDW経由でSQLから読める状態にするための連携用。
+        if let Some(entries) = this.introspect_developer_metadata.clone() {
+            if this.src_idx >= entries.len() {
+                return Ok(None);
+            }
+            let entry = &entries[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "id" => Some(Cell::I64(entry.id)),
+                    "key" => Some(Cell::String(entry.key.clone())),
+                    "value" => entry.value.clone().map(Cell::String),
+                    "location" => Some(Cell::Json(entry.location.to_string())),
+                    "visibility" => Some(Cell::String(entry.visibility.clone())),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-        // Google API にリクエストを送り、レスポンスを JSON として解析する
-        let req = http::Request {
-            method: http::Method::Get,
-            url,
-            headers,
-            body: String::default(),
-        };
-        let resp = http::get(&req)?;
-        // 無効なプレフィックスをレスポンスから削除して、有効なJSON文字列にする。
-        let body = resp.body.strip_prefix(")]}'\n").ok_or("invalid response")?;
-        let resp_json: JsonValue = serde_json::from_str(body).map_err(|e| e.to_string())?;
-        // レスポンスからソースの行を抽出する
-        this.src_rows = resp_json
-            .pointer("/table/rows")
-            .ok_or("cannot get rows from response")
-            .map(|v| v.as_array().unwrap().to_owned())?;
-        // Postgres INFO をユーザーに出力する（psql で表示可能）、デバッグにも便利
-        utils::report_info(&format!(
-            "We got response array length: {}",
-            this.src_rows.len()
-        ));
-        Ok(())
-    }
+        // introspect = 'protected_ranges' モード: シートオーナーが設定した保護範囲を、A1形式の
+        // range文字列とともに固定スキーマ（id/sheet/range/description/editors）で1件ずつ返す。
+        // write対応の連携で、誤って保護セルへのupdateを試みる前にどこが保護されているかを
+        // SQLから確認できるようにするためのもの。
+        if let Some(entries) = this.introspect_protected_ranges.clone() {
+            if this.src_idx >= entries.len() {
+                return Ok(None);
+            }
+            let entry = &entries[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "id" => Some(Cell::I64(entry.id)),
+                    "sheet" => Some(Cell::String(entry.sheet_title.clone())),
+                    "range" => Some(Cell::String(entry.range.clone())),
+                    "description" => {
+                        if entry.description.is_empty() {
+                            None
+                        } else {
+                            Some(Cell::String(entry.description.clone()))
+                        }
+                    }
+                    "editors" => Some(Cell::Json(entry.editors.to_string())),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
 
-    // この関数 iter_scan は、PostgreSQLのFDW（Foreign Data Wrapper）におけるデータスキャンの処理を行う部分です。
-    // ここでは、外部データソースからデータを取得し、PostgreSQLに対して返すための変換を行います。
-    fn iter_scan(ctx: &Context, row: &Row) -> Result<Option<u32>, FdwError> {
-        let this = Self::this_mut();
-        // if all source rows are consumed, stop data scan
-        if this.src_idx >= this.src_rows.len() {
-            return Ok(None);
+        // introspect = 'version' モード: クレートのバージョンや対応しているソースモード/型
+        // などのビルド時定数を固定スキーマで1行だけ返す。「デプロイされているwasmが期待した
+        // ビルドか」を確認するためのもので、スキーマはツール側から依存できるよう安定させる。
+        if this.introspect_version {
+            if this.src_idx >= 1 {
+                return Ok(None);
+            }
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "crate_version" => Some(Cell::String(env!("CARGO_PKG_VERSION").to_owned())),
+                    "host_version_requirement" => {
+                        Some(Cell::String(Self::host_version_requirement()))
+                    }
+                    "source_modes" => Some(Cell::Json(
+                        JsonValue::from(vec!["gviz", "v4", "csv"]).to_string(),
+                    )),
+                    "type_oids" => Some(Cell::Json(
+                        JsonValue::from(vec![
+                            "bool",
+                            "i64",
+                            "f64",
+                            "string",
+                            "date",
+                            "timestamp",
+                            "timestamptz",
+                            "json",
+                        ])
+                        .to_string(),
+                    )),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
+
+        // introspect = 'stats' モード: begin_scan開始時点でのStatsシングルトンのスナップショットを
+        // 固定スキーマ（total_scans/cache_hits/cache_misses/retries/bytes_fetched）で1行だけ返す。
+        if let Some(snapshot) = this.introspect_stats {
+            if this.src_idx >= 1 {
+                return Ok(None);
+            }
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "total_scans" => Some(Cell::I64(snapshot.total_scans)),
+                    "cache_hits" => Some(Cell::I64(snapshot.cache_hits)),
+                    "cache_misses" => Some(Cell::I64(snapshot.cache_misses)),
+                    "retries" => Some(Cell::I64(snapshot.retries)),
+                    "bytes_fetched" => Some(Cell::I64(snapshot.bytes_fetched)),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
+
+        // introspect = 'rowcount' モード: begin_scanで解決したシートのデータ行数を固定スキーマ
+        // （row_count列）で1行だけ返す。
+        if let Some(row_count) = this.introspect_rowcount {
+            if this.src_idx >= 1 {
+                return Ok(None);
+            }
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "row_count" => Some(Cell::I64(row_count)),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
+
+        // introspectモード: 固定スキーマ（ordinal/column_label/gviz_type/pg_type）で1列ずつ返す。
+        if let Some(cols) = this.introspect_cols.clone() {
+            if this.src_idx >= cols.len() {
+                return Ok(None);
+            }
+            let col_info = &cols[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "ordinal" => Some(Cell::I64(col_info.ordinal)),
+                    "column_label" => Some(Cell::String(col_info.label.clone())),
+                    "gviz_type" => Some(Cell::String(col_info.gviz_type.clone())),
+                    "pg_type" => Some(Cell::String(col_info.pg_type.clone())),
+                    "header_note" => col_info.header_note.clone().map(Cell::String),
+                    "column_comment" => Some(Cell::String(col_info.column_comment.clone())),
+                    other => return Err(format!("unknown introspect column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
+
+        // keyvalueモード: 固定スキーマ（key/value）で1行ずつ返す。
+        if let Some(kv_rows) = this.keyvalue_rows.clone() {
+            if this.src_idx >= kv_rows.len() {
+                return Ok(None);
+            }
+            let (key, value) = &kv_rows[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let cell = match tgt_col.name().as_str() {
+                    "key" => Some(Cell::String(key.clone())),
+                    "value" => Some(Cell::String(value.clone())),
+                    other => return Err(format!("unknown keyvalue column '{}'", other)),
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
+        }
+
+        // unpivotモード: 固定スキーマ（id列のラベルに一致する列 + variable + value）で1行ずつ返す。
+        if let Some(rows) = this.unpivot_rows.clone() {
+            if this.src_idx >= rows.len() {
+                return Ok(None);
+            }
+            let unpivot_row = &rows[this.src_idx];
+            for tgt_col in ctx.get_columns() {
+                let tgt_col_name = tgt_col.name();
+                let cell = if tgt_col_name == "variable" {
+                    Some(Cell::String(unpivot_row.variable.clone()))
+                } else if tgt_col_name == "value" {
+                    unpivot_row.value.clone().map(Cell::String)
+                } else if let Some((_, v)) =
+                    unpivot_row.id_values.iter().find(|(label, _)| label == &tgt_col_name)
+                {
+                    v.clone().map(Cell::String)
+                } else {
+                    return Err(format!(
+                        "unknown unpivot column '{}' (expected an unpivot_id_columns label, 'variable', or 'value')",
+                        tgt_col_name
+                    ));
+                };
+                row.push(cell.as_ref());
+            }
+            this.src_idx += 1;
+            return Ok(Some(0));
         }
-        // extract current source row, an example of the source row in JSON:
-        // {
-        //   "c": [{
-        //      "v": 1.0,
-        //      "f": "1"
-        //    }, {
-        //      "v": "Erlich Bachman"
-        //    }, null, null, null, null, { "v": null }
-        //    ]
-        // }
-        let src_row = &this.src_rows[this.src_idx];
-        // loop through each target column, map source cell to target cell
-        for tgt_col in ctx.get_columns() {
-            let (tgt_col_num, tgt_col_name) = (tgt_col.num(), tgt_col.name());
-            if let Some(src) = src_row.pointer(&format!("/c/{}/v", tgt_col_num - 1)) {
-                // we only support I64 and String cell types here, add more type
-                // conversions if you need
-                let cell = match tgt_col.type_oid() {
-                    TypeOid::I64 => src.as_f64().map(|v| Cell::I64(v as _)),
-                    TypeOid::String => src.as_str().map(|v| Cell::String(v.to_owned())),
+
+        // include_header_row = 'true' の場合、gvizの通常モードでのみ、実データの前に1回だけ
+        // source labelから成るヘッダー行を返す。src_idxは進めない（この行は実データの1行を
+        // 消費していない）ので、直後に続くループはこれまで通りsrc_idx = 0のデータ行から始まる。
+        if this.include_header_row && !this.header_row_emitted {
+            this.header_row_emitted = true;
+            let tgt_col_nums: Vec<u32> = ctx.get_columns().iter().map(|c| c.num()).collect();
+            let labels = header_row_labels(&this.column_labels, &this.column_order, &tgt_col_nums);
+            for label in &labels {
+                row.push(Some(&Cell::String(label.clone())));
+            }
+            return Ok(Some(0));
+        }
+
+        // required_columns + on_row_error='skip' の場合、不正な行を読み飛ばして次の行を試す必要があるため
+        // ループにしている。それ以外のケースでは1回のイテレーションでreturnする。
+        loop {
+            // if all source rows are consumed, stop data scan
+            if this.src_idx >= this.src_rows.len() {
+                return Ok(None);
+            }
+            let row_idx = this.src_idx;
+            // extract current source row, an example of the source row in JSON:
+            // {
+            //   "c": [{
+            //      "v": 1.0,
+            //      "f": "1"
+            //    }, {
+            //      "v": "Erlich Bachman"
+            //    }, null, null, null, null, { "v": null }
+            //    ]
+            // }
+            let src_row = this.src_rows[row_idx].clone();
+            // on_short_row: マップ対象の（疑似カラムを除く）ターゲット列が要求する幅よりソース行の
+            // "c"配列が短い（raggedな）場合の扱い。strict_column_bounds（シート全体の宣言列数という
+            // スキーマレベルのチェック）とは別レイヤーの、この行そのものの実際の幅に対するチェック。
+            // skip_rowsで既に間引かれた行はこの時点で存在しないので、対象は常に間引き後の行になる。
+            let tgt_col_nums_for_width: Vec<u32> = ctx
+                .get_columns()
+                .iter()
+                .filter(|c| !PSEUDO_COLUMN_NAMES.contains(&c.name().as_str()))
+                .map(|c| c.num())
+                .collect();
+            if let Some((actual_len, required_len)) =
+                ragged_row_shortfall(&src_row, &tgt_col_nums_for_width, &this.column_order)
+            {
+                match this.on_short_row.as_str() {
+                    "null" => {} // 既定挙動: 欠けている列はこの後nullとして扱われる
+                    "skip" => {
+                        report_warning(&format!(
+                            "skipping source row {} because it has only {} column(s) but {} are mapped (ragged row)",
+                            row_idx, actual_len, required_len
+                        ));
+                        this.src_idx += 1;
+                        this.rows_skipped_for_errors += 1;
+                        if this.max_row_errors > 0 && this.rows_skipped_for_errors > this.max_row_errors {
+                            return Err(format!(
+                                "aborting scan: {} of {} source rows skipped (max_row_errors = {}); the sheet may be broadly malformed",
+                                this.rows_skipped_for_errors, this.src_idx, this.max_row_errors
+                            ));
+                        }
+                        continue;
+                    }
                     _ => {
                         return Err(format!(
-                            "column {} data type is not supported",
-                            tgt_col_name
+                            "source row {} has only {} column(s) but {} are mapped (on_short_row = 'error')",
+                            row_idx, actual_len, required_len
+                        ));
+                    }
+                }
+            }
+            // loop through each target column, map source cell to target cell
+            let mut cells: Vec<Option<Cell>> = Vec::new();
+            let mut skip_row = false;
+            for tgt_col in ctx.get_columns() {
+                let (tgt_col_num, tgt_col_name) = (tgt_col.num(), tgt_col.name());
+                let src_col_idx = resolve_source_col_index(tgt_col_num, &this.column_order);
+                let value_pointer = resolve_cell_pointer(&this.cell_value_pointer, src_col_idx);
+                let formatted_pointer = resolve_cell_pointer(&this.cell_formatted_pointer, src_col_idx);
+                // _spread_sheet_id は、spread_sheet_ids（複数ファイル読み込み）を使った場合に
+                // どのファイル由来の行かを示す疑似カラム。単一spread_sheet_idの場合は使えない。
+                let cell = if let Some(c) = constant_cell_for_column(&tgt_col, &this.constant_columns)? {
+                    Some(c)
+                } else if tgt_col_name == "_spread_sheet_id" {
+                    this.row_spread_sheet_ids
+                        .get(row_idx)
+                        .cloned()
+                        .map(Cell::String)
+                } else if tgt_col_name == "_row" {
+                    // _row はソース行全体（gvizの"c"配列、各セルの v/f 等をそのまま含む）をjsonbで
+                    // 返す疑似カラム。このFDWはgvizへの列選択（select）プッシュダウンを行わず、
+                    // 常にシートの全列を取得してからctx.get_columns()に載っている列だけをローカルで
+                    // マッピングする実装のため、他の型付き列を同時に選択していても_rowの内容が
+                    // 一部だけに絞り込まれて欠けることは構造上起こらない。
+                    Some(Cell::Json(
+                        src_row.pointer("/c").cloned().unwrap_or(JsonValue::Null).to_string(),
+                    ))
+                } else if tgt_col_name == "_col_count" {
+                    // _col_count は、この行のgviz上の"c"配列の長さ（末尾の欠損セルも含めた
+                    // ソース列数）をそのまま返す疑似カラム。他の型付き列のように通常のソース
+                    // インデックスを消費しないため、ragged data（行ごとに列数が揺れるシート）を
+                    // SQL側のフィルタで検出する用途に使える。bigint列として宣言する想定。
+                    Some(Cell::I64(
+                        src_row
+                            .pointer("/c")
+                            .and_then(|v| v.as_array())
+                            .map(|a| a.len() as i64)
+                            .unwrap_or(0),
+                    ))
+                } else if tgt_col_name == "_synthetic_key" {
+                    // _synthetic_key は、シート自体に自然なrowidが無いテーブルでもETL側でdedupe/upsertの
+                    // キーとして使える、{spread_sheet_id}:{sheet_id}:{rownum}形式の合成キーを返す疑似
+                    // カラム。A1形式のセル参照とは別物で、シートの行が並べ替えられない限りスキャンを
+                    // 跨いで安定する。複数ファイル（spread_sheet_ids）指定時はrow_spread_sheet_idsから
+                    // 行ごとのspread_sheet_idを、単一spread_sheet_id指定時はsynthetic_key_spread_sheet_id
+                    // を使う。
+                    let scoped_spread_sheet_id = this
+                        .row_spread_sheet_ids
+                        .get(row_idx)
+                        .cloned()
+                        .unwrap_or_else(|| this.synthetic_key_spread_sheet_id.clone());
+                    Some(Cell::String(build_synthetic_key(
+                        &scoped_spread_sheet_id,
+                        &this.synthetic_key_sheet_id,
+                        row_idx,
+                    )))
+                } else if tgt_col_name == "_row_hash" {
+                    // _row_hash は、選択されている列（SELECTで絞り込んだ列や並び順）に関わらず、_rowと
+                    // 同じソース行データ（gvizの"c"配列全体）から計算した安定なハッシュの16進文字列を
+                    // 返す疑似カラム。同一のソース行は、スキャンを跨いでも常に同じ値になる。ETL側で
+                    // 前回スキャン時のハッシュと比較するだけで変更検知ができ、行全体を転送・比較する
+                    // 必要が無くなる。暗号強度は不要なので、依存を増やさず既存のsha2クレートを流用する。
+                    Some(Cell::String(hash_row_hex(
+                        src_row.pointer("/c").unwrap_or(&JsonValue::Null),
+                    )))
+                } else if tgt_col_name == "_is_empty" {
+                    // _is_empty は、選択された（_is_empty自身を含む疑似カラムを除く）ターゲット列に
+                    // 対応するソースセルが全てnull/空文字であればtrueを返す疑似カラム。
+                    // stop_at_blank_rowsのように行そのものを切り詰めるのではなく、フラグとして
+                    // 残すことで「空行を消さずに検出したい」ダッシュボード用途に応える。
+                    let tgt_col_nums: Vec<u32> = ctx
+                        .get_columns()
+                        .iter()
+                        .filter(|c| !PSEUDO_COLUMN_NAMES.contains(&c.name().as_str()))
+                        .map(|c| c.num())
+                        .collect();
+                    Some(Cell::Bool(row_is_empty_over_columns(
+                        &src_row,
+                        &tgt_col_nums,
+                        &this.column_order,
+                    )))
+                } else if let Some((_, (start, end))) =
+                    this.source_letters.iter().find(|(name, _)| name == &tgt_col_name)
+                {
+                    // source_letters対象列: 範囲内の各ソース列の値をそのままjsonb配列に詰める。
+                    // 欠けているセル（列が無い/nullのセル）はJSON nullになる。
+                    let values: Vec<JsonValue> = (*start..=*end)
+                        .map(|i| {
+                            src_row
+                                .pointer(&resolve_cell_pointer(&this.cell_value_pointer, i))
+                                .cloned()
+                                .unwrap_or(JsonValue::Null)
+                        })
+                        .collect();
+                    Some(Cell::Json(JsonValue::Array(values).to_string()))
+                } else if let Some((_, other_col)) =
+                    this.format_of.iter().find(|(name, _)| name == &tgt_col_name)
+                {
+                    // format_of対象列: 自身の値の代わりに、other_colと同じソース列のgvizパターン文字列
+                    // （/table/colsのpattern）を返す。列単位のメタデータなので全行で同じ値になる。
+                    let other_tgt_num = ctx
+                        .get_columns()
+                        .iter()
+                        .find(|c| &c.name() == other_col)
+                        .map(|c| c.num())
+                        .ok_or_else(|| format!("format_of references unknown column '{}'", other_col))?;
+                    let other_src_idx = resolve_source_col_index(other_tgt_num, &this.column_order);
+                    this.column_patterns
+                        .get(other_src_idx)
+                        .cloned()
+                        .flatten()
+                        .map(Cell::String)
+                } else if src_col_idx >= this.column_patterns.len() {
+                    // 列の対応するソースインデックスがシートの総列数を超えている = 特定行がその列を
+                    // 短く終えているだけの話（下のpointer失敗によるnull）とは別に、スキーマ側の
+                    // 問題として区別して知らせる。
+                    if this.strict_column_bounds {
+                        return Err(format!(
+                            "column '{}' maps to source index {} but the sheet only has {} columns",
+                            tgt_col_name,
+                            src_col_idx,
+                            this.column_patterns.len()
+                        ));
+                    }
+                    if !this.oob_reported_columns.iter().any(|c| c == &tgt_col_name) {
+                        report_info(&format!(
+                            "column '{}' maps to source index {} but the sheet only has {} columns; returning null for every row",
+                            tgt_col_name,
+                            src_col_idx,
+                            this.column_patterns.len()
                         ));
+                        this.oob_reported_columns.push(tgt_col_name.clone());
+                    }
+                    None
+                } else if let Some(error_text) = gviz_cell_error(&src_row, &value_pointer, &formatted_pointer) {
+                    // #REF!/#DIV/0!等の数式エラーセル。on_cell_errorに従って扱いを決める。
+                    match this.on_cell_error.as_str() {
+                        "error" => {
+                            return Err(format!(
+                                "cell error '{}' in column '{}' at row {}",
+                                error_text, tgt_col_name, row_idx
+                            ));
+                        }
+                        "string" => Some(Cell::String(error_text)),
+                        _ => None,
                     }
+                } else if let Some(src) = src_row.pointer(&value_pointer) {
+                    // null_strings に一致するセルは、型変換の前にNULLとして扱う。数値/日付列でも
+                    // 人間が"N/A"等の文字列を入力していれば(vが文字列のままgvizから返るため)ここで拾える。
+                    if src.as_str().is_some_and(|v| is_null_sentinel(v, &this.null_strings)) {
+                        None
+                    } else {
+                        // we only support I64 and String cell types here, add more type
+                        // conversions if you need
+                        match tgt_col.type_oid() {
+                            TypeOid::I64 => {
+                                if this.duration_as.iter().any(|(c, mode)| c == &tgt_col_name && mode == "total_seconds") {
+                                    // 表示用文字列"f"が`[h]:mm:ss`でパースできればそれを使い、できなければ
+                                    // v（日数の端数として表された経過時間）を秒に換算する。
+                                    let formatted = src_row.pointer(&formatted_pointer).and_then(|v| v.as_str());
+                                    let seconds = formatted
+                                        .and_then(parse_duration_string_to_seconds)
+                                        .or_else(|| src.as_f64().map(|days| (days * 86400.0).round() as i64));
+                                    match seconds {
+                                        Some(seconds) => Some(Cell::I64(seconds)),
+                                        None => record_or_propagate_cell_error(
+                                            this,
+                                            format!("cannot parse '[h]:mm:ss' duration cell '{}'", formatted.unwrap_or_default()),
+                                            row_idx,
+                                            &tgt_col_name,
+                                            formatted.unwrap_or_default(),
+                                        )?,
+                                    }
+                                } else if this.datetime_as.iter().any(|(c, mode)| c == &tgt_col_name && mode == "epoch_ms") {
+                                    match src.as_str() {
+                                        Some(v) => match gviz_date_value_to_epoch_ms(
+                                            v,
+                                            resolve_column_timezone(&tgt_col_name, &this.column_timezones, &this.timezone),
+                                        ) {
+                                            Ok(epoch_ms) => Some(Cell::I64(epoch_ms)),
+                                            Err(e) => record_or_propagate_cell_error(this, e, row_idx, &tgt_col_name, v)?,
+                                        },
+                                        None => None,
+                                    }
+                                } else if this.use_formatted_columns.iter().any(|c| c == &tgt_col_name) {
+                                    // use_formatted対象列: v(f64)ではなく表示用文字列"f"（桁区切り付き）を読む。
+                                    let formatted = src_row
+                                        .pointer(&formatted_pointer)
+                                        .and_then(|v| v.as_str());
+                                    match formatted {
+                                        Some(f) => {
+                                            let pattern: Option<String> = this
+                                                .column_patterns
+                                                .get(src_col_idx)
+                                                .and_then(|p| p.clone());
+                                            match parse_grouped_integer(f, pattern.as_deref(), &this.locale, &tgt_col_name) {
+                                                Ok(v) => Some(Cell::I64(v)),
+                                                Err(e) => record_or_propagate_cell_error(this, e, row_idx, &tgt_col_name, f)?,
+                                            }
+                                        }
+                                        None => None,
+                                    }
+                                } else if let Some(v) = src.as_str() {
+                                    // vそのものが桁区切り付きの文字列になっている場合（巨大な整数等）。
+                                    let pattern: Option<String> = this
+                                        .column_patterns
+                                        .get(src_col_idx)
+                                        .and_then(|p| p.clone());
+                                    match parse_grouped_integer(v, pattern.as_deref(), &this.locale, &tgt_col_name) {
+                                        Ok(parsed) => Some(Cell::I64(parsed)),
+                                        Err(e) => record_or_propagate_cell_error(this, e, row_idx, &tgt_col_name, v)?,
+                                    }
+                                } else {
+                                    match src.as_f64() {
+                                        Some(v) => {
+                                            let scaled = apply_scale_offset(v, &tgt_col_name, &this.scale_columns, &this.offset_columns);
+                                            resolve_nonfinite(scaled, &tgt_col_name, &this.nonfinite)?.map(|v| Cell::I64(v as _))
+                                        }
+                                        None => None,
+                                    }
+                                }
+                            }
+                            // patternが付いた通貨/桁区切り列は、v(f64)ではなく表示用文字列"f"を
+                            // patternまたはlocaleの規則で正規化してから浮動小数点として復元する。
+                            TypeOid::F64 => {
+                                let formatted = src_row
+                                    .pointer(&formatted_pointer)
+                                    .and_then(|v| v.as_str());
+                                let parsed = match formatted {
+                                    Some(f) => {
+                                        let pattern = this
+                                            .column_patterns
+                                            .get(src_col_idx)
+                                            .and_then(|p| p.as_deref());
+                                        let f = apply_strip_affixes(
+                                            f,
+                                            &tgt_col_name,
+                                            &this.strip_prefix_columns,
+                                            &this.strip_suffix_columns,
+                                        );
+                                        strip_currency_and_separators(&f, pattern, &this.locale)
+                                            .parse::<f64>()
+                                            .ok()
+                                    }
+                                    None => src.as_f64(),
+                                };
+                                match parsed {
+                                    Some(v) => {
+                                        let scaled = apply_scale_offset(v, &tgt_col_name, &this.scale_columns, &this.offset_columns);
+                                        resolve_nonfinite(scaled, &tgt_col_name, &this.nonfinite)?.map(Cell::F64)
+                                    }
+                                    None => None,
+                                }
+                            }
+                            // numeric(p,s)列: round_toで宣言済みのscaleに収まるよう桁数を丸めてから
+                            // Cell::Numericに詰める（WITのnumericバリアントはf64をそのまま運ぶ）。
+                            TypeOid::Numeric => {
+                                let formatted = src_row
+                                    .pointer(&formatted_pointer)
+                                    .and_then(|v| v.as_str());
+                                let parsed = match formatted {
+                                    Some(f) => {
+                                        let pattern = this
+                                            .column_patterns
+                                            .get(src_col_idx)
+                                            .and_then(|p| p.as_deref());
+                                        let f = apply_strip_affixes(
+                                            f,
+                                            &tgt_col_name,
+                                            &this.strip_prefix_columns,
+                                            &this.strip_suffix_columns,
+                                        );
+                                        strip_currency_and_separators(&f, pattern, &this.locale)
+                                            .parse::<f64>()
+                                            .ok()
+                                    }
+                                    None => src.as_f64(),
+                                };
+                                match parsed {
+                                    Some(v) => {
+                                        let scaled = apply_scale_offset(v, &tgt_col_name, &this.scale_columns, &this.offset_columns);
+                                        let rounded = apply_round_to(scaled, &tgt_col_name, &this.round_to_columns);
+                                        resolve_nonfinite(rounded, &tgt_col_name, &this.nonfinite)?.map(Cell::Numeric)
+                                    }
+                                    None => None,
+                                }
+                            }
+                            TypeOid::String => {
+                                // number_as_text_columns に含まれる列では、v(f64)を経由すると桁数の多いID等が
+                                // 精度落ち・指数表記になってしまう。gvizが提供する表示用文字列(f)を優先し、
+                                // なければ指数表記を使わずにv(f64)を文字列化する。テキスト由来のセルには影響しない。
+                                let raw: Option<String> = if src.is_number()
+                                    && (this.lenient_text || this.number_as_text_columns.iter().any(|c| c == &tgt_col_name))
+                                {
+                                    src_row
+                                        .pointer(&formatted_pointer)
+                                        .and_then(|v| v.as_str())
+                                        .map(|v| v.to_owned())
+                                        .or_else(|| src.as_f64().map(format_f64_without_scientific_notation))
+                                } else if let Some(v) = src.as_str() {
+                                    Some(v.to_owned())
+                                } else if this.lenient_text {
+                                    // lenient_text = 'true': gvizの型がtext列と一致しない値（真偽値など）でも
+                                    // nullにせず素朴に文字列化して返す、探索的クエリ用のエスケープハッチ。
+                                    // 数値の精度に関する扱いは上のnumber_as_text_columnsと同じ経路を通るため、
+                                    // ここではそれ以外（真偽値）のみを対象にする。型の厳密さは失われる点に注意。
+                                    src.as_bool().map(|b| b.to_string())
+                                } else {
+                                    None
+                                };
+                                // strip_leading_apostropheでテキスト強制の'を落としてから、
+                                // strip_prefix/strip_suffixで引用符や通貨記号のような一貫した
+                                // 包み込み文字列を型変換の最後に取り除く。
+                                raw.map(|v| {
+                                    let v = strip_leading_apostrophe(&v, this.strip_leading_apostrophe);
+                                    Cell::String(apply_strip_affixes(
+                                        &v,
+                                        &tgt_col_name,
+                                        &this.strip_prefix_columns,
+                                        &this.strip_suffix_columns,
+                                    ))
+                                })
+                            }
+                            TypeOid::Date | TypeOid::Timestamp | TypeOid::Timestamptz => match src.as_str() {
+                                Some(v) => match parse_date_cell_to_epoch_ms(
+                                    v,
+                                    &this.date_format,
+                                    resolve_column_timezone(&tgt_col_name, &this.column_timezones, &this.timezone),
+                                ) {
+                                    Ok(epoch_ms) => Some(match tgt_col.type_oid() {
+                                        TypeOid::Date => Cell::Date(epoch_ms / 1000),
+                                        TypeOid::Timestamp => Cell::Timestamp(epoch_ms * 1000),
+                                        _ => Cell::Timestamptz(epoch_ms * 1000),
+                                    }),
+                                    Err(e) => match this.on_cell_error.as_str() {
+                                        "error" => return Err(e),
+                                        "string" => Some(Cell::String(v.to_owned())),
+                                        _ => None,
+                                    },
+                                },
+                                None => None,
+                            },
+                            TypeOid::Bool => {
+                                if let Some(b) = src.as_bool() {
+                                    // gviz自体がJSONの真偽値としてvを返している（tqのbooleanリテラル等）場合はそのまま使う。
+                                    Some(Cell::Bool(b))
+                                } else if let Some(v) = src.as_str() {
+                                    match parse_bool_token(v, &this.bool_true_values, &this.bool_false_values) {
+                                        Some(b) => Some(Cell::Bool(b)),
+                                        None => match this.on_cell_error.as_str() {
+                                            "error" => {
+                                                return Err(format!(
+                                                    "cannot parse '{}' as a boolean in column '{}' at row {}",
+                                                    v, tgt_col_name, row_idx
+                                                ));
+                                            }
+                                            "string" => Some(Cell::String(v.to_owned())),
+                                            _ => None,
+                                        },
+                                    }
+                                } else {
+                                    None
+                                }
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "column {} data type is not supported",
+                                    tgt_col_name
+                                ));
+                            }
+                        }
+                    }
+                } else {
+                    None
                 };
-                // push the cell to target row
+                // default/default_number/default_text/default_boolによる欠損値の穴埋め。
+                // allowed_values/required_columnsより先に適用し、埋まった値がそれらのチェックにも
+                // 素通りするのではなく、実際の値として評価されるようにする。
+                let cell = apply_default_cell(
+                    cell,
+                    &tgt_col,
+                    &this.default_columns,
+                    this.default_number.as_deref(),
+                    this.default_text.as_deref(),
+                    this.default_bool.as_deref(),
+                )?;
+                // allowed_values に許可値セットが指定されている列は、セット外の値をon_row_error
+                // ポリシーに従って処理する（required_columnsより先に評価し、nullにされた場合は
+                // 下のrequired_columnsチェックにも引き続きかかるようにする）。
+                let (cell, allowed_values_skip) = enforce_allowed_values(
+                    cell,
+                    &tgt_col_name,
+                    row_idx,
+                    &this.allowed_values,
+                    this.allowed_values_case_sensitive,
+                    &this.on_row_error,
+                )?;
+                if allowed_values_skip {
+                    skip_row = true;
+                    break;
+                }
+                // required_columns に含まれる列がnullになる場合は、on_row_errorポリシーに従って処理する。
+                if cell.is_none() && this.required_columns.iter().any(|c| c == &tgt_col_name) {
+                    match this.on_row_error.as_str() {
+                        "null" => {} // ポリシー上nullを許容し、そのまま通す
+                        "skip" => {
+                            report_warning(&format!(
+                                "skipping source row {} because required column '{}' was null",
+                                row_idx, tgt_col_name
+                            ));
+                            skip_row = true;
+                            break;
+                        }
+                        _ => {
+                            return Err(format!(
+                                "required column '{}' is null at source row {}",
+                                tgt_col_name, row_idx
+                            ));
+                        }
+                    }
+                }
+                cells.push(cell);
+            }
+            this.src_idx += 1;
+            if skip_row {
+                this.rows_skipped_for_errors += 1;
+                if this.max_row_errors > 0 && this.rows_skipped_for_errors > this.max_row_errors {
+                    return Err(format!(
+                        "aborting scan: {} of {} source rows skipped by on_row_error = 'skip' (max_row_errors = {}); the sheet may be broadly malformed",
+                        this.rows_skipped_for_errors, this.src_idx, this.max_row_errors
+                    ));
+                }
+                continue;
+            }
+            for cell in &cells {
                 row.push(cell.as_ref());
-            } else {
-                row.push(None);
             }
+            break;
         }
-        // advance to next source row
-        this.src_idx += 1;
         // tell Postgres we've done one row, and need to scan the next row
         Ok(Some(0))
     }
+}
 
-    // ここからエラーと未サポート機能の関数。
+// ⭐️ここから gviz クエリ言語へのプッシュダウンに使うヘルパー関数
 
-    fn re_scan(_ctx: &Context) -> FdwResult {
-        Err("re_scan on foreign table is not supported".to_owned())
+// Cellの日時系バリアントを、gvizのクエリ言語(https://developers.google.com/chart/interactive/docs/querylanguage)
+// が受け付けるリテラル表記（date "yyyy-mm-dd" / datetime "yyyy-mm-dd HH:MM:SS"）に変換します。
+// timeホストAPI（RFC3339）を経由することで、うるう年やタイムゾーンの計算を自前で行わずに済ませています。
+fn gviz_date_literal(cell: &Cell) -> Result<Option<String>, FdwError> {
+    let (epoch_ms, is_date_only) = match cell {
+        Cell::Date(secs) => (secs * 1000, true),
+        Cell::Timestamp(usecs) | Cell::Timestamptz(usecs) => (usecs / 1000, false),
+        _ => return Ok(None),
+    };
+    let rfc3339 = time::epoch_ms_to_rfc3339(epoch_ms).map_err(|e| e.to_string())?;
+    let (date_part, time_part) = rfc3339
+        .split_once('T')
+        .ok_or_else(|| "unexpected timestamp format from host".to_owned())?;
+    if is_date_only {
+        Ok(Some(format!("date \"{}\"", date_part)))
+    } else {
+        let time_part = time_part.trim_end_matches('Z');
+        let time_part = time_part.split('.').next().unwrap_or(time_part);
+        Ok(Some(format!("datetime \"{} {}\"", date_part, time_part)))
     }
+}
 
-    fn end_scan(_ctx: &Context) -> FdwResult {
-        let this = Self::this_mut();
-        this.src_rows.clear();
-        Ok(())
+// pushdown_column_ref = 'label' のときの列参照。ヘッダーテキスト（postgres側の列名と一致させる
+// 運用を想定）をバッククォートで囲んで参照する。バッククォート自体を含むラベルはエスケープする。
+fn pushdown_label_ref(field: &str) -> String {
+    format!("`{}`", field.replace('`', "\\`"))
+}
+
+// pushdown_column_ref = 'letter'（既定）のときの列参照。resolve_source_col_indexで実際の
+// ソース列インデックスを求めてから、column_index_to_lettersでシート上のレター（A, B, ...）に変換する。
+fn pushdown_letter_ref(tgt_col_num: u32, column_order: &[usize]) -> String {
+    column_index_to_letters(resolve_source_col_index(tgt_col_num, column_order))
+}
+
+// gvizのtq句で列を参照する際の識別子形式を、pushdown_column_refオプションに従って組み立てる。
+// 既定のgviz（gviz_headers未使用）では列はシート上のレター（A, B, ...）でしか参照できないため、
+// mode = 'letter'ではpushdown_letter_refを使う。gviz_headersを有効にしてヘッダー行をラベルとして
+// 解釈させている場合はmode = 'label'でpushdown_label_refを使う。
+fn pushdown_column_ref(
+    ctx: &Context,
+    field: &str,
+    column_order: &[usize],
+    mode: &str,
+) -> Result<String, FdwError> {
+    if mode == "label" {
+        return Ok(pushdown_label_ref(field));
     }
+    let tgt_col_num = ctx
+        .get_columns()
+        .iter()
+        .find(|c| c.name() == field)
+        .map(|c| c.num())
+        .ok_or_else(|| format!("pushdown: unknown column '{}' while building a gviz column reference", field))?;
+    Ok(pushdown_letter_ref(tgt_col_num, column_order))
+}
 
-    fn begin_modify(_ctx: &Context) -> FdwResult {
-        Err("modify on foreign table is not supported".to_owned())
+// modified_columnテーブルオプションが指定されている場合、対応するカラムへの ">" 述語を探し、
+// gvizのtqクエリとして使える "where句" の断片を組み立てます。
+// 注意: この機能はmodified_columnがシート内で昇順（単調増加）にソートされていることを前提とします。
+// ソートされていない場合、last-run以降に追加された行以外も欠落する可能性があります。
+fn build_modified_since_clause(
+    ctx: &Context,
+    modified_column: &str,
+    column_order: &[usize],
+    pushdown_column_ref_mode: &str,
+) -> Result<Option<String>, FdwError> {
+    for qual in ctx.get_quals() {
+        if qual.field() != modified_column || qual.operator() != ">" {
+            continue;
+        }
+        if let QualValue::Cell(cell) = qual.value() {
+            if let Some(literal) = gviz_date_literal(&cell)? {
+                let col_ref =
+                    pushdown_column_ref(ctx, modified_column, column_order, pushdown_column_ref_mode)?;
+                return Ok(Some(format!("{} > {}", col_ref, literal)));
+            }
+        }
     }
+    Ok(None)
+}
 
-    fn insert(_ctx: &Context, _row: &Row) -> FdwResult {
-        Ok(())
+// CellをgvizクエリのリテラルVALUEとしてフォーマットする。日付/タイムスタンプ系はgviz_date_literalに
+// 委譲し、それ以外は数値はそのまま、文字列は引用符で囲み、真偽値は"true"/"false"の予約語で表す。
+// 型を無視して常に文字列リテラルとして送ってしまうと、gvizはブール列を文字列比較として扱ってしまい
+// 一致しなくなるため、Cellの型を見て出し分ける必要がある。
+fn gviz_literal(cell: &Cell) -> Result<Option<String>, FdwError> {
+    match cell {
+        Cell::Bool(b) => Ok(Some(if *b { "true" } else { "false" }.to_owned())),
+        Cell::I8(v) => Ok(Some(v.to_string())),
+        Cell::I16(v) => Ok(Some(v.to_string())),
+        Cell::I32(v) => Ok(Some(v.to_string())),
+        Cell::I64(v) => Ok(Some(v.to_string())),
+        Cell::F32(v) => Ok(Some(v.to_string())),
+        Cell::F64(v) => Ok(Some(v.to_string())),
+        Cell::Numeric(v) => Ok(Some(v.to_string())),
+        Cell::String(s) => Ok(Some(format!("\"{}\"", s.replace('"', "\\\"")))),
+        Cell::Date(_) | Cell::Timestamp(_) | Cell::Timestamptz(_) => gviz_date_literal(cell),
+        Cell::Json(_) => Ok(None),
     }
+}
 
-    fn update(_ctx: &Context, _rowid: Cell, _row: &Row) -> FdwResult {
-        Ok(())
+// 真偽値列に対する "=" 述語を探し、gvizのブールリテラルを使ったwhere句の断片を組み立てます。
+// modified_since句と異なり列名を限定しないため、"=" で真偽値を比較しているqualが見つかり次第使う。
+fn build_bool_equality_clause(
+    ctx: &Context,
+    column_order: &[usize],
+    pushdown_column_ref_mode: &str,
+) -> Result<Option<String>, FdwError> {
+    for qual in ctx.get_quals() {
+        if qual.operator() != "=" {
+            continue;
+        }
+        if let QualValue::Cell(cell @ Cell::Bool(_)) = qual.value() {
+            if let Some(literal) = gviz_literal(&cell)? {
+                let col_ref =
+                    pushdown_column_ref(ctx, &qual.field(), column_order, pushdown_column_ref_mode)?;
+                return Ok(Some(format!("{} = {}", col_ref, literal)));
+            }
+        }
     }
+    Ok(None)
+}
 
-    fn delete(_ctx: &Context, _rowid: Cell) -> FdwResult {
-        Ok(())
+// LIKE/ILIKEパターンのうち、"%x%"（contains）/ "x%"（starts with）/ "%x"（ends with）という
+// 単純な形にきれいに収まるものだけを、gvizクエリ言語の対応する演算子に変換する。
+// "_"（1文字ワイルドカード）やパターン中央に現れる"%"、エスケープ文字"\"を含むもの、
+// ワイルドカードを一切含まないものは、意味を保ったまま変換できないためNoneを返し、
+// postgres側のローカル評価にフォールバックさせる。
+fn classify_like_pattern(pattern: &str) -> Option<(&'static str, &str)> {
+    if pattern.contains('_') || pattern.contains('\\') {
+        return None;
     }
+    let starts_with_pct = pattern.starts_with('%');
+    let ends_with_pct = pattern.ends_with('%') && pattern.len() > 1;
+    let inner = match (starts_with_pct, ends_with_pct) {
+        (true, true) => &pattern[1..pattern.len() - 1],
+        (false, true) => &pattern[..pattern.len() - 1],
+        (true, false) => &pattern[1..],
+        (false, false) => return None,
+    };
+    if inner.is_empty() || inner.contains('%') {
+        return None;
+    }
+    let op = match (starts_with_pct, ends_with_pct) {
+        (true, true) => "contains",
+        (false, true) => "starts with",
+        (true, false) => "ends with",
+        (false, false) => unreachable!(),
+    };
+    Some((op, inner))
+}
 
-    fn end_modify(_ctx: &Context) -> FdwResult {
-        Ok(())
+// LIKE("~~")/ILIKE("~~*")のqualを探し、classify_like_patternで変換できるものが見つかり次第
+// gvizのwhere句の断片を組み立てます（bool equality句と同様、最初の1件のみを採用）。
+//
+// 注意（ILIKEの大文字小文字caveat）: gvizクエリ言語のcontains/starts with/ends withは常に
+// 大文字小文字を区別しない[1]ため、この変換はILIKEの意味論とは完全に一致します。一方で
+// 大文字小文字を区別するはずのLIKEをこの演算子に変換すると、gviz側は大文字小文字違いの行も
+// 一致として返してしまいます。本FDWはpostgres側の再チェックに頼れる保証を持たない
+// （report_pushdown_coverageが「pushed to gviz」と表示するqualはpostgres側で再評価されない
+// 前提で運用している）ため、LIKEをこの演算子にプッシュダウンすると大文字小文字違いの行が
+// 余分に返る可能性がある。呼び出し側で許容できるトレードオフとして受け入れている。
+// [1] https://developers.google.com/chart/interactive/docs/querylanguage#comparison-operators
+fn build_like_clause(
+    ctx: &Context,
+    column_order: &[usize],
+    pushdown_column_ref_mode: &str,
+) -> Result<Option<String>, FdwError> {
+    for qual in ctx.get_quals() {
+        if qual.operator() != "~~" && qual.operator() != "~~*" {
+            continue;
+        }
+        if let QualValue::Cell(Cell::String(pattern)) = qual.value() {
+            if let Some((op, literal)) = classify_like_pattern(&pattern) {
+                let escaped = literal.replace('"', "\\\"");
+                let col_ref =
+                    pushdown_column_ref(ctx, &qual.field(), column_order, pushdown_column_ref_mode)?;
+                return Ok(Some(format!("{} {} \"{}\"", col_ref, op, escaped)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// pushdown_limit = 'true' のとき、プランナーのLIMITヒント（ctx.get_limit）をgvizのtq句へ
+// "limit N [offset M]"として渡すための断片を組み立てる。countが負（=LIMIT無し相当）の場合は
+// 何も返さない。JOIN等でプランナーのLIMITが実際に必要な行数より小さいことがあり得るため
+// 既定では呼ばれない（begin_scan側のpushdown_limitオプション参照）。
+fn build_limit_clause(ctx: &Context) -> Option<String> {
+    let limit = ctx.get_limit()?;
+    let count = limit.count();
+    if count < 0 {
+        return None;
+    }
+    let offset = limit.offset();
+    if offset > 0 {
+        Some(format!("limit {} offset {}", count, offset))
+    } else {
+        Some(format!("limit {}", count))
+    }
+}
+
+// pushdown_order_by = 'true' のとき、プランナーが要求したソート順（ctx.get_sorts）をgvizのtq句へ
+// "order by `col1` [desc], `col2` [desc]"として渡すための断片を組み立てる。ソートが1つも
+// 要求されていなければNoneを返す。collate指定のあるソートはgvizのorder byに対応する概念が無いため
+// 諦めてローカル評価にフォールバックさせる（1件でもcollate指定があれば全体を諦める。部分的な
+// プッシュダウンは行と行の相対順序が崩れるため不可）。
+//
+// 重要な注意（このFDWがプランナーへ「ソート済み」を広告することはできない）:
+// このFDWが実装しているsupabase-wrappersのWASMゲストインターフェース（routines.wit）には、
+// ネイティブPostgres CのFDW APIにあるGetForeignPaths相当のフック（pathkeysを添えてadd_pathする
+// 仕組み）が存在しない。begin-scan/iter-scan/re-scan/end-scanしか公開されていないため、
+// このFDWはスキャン結果がソート済みであることをプランナー側へ伝える手段を持たない。
+// したがって、この関数によるプッシュダウンはgvizに実際に並べ替えさせて出力行の順序を変えるだけであり、
+// merge joinを成立させるためにプランナーが必要とする「入力がソート済みである」という保証を
+// 広告することはできない（=ORDER BYが必要なプランではプランナーは変わらず自前でSortノードを
+// 追加する）。安全に広告できるソート順は現状の実装では存在しない。
+fn build_order_by_clause(ctx: &Context, column_order: &[usize], pushdown_column_ref_mode: &str) -> Option<String> {
+    let sorts = ctx.get_sorts();
+    if sorts.is_empty() || sorts.iter().any(|s| s.collate().is_some()) {
+        return None;
+    }
+    let mut parts = Vec::with_capacity(sorts.len());
+    for s in &sorts {
+        // 列参照の解決に失敗した場合（pushdown_column_ref_modeとgviz_headersの設定が噛み合って
+        // いない等）は、プッシュダウン全体を諦めてローカルのSortノードにフォールバックさせる方が
+        // 一部だけ壊れたorder by句を送るより安全なため、ここで即座にNoneを返す。
+        let col_ref = pushdown_column_ref(ctx, &s.field(), column_order, pushdown_column_ref_mode).ok()?;
+        parts.push(if s.reversed() {
+            format!("{} desc", col_ref)
+        } else {
+            col_ref
+        });
+    }
+    Some(format!("order by {}", parts.join(", ")))
+}
+
+// verboseオプションが有効な場合に、各qualがgvizへプッシュダウンされたか、postgres側で
+// 再チェックされるかをreport_infoで一覧表示する。判定基準はbuild_modified_since_clause、
+// build_bool_equality_clause、build_like_clauseが実際に採用する条件と一致させてある
+// （bool述語・LIKE/ILIKE述語はどちらも最初の1件のみが採用されるため、2件目以降は
+// 「pushed済みのため対象外」として扱う）。
+// OFFSETはLIMITと同じくpushdown_limit = 'true'のときだけbuild_limit_clauseで"limit N offset M"として
+// プッシュダウンされ、qual単位のこの一覧には含めない。ORDER BYは
+// pushdown_order_by = 'true'のときだけbuild_order_by_clauseでプッシュダウンされ得るが、qual
+// （WHERE条件）ではなくctx.get_sorts()由来のため、qual単位のこの一覧には含めない
+// （かつ、build_order_by_clauseのドキュメント参照の通りプランナーへの広告は行われない）。
+// LIMITはpushdown_limit = 'true'のときだけbuild_limit_clauseでプッシュダウンされるが、
+// これはqual（WHERE条件）ではなくctx.get_limit()由来のため、この一覧には含めない。
+fn report_pushdown_coverage(ctx: &Context, modified_column: Option<&str>) {
+    let mut bool_condition_claimed = false;
+    let mut like_condition_claimed = false;
+    for qual in ctx.get_quals() {
+        let pushed_as_modified_since = modified_column == Some(qual.field().as_str())
+            && qual.operator() == ">"
+            && matches!(
+                qual.value(),
+                QualValue::Cell(Cell::Date(_) | Cell::Timestamp(_) | Cell::Timestamptz(_))
+            );
+        let pushed_as_bool_equality = !bool_condition_claimed
+            && qual.operator() == "="
+            && matches!(qual.value(), QualValue::Cell(Cell::Bool(_)));
+        if pushed_as_bool_equality {
+            bool_condition_claimed = true;
+        }
+        let pushed_as_like = !like_condition_claimed
+            && (qual.operator() == "~~" || qual.operator() == "~~*")
+            && matches!(
+                qual.value(),
+                QualValue::Cell(Cell::String(p)) if classify_like_pattern(&p).is_some()
+            );
+        if pushed_as_like {
+            like_condition_claimed = true;
+        }
+        let pushed = pushed_as_modified_since || pushed_as_bool_equality || pushed_as_like;
+        report_info(&format!(
+            "pushdown: `{}` -> {}",
+            qual.deparse(),
+            if pushed {
+                "pushed to gviz"
+            } else {
+                "re-checked by postgres"
+            }
+        ));
+    }
+    report_info(
+        "pushdown: LIMIT/OFFSET are pushed to gviz only when pushdown_limit = 'true', ORDER BY only when pushdown_order_by = 'true'; postgres always re-applies them",
+    );
+    // 注意: 上の「pushed to gviz」判定はmax_pushed_quals/URL長ガードによる後段の打ち切りを
+    // 考慮していない（打ち切りが起きたかはfetch_gviz_resp_json側の別のverboseログで報告する）。
+    // そのため、実際にはmax_pushed_qualsで弾かれてローカル評価に回った条件が
+    // ここでは「pushed to gviz」と表示されることがある。
+}
+
+// gviz クエリ文字列をGETパラメータとして安全に渡すための最小限のパーセントエンコード。
+// フルのURLエンコーディングではなく、tq句に現れうる文字（空白・引用符・バッククォート等）のみを対象にする。
+fn gviz_url_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+// ⭐️ここまで gviz クエリ言語へのプッシュダウンに使うヘルパー関数
+
+// http_headersオプションの値（JSONオブジェクト文字列 `{"Name": "value"}`）を
+// ヘッダーのリストにパースする。未指定なら空のリストを返す。
+fn parse_http_headers_option(value: Option<&str>) -> Result<Vec<(String, String)>, FdwError> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let parsed: JsonValue = serde_json::from_str(value)
+        .map_err(|e| format!("invalid http_headers option (expected a JSON object): {}", e))?;
+    let obj = parsed
+        .as_object()
+        .ok_or_else(|| "invalid http_headers option: expected a JSON object".to_owned())?;
+    Ok(obj
+        .iter()
+        .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_owned()))
+        .collect())
+}
+
+// query_params サーバー/テーブルオプションをパースする。http_headersと違い、プロキシの
+// 事情でJSONを組み立てにくい環境（シェルスクリプトからDDLを生成する等）も想定し、
+// JSONオブジェクトに加えて"k=v&k2=v2"形式の文字列も受理する。
+fn parse_query_params_option(value: Option<&str>) -> Result<Vec<(String, String)>, FdwError> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    if let Ok(parsed) = serde_json::from_str::<JsonValue>(value) {
+        if let Some(obj) = parsed.as_object() {
+            return Ok(obj
+                .iter()
+                .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_owned()))
+                .collect());
+        }
+    }
+    value
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) => Ok((k.to_owned(), v.to_owned())),
+            None => Err(format!(
+                "invalid query_params option: expected a JSON object or 'k=v&k2=v2' pairs, got segment '{}'",
+                pair
+            )),
+        })
+        .collect()
+}
+
+// urlに、まだ同名のキーが含まれていないquery_paramsだけを追加する（FDW自身が組み立てた
+// tqx/tq等のパラメータを、ユーザー指定のquery_paramsが上書きしないようにするため）。
+// 値はgviz_url_encodeでURLエンコードする。
+fn append_query_params(url: &str, query_params: &[(String, String)]) -> String {
+    if query_params.is_empty() {
+        return url.to_owned();
+    }
+    let existing_keys: std::collections::HashSet<&str> = url
+        .split_once('?')
+        .map(|(_, query)| query.split('&').filter_map(|kv| kv.split('=').next()).collect())
+        .unwrap_or_default();
+    let mut out = url.to_owned();
+    for (k, v) in query_params {
+        if existing_keys.contains(k.as_str()) {
+            continue;
+        }
+        out.push(if out.contains('?') { '&' } else { '?' });
+        out.push_str(&gviz_url_encode(k));
+        out.push('=');
+        out.push_str(&gviz_url_encode(v));
+    }
+    out
+}
+
+// 複数のヘッダーリストを、後にあるものほど優先されるようにマージする（同名ヘッダーは上書き）。
+// precedence: built-in < server < table
+fn merge_headers(layers: &[Vec<(String, String)>]) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = Vec::new();
+    for layer in layers {
+        for (k, v) in layer {
+            if let Some(existing) = merged.iter_mut().find(|(ek, _)| ek == k) {
+                existing.1 = v.clone();
+            } else {
+                merged.push((k.clone(), v.clone()));
+            }
+        }
+    }
+    merged
+}
+
+// ⭐️ここから auth_mode による認証方式の切り替え・フォールバック
+
+// auth_mode テーブルオプションに応じて、gvizリクエストに試す認証方式を優先順に並べた候補リストを
+// 組み立てる。"auto"以外はそのモード専用の候補を1つだけ返し、必要な認証情報（service_account/
+// api_key）が無ければエラーにする。"auto"はまず無認証（public）を試し、その後利用可能な
+// 認証情報（jwt -> token -> api_key の順）をフォールバック候補として積む。
+fn build_auth_candidates(
+    url: &str,
+    auth_mode: &str,
+    headers_public: &[(String, String)],
+    headers_token: &[(String, String)],
+    jwt_header: Option<(String, String)>,
+    api_key: Option<&str>,
+) -> Result<Vec<(&'static str, String, Vec<(String, String)>)>, FdwError> {
+    let with_api_key = |key: &str| format!("{}&key={}", url, gviz_url_encode(key));
+    match auth_mode {
+        "public" => Ok(vec![("public", url.to_owned(), headers_public.to_vec())]),
+        "token" => Ok(vec![("token", url.to_owned(), headers_token.to_vec())]),
+        "jwt" => match jwt_header {
+            Some(h) => {
+                let mut headers = headers_public.to_vec();
+                headers.push(h);
+                Ok(vec![("jwt", url.to_owned(), headers)])
+            }
+            None => Err("auth_mode = 'jwt' requires a 'service_account' server option".to_owned()),
+        },
+        "api_key" => match api_key {
+            Some(key) => Ok(vec![("api_key", with_api_key(key), headers_public.to_vec())]),
+            None => Err("auth_mode = 'api_key' requires an 'api_key' table option".to_owned()),
+        },
+        "auto" => {
+            let mut candidates = vec![("public", url.to_owned(), headers_public.to_vec())];
+            if let Some(h) = jwt_header {
+                let mut headers = headers_public.to_vec();
+                headers.push(h);
+                candidates.push(("jwt", url.to_owned(), headers));
+            }
+            if headers_token != headers_public {
+                candidates.push(("token", url.to_owned(), headers_token.to_vec()));
+            }
+            if let Some(key) = api_key {
+                candidates.push(("api_key", with_api_key(key), headers_public.to_vec()));
+            }
+            Ok(candidates)
+        }
+        other => Err(format!(
+            "invalid auth_mode option '{}' (expected 'public', 'token', 'jwt', 'api_key', or 'auto')",
+            other
+        )),
+    }
+}
+
+// gvizのtqxレスポンスに付与される")]}'\n"プレフィックスを取り除く。
+// プレフィックスが無い応答はログインページへのリダイレクト等、認証失敗の兆候とみなす。
+fn strip_gviz_prefix(body: &str) -> Result<&str, FdwError> {
+    body.strip_prefix(")]}'\n")
+        .ok_or_else(|| "invalid response (not a gviz JSON body; looks like an auth/login redirect)".to_owned())
+}
+
+// debug = 'raw_body' 用に、debug_max_bytesを超える本文をUTF-8の文字境界を壊さずに切り詰める。
+// (切り詰め後の本文, 切り詰めたかどうか) を返す。
+fn truncate_body_for_debug(body: &str, max_bytes: usize) -> (String, bool) {
+    if body.len() <= max_bytes {
+        return (body.to_owned(), false);
+    }
+    let mut end = max_bytes;
+    while end > 0 && !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    (body[..end].to_owned(), true)
+}
+
+// 1つの認証候補でgvizへリクエストし、有効なgvizレスポンス（")]}'\n"プレフィックス付きJSON）を
+// 取り出す。プレフィックスが無い応答はログインページへのリダイレクト等、認証失敗の兆候とみなす。
+fn try_gviz_auth_candidate(
+    url: &str,
+    headers: &[(String, String)],
+    cache_ttl_secs: i64,
+    cache_max_entries: usize,
+    cache_max_bytes: usize,
+    circuit_threshold: u32,
+    circuit_window_secs: u64,
+    circuit_cooldown_secs: u64,
+    circuit_max_entries: usize,
+    snapshot: bool,
+    refresh: bool,
+    query_params: &[(String, String)],
+) -> Result<String, FdwError> {
+    let req = http::Request {
+        method: http::Method::Get,
+        url: url.to_owned(),
+        headers: headers.to_vec(),
+        body: String::default(),
+    };
+    let raw_body = fetch_sheet_body(
+        req,
+        cache_ttl_secs,
+        cache_max_entries,
+        cache_max_bytes,
+        circuit_threshold,
+        circuit_window_secs,
+        circuit_cooldown_secs,
+        circuit_max_entries,
+        snapshot,
+        refresh,
+        query_params,
+    )?;
+    strip_gviz_prefix(&raw_body).map(str::to_owned)
+}
+
+// build_auth_candidatesが返した候補を優先順に試し、最初に成功したものの本文と、成功した
+// 認証方式名を返す。auth_mode = 'auto' の場合、verboseが有効なら最終的に使われた方式を報告する。
+fn fetch_gviz_body_with_auth_fallback(
+    candidates: &[(&'static str, String, Vec<(String, String)>)],
+    cache_ttl_secs: i64,
+    cache_max_entries: usize,
+    cache_max_bytes: usize,
+    circuit_threshold: u32,
+    circuit_window_secs: u64,
+    circuit_cooldown_secs: u64,
+    circuit_max_entries: usize,
+    verbose: bool,
+    snapshot: bool,
+    refresh: bool,
+    query_params: &[(String, String)],
+) -> Result<(String, &'static str, String), FdwError> {
+    let mut last_err = "auth_mode has no candidate strategies to try".to_owned();
+    for (name, url, headers) in candidates {
+        match try_gviz_auth_candidate(
+            url,
+            headers,
+            cache_ttl_secs,
+            cache_max_entries,
+            cache_max_bytes,
+            circuit_threshold,
+            circuit_window_secs,
+            circuit_cooldown_secs,
+            circuit_max_entries,
+            snapshot,
+            refresh,
+            query_params,
+        ) {
+            Ok(body) => {
+                if verbose {
+                    report_info(&format!("auth: strategy '{}' succeeded", name));
+                }
+                return Ok((body, name, url.clone()));
+            }
+            Err(e) => {
+                if verbose {
+                    report_info(&format!("auth: strategy '{}' failed ({}); trying next", name, e));
+                }
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+// ⭐️ここまで auth_mode による認証方式の切り替え・フォールバック
+
+// ⭐️ここからレスポンスキャッシュ（TTL付きLRU）
+
+// 同一URLへの再スキャンをキャッシュし、TTLの範囲内であれば再取得を避ける。
+// メモリ使用量を予測可能にするため、エントリ数と合計バイト数の両方に上限を設けたLRUで管理する。
+struct CacheEntry {
+    url: String,
+    fetched_at: i64,
+    ttl_secs: i64,
+    body: String,
+}
+
+#[derive(Default)]
+struct ResponseCache {
+    // 先頭が最も古く使われたもの、末尾が最も新しく使われたもの。
+    entries: Vec<CacheEntry>,
+}
+
+static mut RESPONSE_CACHE: *mut ResponseCache = std::ptr::null_mut::<ResponseCache>();
+
+fn response_cache() -> &'static mut ResponseCache {
+    unsafe {
+        if RESPONSE_CACHE.is_null() {
+            RESPONSE_CACHE = Box::leak(Box::new(ResponseCache::default()));
+        }
+        &mut *RESPONSE_CACHE
+    }
+}
+
+impl ResponseCache {
+    fn get(&mut self, url: &str, now: i64) -> Option<String> {
+        let idx = self.entries.iter().position(|e| e.url == url)?;
+        // ttl_secsはsnapshot = 'true'（fetch_sheet_body参照）やcache_ttl_secsに極端に大きな値を
+        // 指定された場合にi64::MAXになり得る。素直に加算するとfetched_atとの和がi64をオーバーフローし、
+        // リリースビルドでは無言でラップして負数になり「即座に期限切れ」という真逆の挙動になる
+        // （デバッグ/テストビルドではオーバーフローチェックでpanicする）。saturating_addで
+        // 「無期限」をi64::MAXに張り付かせることで、どちらのビルドでも意図通り期限切れ扱いにしない。
+        if self.entries[idx].fetched_at.saturating_add(self.entries[idx].ttl_secs) < now {
+            // TTL切れなので取り除く。
+            self.entries.remove(idx);
+            return None;
+        }
+        // LRUなので、末尾（最新扱い）へ移動する。
+        let entry = self.entries.remove(idx);
+        let body = entry.body.clone();
+        self.entries.push(entry);
+        Some(body)
+    }
+
+    fn put(&mut self, url: String, body: String, now: i64, ttl_secs: i64, max_entries: usize, max_bytes: usize) {
+        self.entries.retain(|e| e.url != url);
+        self.entries.push(CacheEntry {
+            url,
+            fetched_at: now,
+            ttl_secs,
+            body,
+        });
+        self.evict(max_entries, max_bytes);
+    }
+
+    // refresh = 'true' が明示された時にだけ呼び出す。TTLの経過を待たず、そのURLのエントリを
+    // 強制的に捨てて次回のget()を必ずキャッシュミスにする。
+    fn invalidate(&mut self, url: &str) {
+        self.entries.retain(|e| e.url != url);
+    }
+
+    fn evict(&mut self, max_entries: usize, max_bytes: usize) {
+        while self.entries.len() > max_entries {
+            self.entries.remove(0);
+        }
+        while self.total_bytes() > max_bytes && !self.entries.is_empty() {
+            self.entries.remove(0);
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.body.len()).sum()
+    }
+}
+
+// キャッシュを考慮しつつ、シートのレスポンス本文を取得する。
+// ttl_secsが0の場合はキャッシュを無効化し、常に再取得する。
+//
+// snapshot = 'true' の場合、ttl_secsの設定に関わらずキャッシュを実質無期限（i64::MAX）として
+// 扱い、以後同じURL（＝同じプッシュダウン結果）へのスキャンは同じ行を返し続ける。refresh = 'true'
+// が指定された時だけ、取得前にそのURLのエントリを明示的に無効化し、キャッシュを置き換える。
+// つまりsnapshotは「いつクエリしても同じ結果」、refreshは「今回だけは必ず取り直す」という
+// 独立した2つのテーブルオプションであり、両方trueなら「取り直した上でその結果をスナップショット
+// として固定する」という意味になる。
+fn fetch_sheet_body(
+    req: http::Request,
+    ttl_secs: i64,
+    max_entries: usize,
+    max_bytes: usize,
+    circuit_threshold: u32,
+    circuit_window_secs: u64,
+    circuit_cooldown_secs: u64,
+    circuit_max_entries: usize,
+    snapshot: bool,
+    refresh: bool,
+    query_params: &[(String, String)],
+) -> Result<String, FdwError> {
+    let url = append_query_params(&req.url, query_params);
+    circuit_breaker().check(&url, Duration::from_secs(circuit_cooldown_secs))?;
+    let now = time::epoch_secs();
+    let effective_ttl_secs = if snapshot { i64::MAX } else { ttl_secs };
+    if refresh {
+        response_cache().invalidate(&url);
+    }
+    if effective_ttl_secs > 0 {
+        if let Some(body) = response_cache().get(&url, now) {
+            stats().cache_hits += 1;
+            return Ok(body);
+        }
+        stats().cache_misses += 1;
+    }
+    match get_following_redirects(req, query_params) {
+        Ok(resp) => {
+            circuit_breaker().record_success(&url);
+            let body = resp.body;
+            stats().bytes_fetched += body.len() as i64;
+            if effective_ttl_secs > 0 {
+                response_cache().put(url, body.clone(), now, effective_ttl_secs, max_entries, max_bytes);
+            }
+            Ok(body)
+        }
+        Err(e) => {
+            circuit_breaker().record_failure(&url, circuit_threshold, Duration::from_secs(circuit_window_secs), circuit_max_entries);
+            Err(e)
+        }
+    }
+}
+
+// ⭐️ここまでレスポンスキャッシュ（TTL付きLRU）
+
+// ⭐️ここから 恒常的に失敗しているシートへの無駄なリクエストを避けるサーキットブレーカー
+
+// あるURLへのリクエストがwindow秒以内にthreshold回失敗したら、以降cooldown秒が経過するまで
+// そのURLへのリクエストを試みずに「circuit open」エラーで即座に失敗させる。認証取り消し/削除
+// されたシートに対して、クエリのたびにリトライ予算を丸ごと浪費するのを防ぐための仕組み。
+struct CircuitEntry {
+    url: String,
+    failure_count: u32,
+    window_started_at: SystemTime,
+    opened_at: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct CircuitBreaker {
+    entries: Vec<CircuitEntry>,
+}
+
+static mut CIRCUIT_BREAKER: *mut CircuitBreaker = std::ptr::null_mut::<CircuitBreaker>();
+
+fn circuit_breaker() -> &'static mut CircuitBreaker {
+    unsafe {
+        if CIRCUIT_BREAKER.is_null() {
+            CIRCUIT_BREAKER = Box::leak(Box::new(CircuitBreaker::default()));
+        }
+        &mut *CIRCUIT_BREAKER
+    }
+}
+
+impl CircuitBreaker {
+    // circuitがクールダウン中に開いていれば、その旨のエラーを返す。
+    fn check(&self, url: &str, cooldown: Duration) -> Result<(), FdwError> {
+        let entry = match self.entries.iter().find(|e| e.url == url) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let opened_at = match entry.opened_at {
+            Some(opened_at) => opened_at,
+            None => return Ok(()),
+        };
+        let elapsed = SystemTime::now().duration_since(opened_at).unwrap_or_default();
+        if elapsed < cooldown {
+            return Err(format!(
+                "circuit open for '{}': too many recent failures, cooling down for {}s more",
+                url,
+                (cooldown - elapsed).as_secs()
+            ));
+        }
+        Ok(())
+    }
+
+    fn record_success(&mut self, url: &str) {
+        self.entries.retain(|e| e.url != url);
+    }
+
+    fn record_failure(&mut self, url: &str, threshold: u32, window: Duration, max_entries: usize) {
+        let now = SystemTime::now();
+        if let Some(idx) = self.entries.iter().position(|e| e.url == url) {
+            let entry = &mut self.entries[idx];
+            if now.duration_since(entry.window_started_at).unwrap_or_default() > window {
+                // ウィンドウが過ぎていたのでカウントをリセットする。
+                entry.failure_count = 0;
+                entry.window_started_at = now;
+            }
+            entry.failure_count += 1;
+            if entry.failure_count >= threshold {
+                entry.opened_at = Some(now);
+            }
+            // 最近失敗した順にLRUの末尾へ動かす。evictが最初に捨てるのは常に先頭（最も長く
+            // 動きが無かったURL）にするため。
+            let entry = self.entries.remove(idx);
+            self.entries.push(entry);
+        } else {
+            let failure_count = 1;
+            self.entries.push(CircuitEntry {
+                url: url.to_owned(),
+                failure_count,
+                window_started_at: now,
+                opened_at: if failure_count >= threshold { Some(now) } else { None },
+            });
+        }
+        self.evict(max_entries);
+    }
+
+    // ResponseCacheと同様、エントリ数に上限を設けたLRUで管理する。cooldownを過ぎて既に閉じている
+    // エントリだけを狙って間引く仕組みは持たず、単純に最も長く失敗も成功も記録していないURLから
+    // 捨てる（間欠的に失敗するURLや、一度きり問い合わせて以後二度と来ないURLが、クリーンな
+    // successを挟まない限り無制限に溜まり続けるのを防ぐのが目的のため、開いているかどうかは問わない）。
+    fn evict(&mut self, max_entries: usize) {
+        while self.entries.len() > max_entries {
+            self.entries.remove(0);
+        }
+    }
+}
+
+// ⭐️ここまで サーキットブレーカー
+
+// ⭐️ここから scan_deadline_ms による、begin_scan全体（リトライ・ページング含む）の累積時間の上限
+
+// SystemTimeベースで、begin_scan開始時刻からの累積経過時間を追跡する。CIRCUIT_BREAKER/
+// RESPONSE_CACHEと同じシングルトンパターンを使い、各ネットワークI/Oの呼び出し経路ごとに
+// 締め切りを引数で引き回さずに済むようにする。deadline_ms = 0は無効（上限なし）を表す。
+struct ScanDeadline {
+    started_at: SystemTime,
+    deadline_ms: u64,
+}
+
+static mut SCAN_DEADLINE: *mut ScanDeadline = std::ptr::null_mut::<ScanDeadline>();
+
+fn scan_deadline() -> &'static mut ScanDeadline {
+    unsafe {
+        if SCAN_DEADLINE.is_null() {
+            SCAN_DEADLINE = Box::leak(Box::new(ScanDeadline {
+                started_at: SystemTime::now(),
+                deadline_ms: 0,
+            }));
+        }
+        &mut *SCAN_DEADLINE
+    }
+}
+
+// begin_scanの先頭で呼び出し、このスキャンの開始時刻と上限（scan_deadline_msテーブルオプション）を記録する。
+fn start_scan_deadline(deadline_ms: u64) {
+    let d = scan_deadline();
+    d.started_at = SystemTime::now();
+    d.deadline_ms = deadline_ms;
+}
+
+// ネットワークI/Oを伴う処理（HTTPリクエスト・v4ページング等）の直前に呼び出し、
+// scan_deadline_msが設定されていて既に超過していれば即座にエラーで打ち切る。
+fn check_scan_deadline() -> Result<(), FdwError> {
+    let d = scan_deadline();
+    if d.deadline_ms == 0 {
+        return Ok(());
+    }
+    let elapsed_ms = SystemTime::now().duration_since(d.started_at).unwrap_or_default().as_millis() as u64;
+    if elapsed_ms > d.deadline_ms {
+        return Err(format!(
+            "scan exceeded deadline of {}ms (elapsed {}ms)",
+            d.deadline_ms, elapsed_ms
+        ));
+    }
+    Ok(())
+}
+
+// ⭐️ここまで scan_deadline_ms による、begin_scan全体（リトライ・ページング含む）の累積時間の上限
+
+// ⭐️ここから introspect = 'stats' による運用統計カウンタ
+
+// begin_scanの呼び出し回数、レスポンスキャッシュのヒット/ミス、gviz/v4認証のリトライ回数、
+// 取得したレスポンス本文の総バイト数を、WASMインスタンスの生存期間中（＝個々のbegin_scan/
+// end_scanを跨いで）蓄積するシングルトン。introspect = 'stats' で1行のテーブルとして読み出せる、
+// ログを漁らなくても済むようにするための運用可視化用カウンタ。
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    total_scans: i64,
+    cache_hits: i64,
+    cache_misses: i64,
+    retries: i64,
+    bytes_fetched: i64,
+}
+
+static mut STATS: *mut Stats = std::ptr::null_mut::<Stats>();
+
+fn stats() -> &'static mut Stats {
+    unsafe {
+        if STATS.is_null() {
+            STATS = Box::leak(Box::new(Stats::default()));
+        }
+        &mut *STATS
+    }
+}
+
+// ⭐️ここまで introspect = 'stats' による運用統計カウンタ
+
+// テスト時にのみ、実際のhttp::get/http::postホスト呼び出しを差し替えるためのフック。
+// 本番ビルド（cfg(test)が付かない通常のコンポーネントビルド）では常にホスト実装へそのまま委譲する。
+#[cfg(test)]
+type HttpOverride = Box<dyn Fn(&http::Request) -> http::HttpResult>;
+
+#[cfg(test)]
+static mut HTTP_GET_OVERRIDE: Option<HttpOverride> = None;
+#[cfg(test)]
+static mut HTTP_POST_OVERRIDE: Option<HttpOverride> = None;
+
+#[cfg(test)]
+fn set_http_get_override(f: HttpOverride) {
+    unsafe { HTTP_GET_OVERRIDE = Some(f) };
+}
+#[cfg(test)]
+fn set_http_post_override(f: HttpOverride) {
+    unsafe { HTTP_POST_OVERRIDE = Some(f) };
+}
+
+fn http_get(req: &http::Request) -> http::HttpResult {
+    #[cfg(test)]
+    {
+        if let Some(f) = unsafe { HTTP_GET_OVERRIDE.as_ref() } {
+            return f(req);
+        }
+    }
+    http::get(req)
+}
+
+fn http_post(req: &http::Request) -> http::HttpResult {
+    #[cfg(test)]
+    {
+        if let Some(f) = unsafe { HTTP_POST_OVERRIDE.as_ref() } {
+            return f(req);
+        }
+    }
+    http::post(req)
+}
+
+// テスト時にのみ、utils::report_warning/report_infoホスト呼び出しを差し替えるためのフック。
+// http_get/http_postと同じ理由（utils::report_*はWITホストインポートで、実ホストが無い環境では
+// リンク/実行できない）で必要になる。差し替えが無ければ通常通りホスト実装へ委譲する。
+#[cfg(test)]
+type ReportOverride = Box<dyn Fn(&str)>;
+
+#[cfg(test)]
+static mut REPORT_WARNING_OVERRIDE: Option<ReportOverride> = None;
+#[cfg(test)]
+static mut REPORT_INFO_OVERRIDE: Option<ReportOverride> = None;
+
+#[cfg(test)]
+fn set_report_warning_override(f: ReportOverride) {
+    unsafe { REPORT_WARNING_OVERRIDE = Some(f) };
+}
+#[cfg(test)]
+fn set_report_info_override(f: ReportOverride) {
+    unsafe { REPORT_INFO_OVERRIDE = Some(f) };
+}
+
+fn report_warning(msg: &str) {
+    #[cfg(test)]
+    {
+        if let Some(f) = unsafe { REPORT_WARNING_OVERRIDE.as_ref() } {
+            return f(msg);
+        }
+    }
+    utils::report_warning(msg);
+}
+
+fn report_info(msg: &str) {
+    #[cfg(test)]
+    {
+        if let Some(f) = unsafe { REPORT_INFO_OVERRIDE.as_ref() } {
+            return f(msg);
+        }
+    }
+    utils::report_info(msg);
+}
+
+// テスト時にのみ、time::parse_from_rfc3339ホスト呼び出しを差し替えるためのフック。
+// http_get/http_postと同じ理由（time::parse_from_rfc3339もWITホストインポートで、実ホストが
+// 無い環境ではリンク/実行できない）で必要になる。差し替えが無ければ通常通りホスト実装へ委譲する。
+#[cfg(test)]
+type TimeParseOverride = Box<dyn Fn(&str) -> Result<i64, String>>;
+
+#[cfg(test)]
+static mut TIME_PARSE_FROM_RFC3339_OVERRIDE: Option<TimeParseOverride> = None;
+
+#[cfg(test)]
+fn set_time_parse_from_rfc3339_override(f: TimeParseOverride) {
+    unsafe { TIME_PARSE_FROM_RFC3339_OVERRIDE = Some(f) };
+}
+
+fn time_parse_from_rfc3339(s: &str) -> Result<i64, String> {
+    #[cfg(test)]
+    {
+        if let Some(f) = unsafe { TIME_PARSE_FROM_RFC3339_OVERRIDE.as_ref() } {
+            return f(s);
+        }
+    }
+    time::parse_from_rfc3339(s.to_owned())
+}
+
+// 公開/共有スプレッドシートのURLは、同意画面や正規URLへの302リダイレクトを返すことがある。
+// httpホストバインディングがリダイレクトを自動で追わない場合に備え、3xxステータスと`Location`ヘッダーを見て
+// 手動でGETをやり直す。無限ループを避けるため追跡回数には上限を設ける。
+const MAX_REDIRECTS: u8 = 5;
+// collect_errors = 'true' のとき記録するセル単位の変換エラーの件数上限。
+const MAX_COLLECTED_CELL_ERRORS: usize = 500;
+// tq句を含む最終URLの長さの上限（バイト数）。多くのHTTPサーバー/プロキシが8KB前後で
+// リクエストラインを拒否するため、それより十分小さい値を安全側のデフォルトとして選んでいる。
+// max_pushed_qualsで件数を絞ってもなお超過する場合の二段目のガードとして使う。
+const MAX_GVIZ_TQ_URL_LEN: usize = 6000;
+
+// ⭐️ここから transport_max_retries / status_max_retries による接続層エラー/HTTPステータス
+// エラーの個別リトライ設定
+
+// DNS解決失敗やTLSハンドシェイク失敗のような接続層（トランスポート）のエラーと、HTTPステータス
+// エラー（5xx）とで別々にリトライ回数を設定できるようにする。ホストのHttpErrorは構造化されて
+// おらず単なる文字列なので、分類はエラーメッセージに含まれるキーワードへのマッチングで行う
+// （いずれのキーワードにも一致しなければステータスエラー寄りとして扱う）。SCAN_DEADLINEと同じ
+// シングルトンパターンを使い、get_following_redirects以下のネットワークI/O経路全体に
+// このポリシーを引数で引き回さずに済むようにする。
+struct RetryPolicy {
+    transport_max_retries: u32,
+    status_max_retries: u32,
+    // total_retry_budget（既定0=無制限）と、そのうち既に消費した分。ページネーションや
+    // 複数シートにまたがる取得で個々のリトライ上限が積み重なり、最悪ケースのリクエスト数が
+    // 膨れ上がるのを防ぐための、begin_scan単位で共有されるクロスリクエストの上限。
+    total_retry_budget: u32,
+    budget_used: u32,
+}
+
+static mut RETRY_POLICY: *mut RetryPolicy = std::ptr::null_mut::<RetryPolicy>();
+
+fn retry_policy() -> &'static mut RetryPolicy {
+    unsafe {
+        if RETRY_POLICY.is_null() {
+            RETRY_POLICY = Box::leak(Box::new(RetryPolicy {
+                transport_max_retries: 0,
+                status_max_retries: 0,
+                total_retry_budget: 0,
+                budget_used: 0,
+            }));
+        }
+        &mut *RETRY_POLICY
+    }
+}
+
+// begin_scanの先頭で呼び出し、transport_max_retries/status_max_retries/total_retry_budget
+// テーブルオプションの値を記録する。budget_usedはスキャンごとにリセットする。
+fn start_retry_policy(transport_max_retries: u32, status_max_retries: u32, total_retry_budget: u32) {
+    let p = retry_policy();
+    p.transport_max_retries = transport_max_retries;
+    p.status_max_retries = status_max_retries;
+    p.total_retry_budget = total_retry_budget;
+    p.budget_used = 0;
+}
+
+impl RetryPolicy {
+    // total_retry_budgetが0（無制限）でなければ、1回分を消費して残り予算を返す。
+    // 予算を使い切っている場合はNoneを返し、呼び出し側はそれ以上リトライせず即座に諦める。
+    fn try_consume_budget(&mut self) -> Option<Option<u32>> {
+        if self.total_retry_budget == 0 {
+            return Some(None);
+        }
+        if self.budget_used >= self.total_retry_budget {
+            return None;
+        }
+        self.budget_used += 1;
+        Some(Some(self.total_retry_budget - self.budget_used))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HttpErrorKind {
+    Transport,
+    Status,
+}
+
+const TRANSPORT_ERROR_KEYWORDS: &[&str] = &[
+    "dns",
+    "resolve",
+    "resolution",
+    "tls",
+    "ssl",
+    "certificate",
+    "handshake",
+    "connection refused",
+    "connection reset",
+    "connect error",
+    "could not connect",
+    "timed out",
+    "timeout",
+    "network is unreachable",
+];
+
+fn classify_http_error(e: &str) -> HttpErrorKind {
+    let lower = e.to_lowercase();
+    if TRANSPORT_ERROR_KEYWORDS.iter().any(|k| lower.contains(k)) {
+        HttpErrorKind::Transport
+    } else {
+        HttpErrorKind::Status
+    }
+}
+
+// try_consume_budgetの戻り値をverboseログの末尾に付け足す断片を組み立てる。total_retry_budgetが
+// 無制限（0）の場合はNone(内側)が渡ってくるので何も表示しない。
+fn format_remaining_budget(remaining: Option<u32>) -> String {
+    match remaining {
+        Some(n) => format!("; {} of total_retry_budget remaining", n),
+        None => String::new(),
+    }
+}
+
+// http_get（とそれが返す5xxステータス）を、transport_max_retries/status_max_retriesに従って
+// リトライしながら呼び出す。最終的に失敗した場合は、どちらに分類して何回リトライした末に
+// 失敗したのかをエラーメッセージの先頭に含める（[transport]/[status]）。
+fn fetch_with_classified_retries(req: &http::Request) -> Result<http::Response, FdwError> {
+    let mut transport_attempts = 0u32;
+    let mut status_attempts = 0u32;
+    loop {
+        check_scan_deadline()?;
+        match http_get(req) {
+            Ok(resp) if resp.status_code >= 500 => {
+                if status_attempts < retry_policy().status_max_retries {
+                    match retry_policy().try_consume_budget() {
+                        Some(remaining) => {
+                            status_attempts += 1;
+                            stats().retries += 1;
+                            report_warning(&format!(
+                                "[status] http status error {} (attempt {}/{}); retrying{}",
+                                resp.status_code,
+                                status_attempts,
+                                retry_policy().status_max_retries,
+                                format_remaining_budget(remaining)
+                            ));
+                            continue;
+                        }
+                        None => {
+                            return Err(format!(
+                                "[status] http status error {} after {} retry attempt(s); total_retry_budget exhausted",
+                                resp.status_code, status_attempts
+                            ));
+                        }
+                    }
+                }
+                return Err(format!(
+                    "[status] http status error {} after {} retry attempt(s)",
+                    resp.status_code, status_attempts
+                ));
+            }
+            Ok(resp) => return Ok(resp),
+            Err(e) => {
+                let kind = classify_http_error(&e);
+                let (attempts, max_retries) = match kind {
+                    HttpErrorKind::Transport => (&mut transport_attempts, retry_policy().transport_max_retries),
+                    HttpErrorKind::Status => (&mut status_attempts, retry_policy().status_max_retries),
+                };
+                if *attempts < max_retries {
+                    let kind_label = if kind == HttpErrorKind::Transport { "transport" } else { "status" };
+                    match retry_policy().try_consume_budget() {
+                        Some(remaining) => {
+                            *attempts += 1;
+                            stats().retries += 1;
+                            report_warning(&format!(
+                                "[{}] {} (attempt {}/{}); retrying{}",
+                                kind_label,
+                                e,
+                                *attempts,
+                                max_retries,
+                                format_remaining_budget(remaining)
+                            ));
+                            continue;
+                        }
+                        None => {
+                            return Err(format!(
+                                "[{}] {} after {} retry attempt(s); total_retry_budget exhausted",
+                                kind_label, e, *attempts
+                            ));
+                        }
+                    }
+                }
+                return Err(format!(
+                    "[{}] {} after {} retry attempt(s)",
+                    if kind == HttpErrorKind::Transport { "transport" } else { "status" },
+                    e,
+                    *attempts
+                ));
+            }
+        }
+    }
+}
+
+// ⭐️ここまで transport_max_retries / status_max_retries
+
+fn get_following_redirects(mut req: http::Request, query_params: &[(String, String)]) -> Result<http::Response, FdwError> {
+    req.url = append_query_params(&req.url, query_params);
+    for _ in 0..=MAX_REDIRECTS {
+        check_scan_deadline()?;
+        let resp = fetch_with_classified_retries(&req)?;
+        if !(300..400).contains(&resp.status_code) {
+            return Ok(resp);
+        }
+        let location = resp
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("location"))
+            .map(|(_, v)| v.clone())
+            .ok_or_else(|| {
+                format!(
+                    "received redirect status {} without a Location header",
+                    resp.status_code
+                )
+            })?;
+        req.url = location;
+    }
+    Err(format!(
+        "too many redirects (limit {}) while fetching the sheet",
+        MAX_REDIRECTS
+    ))
+}
+
+// ⭐️ここから 1つのspread_sheet_id分のgvizレスポンス取得（spread_sheet_idsでの複数ファイル読み込みで再利用する）
+
+// base_url に {id} / {gid} のプレースホルダーが含まれる場合、プロキシ経由でGoogleを叩く等
+// "{base_url}/{id}/..." という既定の構造に合わない非標準のURLレイアウトに対応するため、
+// base_url自体を任意のテンプレートとして扱い、プレースホルダーの文字列置換のみでURLを
+// 組み立てる。プレースホルダーを含まない場合は、呼び出し元ごとのdefault_template（既定の
+// "{base_url}/{id}/..."構造）を使う、つまり従来通りの挙動になる。{gid}はsheet_id未指定時は
+// 使えないため、テンプレートに含まれているのにsheet_idが無い場合はエラーにする。
+fn resolve_templated_url(
+    base_url: &str,
+    spread_sheet_id: &str,
+    sheet_id: Option<&str>,
+    default_template_with_gid: &str,
+    default_template_without_gid: &str,
+) -> Result<String, FdwError> {
+    let template: &str = if base_url.contains('{') {
+        if !base_url.contains("{id}") {
+            return Err("base_url template must contain the {id} placeholder".to_owned());
+        }
+        if base_url.contains("{gid}") && sheet_id.is_none() {
+            return Err("base_url template uses {gid} but no sheet_id option was provided".to_owned());
+        }
+        base_url
+    } else if sheet_id.is_some() {
+        default_template_with_gid
+    } else {
+        default_template_without_gid
+    };
+    Ok(template
+        .replace("{base_url}", base_url)
+        .replace("{id}", spread_sheet_id)
+        .replace("{gid}", sheet_id.unwrap_or_default()))
+}
+
+// 1つのspread_sheet_idに対して、tqプッシュダウンつきURLの組み立て・キャッシュ経由での取得・
+// gvizエラー時のtq無しリトライまでを行い、解決済みのレスポンスJSONを返す。
+// spread_sheet_ids（複数ファイル）指定時は、この関数をID毎に呼び出して結果を連結する。
+fn fetch_gviz_resp_json(
+    base_url: &str,
+    gviz_path: &str,
+    spread_sheet_id: &str,
+    sheet_id: Option<&str>,
+    gviz_headers: Option<u32>,
+    modified_column: Option<&str>,
+    ctx: &Context,
+    headers_public: &[(String, String)],
+    headers_token: &[(String, String)],
+    auth_mode: &str,
+    jwt_header: Option<(String, String)>,
+    api_key: Option<&str>,
+    cache_ttl_secs: i64,
+    cache_max_entries: usize,
+    cache_max_bytes: usize,
+    circuit_threshold: u32,
+    circuit_window_secs: u64,
+    circuit_cooldown_secs: u64,
+    circuit_max_entries: usize,
+    verbose: bool,
+    explain_url: bool,
+    schema_only: bool,
+    pushdown_limit: bool,
+    pushdown_order_by: bool,
+    column_order: &[usize],
+    pushdown_column_ref_mode: &str,
+    max_pushed_quals: usize,
+    snapshot: bool,
+    refresh: bool,
+    query_params: &[(String, String)],
+) -> Result<JsonValue, FdwError> {
+    let url = resolve_templated_url(
+        base_url,
+        spread_sheet_id,
+        sheet_id,
+        &format!("{{base_url}}/{{id}}/{}?gid={{gid}}&tqx=out:json", gviz_path),
+        &format!("{{base_url}}/{{id}}/{}?tqx=out:json", gviz_path),
+    )?;
+    // gviz_headers = N を、gvizの"headers"パラメータとしてそのまま渡す。gviz自身に先頭N行を
+    // ヘッダーとして解釈させ、cols（ラベル）をそこから組み立て直させることで、skip_rowsのような
+    // クライアント側の行スキップより自然なラベル検出（source_columnマッピングの精度向上）が
+    // できるようにする。なお本FDWにはheader_rowオプションは存在しない（ヘッダー行自体をデータとして
+    // 使わないのはgviz_headers、取得済みsrc_rowsから先頭N行を捨てるのはskip_rowsの役割）。
+    // gviz_headersとskip_rowsは別レイヤー（gvizの解釈 vs クライアント側の後処理）の機能であり、
+    // 両方を指定すると二重にヘッダー行を読み飛ばすことになるため、通常はどちらか一方だけを使うこと。
+    let url = match gviz_headers {
+        Some(n) => format!("{}&headers={}", url, n),
+        None => url,
+    };
+
+    // modified_columnが指定されていて、かつ対象カラムに ">" のqualが渡ってきていれば増分スキャン用の
+    // 条件を、真偽値列に対する "=" のqualが渡ってきていればブールリテラルでの絞り込み条件を、
+    // LIKE/ILIKEのqualが単純なワイルドカード形状（%x%, x%, %x）であればcontains/starts with/
+    // ends withの条件を、それぞれ組み立てて "and" で連結し、tqパラメータとしてgvizへプッシュダウンする。
+    // url_without_tq は、プッシュダウンが原因のgvizエラーを起こした場合のフォールバック先として保持しておく。
+    let url_without_tq = url.clone();
+    let mut conditions = Vec::new();
+    if let Some(col) = modified_column {
+        if let Some(cond) =
+            build_modified_since_clause(ctx, col, column_order, pushdown_column_ref_mode)?
+        {
+            conditions.push(cond);
+        }
+    }
+    if let Some(cond) = build_bool_equality_clause(ctx, column_order, pushdown_column_ref_mode)? {
+        conditions.push(cond);
+    }
+    if let Some(cond) = build_like_clause(ctx, column_order, pushdown_column_ref_mode)? {
+        conditions.push(cond);
+    }
+    // max_pushed_qualsを超える分のqualは、tq句に足すと巨大なwhere句になりURL長超過を
+    // 招きかねないため、先頭max_pushed_quals件だけを採用し残りはpostgres側のローカル評価へ
+    // フォールバックさせる（report_pushdown_coverageの「pushed to gviz」判定はこの打ち切りを考慮しない）。
+    if conditions.len() > max_pushed_quals {
+        if verbose {
+            report_info(&format!(
+                "max_pushed_quals: {} qual(s) matched pushdown conditions but only {} were pushed; the rest fall back to local evaluation",
+                conditions.len(),
+                max_pushed_quals
+            ));
+        }
+        conditions.truncate(max_pushed_quals);
+    }
+    // schema_only = true（introspect = 'columns'/'meta'）の場合、返す行データは捨てて
+    // colsメタデータ（型/labelの推論元）しか見ないため、"limit 1"をtq句に足して巨大シートでも
+    // 軽量にスキーマを取得できるようにする。既存のwhere句プッシュダウンがあればそれに相乗りする。
+    // pushdown_limit = 'true'の場合、schema_onlyでなければプランナーのLIMITヒント（ctx.get_limit）を
+    // 同じtq句へ"limit N [offset M]"として足す。JOIN等でプランナーのLIMITが実際に必要な行数と
+    // 一致しない場合に取得不足を起こし得るためopt-in（begin_scan側のpushdown_limitオプション参照）。
+    let limit_clause = if pushdown_limit { build_limit_clause(ctx) } else { None };
+    // pushdown_order_by = 'true'かつschema_onlyでない場合のみ、プランナーが要求したソート順を
+    // order by句として足す。schema_onlyは"limit 1"しか見ないため並べ替えても意味が無い。
+    let order_by_clause = if pushdown_order_by && !schema_only {
+        build_order_by_clause(ctx, column_order, pushdown_column_ref_mode)
+    } else {
+        None
+    };
+    let url = if conditions.is_empty() && !schema_only && limit_clause.is_none() && order_by_clause.is_none() {
+        url
+    } else {
+        // tq句にはスペースや引用符が含まれるため、GETパラメータとして安全な形にエンコードする。
+        // gvizクエリ言語の句の並びはwhere -> order by -> limit [offset] の順でなければならない。
+        let mut clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("where {}", conditions.join(" and "))
+        };
+        if let Some(order_by_clause) = &order_by_clause {
+            if !clause.is_empty() {
+                clause.push(' ');
+            }
+            clause.push_str(order_by_clause);
+        }
+        if schema_only {
+            if !clause.is_empty() {
+                clause.push(' ');
+            }
+            clause.push_str("limit 1");
+        } else if let Some(limit_clause) = &limit_clause {
+            if !clause.is_empty() {
+                clause.push(' ');
+            }
+            clause.push_str(limit_clause);
+        }
+        let candidate = format!("{}&tq={}", url, gviz_url_encode(&clause));
+        // max_pushed_qualsによる件数の絞り込みをすり抜けるほど個々のqualのリテラルが長い場合に
+        // 備えた二段目のガード。それでもURLが長すぎるならwhere句（=conditions由来の部分）だけを
+        // 諦め、order by/limit/schema_onlyの断片は維持したままローカル評価へフォールバックする。
+        if candidate.len() > MAX_GVIZ_TQ_URL_LEN && !conditions.is_empty() {
+            if verbose {
+                report_info(&format!(
+                    "max_pushed_quals: pushed-down URL length {} exceeds the {}-byte limit; dropping the where clause and falling back to local evaluation",
+                    candidate.len(),
+                    MAX_GVIZ_TQ_URL_LEN
+                ));
+            }
+            let mut fallback_clause = order_by_clause.clone().unwrap_or_default();
+            let tail = if schema_only { Some("limit 1") } else { limit_clause.as_deref() };
+            if let Some(tail) = tail {
+                if !fallback_clause.is_empty() {
+                    fallback_clause.push(' ');
+                }
+                fallback_clause.push_str(tail);
+            }
+            if fallback_clause.is_empty() {
+                url
+            } else {
+                format!("{}&tq={}", url, gviz_url_encode(&fallback_clause))
+            }
+        } else {
+            candidate
+        }
+    };
+    let used_tq_pushdown = url != url_without_tq;
+
+    // explain_url = 'true' なら組み立てた最終URL（tqを含む）を、verbose = 'true' なら
+    // 各qualのプッシュダウン可否一覧を、それぞれURL確定直後に一度だけreport_infoで出力する。
+    if explain_url {
+        report_info(&format!("explain_url: {}", url));
+    }
+    if verbose {
+        report_pushdown_coverage(ctx, modified_column);
+    }
+
+    let candidates = build_auth_candidates(&url, auth_mode, headers_public, headers_token, jwt_header, api_key)?;
+    // fetch_gviz_body_with_auth_fallbackが返すURLは捨てる: auth_mode = 'api_key'の候補は
+    // `&key=...`をURLに埋め込んでおり、これをexplain_urlの出力に使うと認証情報が
+    // そのままログに漏れてしまうため、explain_urlは常にフォールバック前のURLのみを報告する。
+    let (body, auth_strategy, _url) = fetch_gviz_body_with_auth_fallback(
+        &candidates,
+        cache_ttl_secs,
+        cache_max_entries,
+        cache_max_bytes,
+        circuit_threshold,
+        circuit_window_secs,
+        circuit_cooldown_secs,
+        circuit_max_entries,
+        verbose,
+        snapshot,
+        refresh,
+        query_params,
+    )?;
+    let headers = candidates
+        .iter()
+        .find(|(name, _, _)| *name == auth_strategy)
+        .map(|(_, _, h)| h.clone())
+        .unwrap_or_default();
+    let resp_json: JsonValue = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+
+    // gvizは不正なtqクエリをHTTPエラーではなくJSON内の status == "error" として返してくる。
+    // プッシュダウンが原因と思われる場合は、tq句を外してローズカルフィルタ相当（フィルタなし全件取得）に
+    // 一度だけフォールバックし、それ以外のエラーはgvizの理由をそのまま表面化する。
+    if resp_json.get("status").and_then(|v| v.as_str()) == Some("error") {
+        let reason = resp_json
+            .pointer("/errors/0/detail")
+            .or_else(|| resp_json.pointer("/errors/0/message"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown gviz error")
+            .to_owned();
+        if used_tq_pushdown {
+            report_info(&format!(
+                "gviz rejected the pushed-down query ({}); retrying without tq",
+                reason
+            ));
+            stats().retries += 1;
+            let retry_url = if auth_strategy == "api_key" {
+                format!("{}&key={}", url_without_tq, gviz_url_encode(api_key.unwrap_or_default()))
+            } else {
+                url_without_tq
+            };
+            let retry_req = http::Request {
+                method: http::Method::Get,
+                url: retry_url,
+                headers: headers.to_vec(),
+                body: String::default(),
+            };
+            let retry_body = fetch_sheet_body(
+                retry_req,
+                cache_ttl_secs,
+                cache_max_entries,
+                cache_max_bytes,
+                circuit_threshold,
+                circuit_window_secs,
+                circuit_cooldown_secs,
+                circuit_max_entries,
+                snapshot,
+                refresh,
+                query_params,
+            )?;
+            let retry_body = strip_gviz_prefix(&retry_body)?;
+            let retry_json: JsonValue =
+                serde_json::from_str(retry_body).map_err(|e| e.to_string())?;
+            if retry_json.get("status").and_then(|v| v.as_str()) == Some("error") {
+                return Err(format!("gviz error even without pushdown: {}", reason));
+            }
+            Ok(retry_json)
+        } else {
+            Err(format!("gviz returned an error: {}", reason))
+        }
+    } else {
+        Ok(resp_json)
+    }
+}
+
+// ⭐️ここまで 1つのspread_sheet_id分のgvizレスポンス取得
+
+// gvizのセル(`v`)を、型を問わずキー/値表現に使える文字列に変換する。文字列以外はf64を経由して文字列化する。
+fn gviz_cell_as_string(cell: &JsonValue) -> Option<String> {
+    if let Some(s) = cell.as_str() {
+        Some(s.to_owned())
+    } else {
+        cell.as_f64().map(format_f64_without_scientific_notation)
+    }
+}
+
+// f64を指数表記を使わずに文字列化する。長いID等をnumber_as_text_columns経由で扱う際の最後の手段として使う
+// （gvizが"f"の表示用文字列を返している場合はそちらを優先するため、ここに来るのは稀）。
+fn format_f64_without_scientific_notation(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{}", v)
+    }
+}
+
+// ⭐️ここから pattern/localeに基づく通貨・桁区切り付き数値のfステート正規化
+
+// gvizのpattern（例: "$#,##0.00" や "#.##0,00"）から、桁区切りに"."を小数点に","を使う
+// ヨーロッパ式かどうかを判定する。patternが無い場合はlocaleにフォールバックする。
+fn is_european_number_style(pattern: Option<&str>, locale: &str) -> bool {
+    if let Some(p) = pattern {
+        if let (Some(comma_idx), Some(dot_idx)) = (p.rfind(','), p.rfind('.')) {
+            return comma_idx > dot_idx;
+        }
+    }
+    matches!(
+        locale,
+        "de" | "de-DE" | "fr" | "fr-FR" | "es" | "es-ES" | "it" | "it-IT" | "pt" | "pt-PT" | "nl" | "nl-NL"
+    )
+}
+
+// strip_prefix/strip_suffix列オプションで指定された接頭辞/接尾辞を、実際にその位置にある場合のみ
+// 取り除く。一致しない値はそのまま通す（決め打ちのtrimとは違い、別の文字列を誤って壊さない）。
+fn apply_strip_affixes(
+    value: &str,
+    tgt_col_name: &str,
+    strip_prefix_columns: &[(String, String)],
+    strip_suffix_columns: &[(String, String)],
+) -> String {
+    let mut s = value.to_owned();
+    if let Some((_, prefix)) = strip_prefix_columns.iter().find(|(c, _)| c == tgt_col_name) {
+        if let Some(stripped) = s.strip_prefix(prefix.as_str()) {
+            s = stripped.to_owned();
+        }
+    }
+    if let Some((_, suffix)) = strip_suffix_columns.iter().find(|(c, _)| c == tgt_col_name) {
+        if let Some(stripped) = s.strip_suffix(suffix.as_str()) {
+            s = stripped.to_owned();
+        }
+    }
+    s
+}
+
+// strip_leading_apostrophe = 'true' の場合に、gvizが「テキストとして強制」した先頭の単一の
+// アポストロフィ（例: '007）を取り除く。無効時、またはアポストロフィが無い値には何もしない
+// （決め打ちのtrimではなく、実際にその位置にある場合のみ取り除く点はapply_strip_affixesと同じ）。
+fn strip_leading_apostrophe(value: &str, enabled: bool) -> String {
+    if enabled {
+        value.strip_prefix('\'').unwrap_or(value).to_owned()
+    } else {
+        value.to_owned()
+    }
+}
+
+// gvizの表示用文字列（"f"）から通貨記号や桁区切りを取り除き、Rustの`f64::parse`が
+// 受け付ける形（小数点は"."）に正規化する。patternが無ければlocaleオプションで判断する。
+fn strip_currency_and_separators(formatted: &str, pattern: Option<&str>, locale: &str) -> String {
+    let european = is_european_number_style(pattern, locale);
+    let mut out = String::with_capacity(formatted.len());
+    for c in formatted.chars() {
+        match c {
+            '0'..='9' | '-' => out.push(c),
+            ',' if european => out.push('.'),
+            '.' if !european => out.push('.'),
+            _ => {} // 通貨記号・桁区切り・空白などは読み捨てる
+        }
+    }
+    out
+}
+
+// 桁区切り付きの整数表示文字列（例 "1,234,567"）をstrip_currency_and_separatorsで正規化した上で
+// bigintとしてパースする。正規化後に小数部が残っていれば（例 "1,234.50"）、暗黙の切り捨てを
+// 避けるためエラーにする。
+fn parse_grouped_integer(formatted: &str, pattern: Option<&str>, locale: &str, tgt_col_name: &str) -> Result<i64, FdwError> {
+    let normalized = strip_currency_and_separators(formatted, pattern, locale);
+    let value: f64 = normalized
+        .parse()
+        .map_err(|_| format!("column '{}': cannot parse '{}' as an integer", tgt_col_name, formatted))?;
+    if value.fract() != 0.0 {
+        return Err(format!(
+            "column '{}': value '{}' has a fractional part and cannot be read as an integer",
+            tgt_col_name, formatted
+        ));
+    }
+    Ok(value as i64)
+}
+
+// ⭐️ここまで pattern/localeに基づく通貨・桁区切り付き数値のfステート正規化
+
+// カンマ区切りの列名リストを取るテーブルオプション（例: number_as_text_columns）を
+// トリム済みの列名ベクタにパースする。空文字や空白のみの要素は無視する。
+fn parse_column_list_option(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+// gvizの1セル（"c"配列の要素）がnull/空（vもfも無い、または空文字）かどうかを判定する。
+// is_gviz_row_blankと_is_empty疑似カラム（row_is_empty_over_columns）の両方から共有される。
+fn is_gviz_cell_blank(cell: &JsonValue) -> bool {
+    if cell.is_null() {
+        return true;
+    }
+    let v_blank = match cell.get("v") {
+        None => true,
+        Some(v) if v.is_null() => true,
+        Some(v) => v.as_str().is_some_and(|s| s.trim().is_empty()),
+    };
+    let f_blank = match cell.get("f") {
+        None => true,
+        Some(f) if f.is_null() => true,
+        Some(f) => f.as_str().is_some_and(|s| s.trim().is_empty()),
+    };
+    v_blank && f_blank
+}
+
+// gvizの1行（"c"配列）が全セル空かどうかを判定する。
+// stop_at_blank_rows のしきい値判定にのみ使う（個々のセルのnull判定とは独立）。
+fn is_gviz_row_blank(row: &JsonValue) -> bool {
+    match row.pointer("/c").and_then(|v| v.as_array()) {
+        Some(cells) => cells.iter().all(is_gviz_cell_blank),
+        None => true,
+    }
+}
+
+// _is_empty 疑似カラムの値を組み立てる。stop_at_blank_rows/is_gviz_row_blankがソース行の
+// 全セルを見るのに対し、こちらはSELECTで選択された（疑似カラムを除く）ターゲット列に
+// 対応するソースセルだけを見る。選択列が1つも無い場合は判定しようがないためtrueを返す。
+fn row_is_empty_over_columns(src_row: &JsonValue, tgt_col_nums: &[u32], column_order: &[usize]) -> bool {
+    let cells = src_row.pointer("/c").and_then(|v| v.as_array());
+    tgt_col_nums.iter().all(|&num| {
+        let idx = resolve_source_col_index(num, column_order);
+        match cells.and_then(|c| c.get(idx)) {
+            Some(cell) => is_gviz_cell_blank(cell),
+            None => true,
+        }
+    })
+}
+
+// on_short_row用。マップ対象の（疑似カラムを除く）ターゲット列が要求するソース列インデックスの
+// うち最大のもの+1を「必要な行幅」とし、行の"c"配列がそれより短ければ(実際の行幅, 必要な行幅)を返す。
+// ragged（列数が不揃い）でも必要な行幅を満たしていればNoneを返す（末尾の余分な列は問題にしない）。
+fn ragged_row_shortfall(src_row: &JsonValue, tgt_col_nums: &[u32], column_order: &[usize]) -> Option<(usize, usize)> {
+    let required_len = tgt_col_nums
+        .iter()
+        .map(|&num| resolve_source_col_index(num, column_order) + 1)
+        .max()?;
+    let actual_len = src_row
+        .pointer("/c")
+        .and_then(|v| v.as_array())
+        .map(|c| c.len())
+        .unwrap_or(0);
+    (actual_len < required_len).then_some((actual_len, required_len))
+}
+
+// stop_at_blank_rows = 'true' の場合に呼ばれる。連続してblank_run行以上の全空行が現れた時点を
+// 論理的なデータ末尾とみなし、そこから後ろを丸ごと切り捨てる。過剰な書式設定や迷い込んだマーク等で
+// gvizが数百行の空行を返してくるシートに対応するためのオプション。個々の空行を場所を問わず
+// 取り除くskip_blank_rows（行単位のnull処理）とは独立に動作し、こちらは末尾の判定にのみ使う。
+fn truncate_at_blank_run(rows: &mut Vec<JsonValue>, blank_run: usize) {
+    if blank_run == 0 {
+        return;
+    }
+    let mut consecutive = 0usize;
+    for (i, row) in rows.iter().enumerate() {
+        if is_gviz_row_blank(row) {
+            consecutive += 1;
+            if consecutive >= blank_run {
+                rows.truncate(i + 1 - blank_run);
+                return;
+            }
+        } else {
+            consecutive = 0;
+        }
+    }
+}
+
+// skip_rows / reverse_rows テーブルオプションを、取得済みのsrc_rows（と、複数ファイル読み込み時に
+// 対応するrow_spread_sheet_ids）に対して適用する。常に「先頭からskip_rows行を捨てる」→
+// 「reverse_rowsならその残りを反転する」の順で行う。row_spread_sheet_idsが空（単一ファイル）の
+// 場合、src_rowsにだけ適用される。
+fn apply_skip_and_reverse_rows<T>(
+    src_rows: &mut Vec<T>,
+    row_spread_sheet_ids: &mut Vec<String>,
+    skip_rows: usize,
+    reverse_rows: bool,
+) {
+    if skip_rows > 0 {
+        let skip = skip_rows.min(src_rows.len());
+        src_rows.drain(0..skip);
+        if !row_spread_sheet_ids.is_empty() {
+            let ids_skip = skip.min(row_spread_sheet_ids.len());
+            row_spread_sheet_ids.drain(0..ids_skip);
+        }
+    }
+    if reverse_rows {
+        src_rows.reverse();
+        row_spread_sheet_ids.reverse();
+    }
+}
+
+// このFDWが提供する疑似カラムの一覧。_is_empty疑似カラムが「選択された列」を数える際、
+// ここに載っている疑似カラム自身は対象外とするために使う。
+const PSEUDO_COLUMN_NAMES: &[&str] = &[
+    "_spread_sheet_id",
+    "_row",
+    "_col_count",
+    "_synthetic_key",
+    "_row_hash",
+    "_is_empty",
+];
+
+// _synthetic_key 疑似カラムの値を組み立てる。spread_sheet_idが行ごとに変わり得る複数ファイル
+// 読み込みと、sheet_id（gid）が未指定の場合（空文字のまま連結される）の両方に対応する。
+fn build_synthetic_key(spread_sheet_id: &str, sheet_id: &str, row_idx: usize) -> String {
+    format!("{}:{}:{}", spread_sheet_id, sheet_id, row_idx)
+}
+
+// _row_hash 疑似カラムの値を組み立てる。JsonValueをserde_jsonの正規化された文字列表現に
+// 直してからSHA-256を取り、先頭16バイト（32桁の16進文字列）だけを使う。列選択やDDL上の列順は
+// この文字列表現に影響しないため、_row_hashは常にソース行の内容だけで決まる。
+fn hash_row_hex(value: &JsonValue) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value.to_string().as_bytes());
+    let digest = hasher.finalize();
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// null_strings テーブルオプションで指定されたセンチネル文字列のいずれかに、大文字小文字を
+// 区別せず前後の空白を除いた上で一致するかどうかを判定する。
+fn is_null_sentinel(value: &str, null_strings: &[String]) -> bool {
+    let trimmed = value.trim();
+    null_strings.iter().any(|s| s.eq_ignore_ascii_case(trimmed))
+}
+
+// "status=active:inactive:pending,category=a:b" 形式のテーブルオプション（allowed_values）を、
+// (列名, 許可値のベクタ) のペアのベクタにパースする。値の区切りにはカンマが列同士の区切りに
+// 使われているため":"を使う（source_letters等のcol=start:endレンジ系オプションと同じ発想）。
+fn parse_column_value_set_option(value: Option<&str>) -> Vec<(String, Vec<String>)> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|pair| {
+            let (col, values) = pair.split_once('=')?;
+            let values: Vec<String> = values
+                .split(':')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+                .collect();
+            Some((col.trim().to_owned(), values))
+        })
+        .collect()
+}
+
+// allowed_values テーブルオプションで許可値セットが指定された列に対し、セットに無い値を
+// on_row_errorポリシーに従って処理する。required_columnsのnull判定とは別物（許可値リストに
+// 無いのはnullではなく不正な値なので、そのことが伝わるメッセージにする）。戻り値はskip_rowに
+// するかどうか。
+fn enforce_allowed_values(
+    cell: Option<Cell>,
+    tgt_col_name: &str,
+    row_idx: usize,
+    allowed_values: &[(String, Vec<String>)],
+    case_sensitive: bool,
+    on_row_error: &str,
+) -> Result<(Option<Cell>, bool), FdwError> {
+    let Some(Cell::String(v)) = &cell else {
+        return Ok((cell, false));
+    };
+    let Some((_, allowed)) = allowed_values.iter().find(|(c, _)| c == tgt_col_name) else {
+        return Ok((cell, false));
+    };
+    let is_allowed = if case_sensitive {
+        allowed.iter().any(|a| a == v)
+    } else {
+        allowed.iter().any(|a| a.eq_ignore_ascii_case(v))
+    };
+    if is_allowed {
+        return Ok((cell, false));
+    }
+    match on_row_error {
+        "null" => Ok((None, false)),
+        "skip" => {
+            report_warning(&format!(
+                "skipping source row {} because column '{}' value '{}' is not in allowed_values",
+                row_idx, tgt_col_name, v
+            ));
+            Ok((None, true))
+        }
+        _ => Err(format!(
+            "column '{}' value '{}' is not in allowed_values at source row {}",
+            tgt_col_name, v, row_idx
+        )),
+    }
+}
+
+// "col=1.5,col2=-2" 形式のテーブルオプションを、(列名, f64値) のペアのベクタにパースする
+// （scale/offsetなど、列ごとに数値パラメータを持たせるオプションに使う）。
+fn parse_column_float_map_option(value: Option<&str>) -> Result<Vec<(String, f64)>, FdwError> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (col, v) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid column value pair '{}': expected 'col=value'", pair))?;
+            let v: f64 = v
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid numeric value in '{}'", pair))?;
+            Ok((col.trim().to_owned(), v))
+        })
+        .collect()
+}
+
+// scale/offset テーブルオプションで指定された列に対して value * scale + offset を適用する。
+// 指定が無い列はscale=1.0, offset=0.0（no-op）として扱う。
+fn apply_scale_offset(value: f64, tgt_col_name: &str, scale_columns: &[(String, f64)], offset_columns: &[(String, f64)]) -> f64 {
+    let scale = scale_columns
+        .iter()
+        .find(|(c, _)| c == tgt_col_name)
+        .map(|(_, v)| *v)
+        .unwrap_or(1.0);
+    let offset = offset_columns
+        .iter()
+        .find(|(c, _)| c == tgt_col_name)
+        .map(|(_, v)| *v)
+        .unwrap_or(0.0);
+    value * scale + offset
+}
+
+// "col=2,col2=4" 形式のテーブルオプションを、(列名, u32値) のペアのベクタにパースする
+// （round_toなど、列ごとに桁数を持たせるオプションに使う）。
+fn parse_column_u32_map_option(value: Option<&str>) -> Result<Vec<(String, u32)>, FdwError> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (col, v) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid column value pair '{}': expected 'col=value'", pair))?;
+            let v: u32 = v
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid digit count in '{}'", pair))?;
+            Ok((col.trim().to_owned(), v))
+        })
+        .collect()
+}
+
+// valueをdigits桁（小数点以下）でhalf-to-even（偶数への丸め、いわゆる銀行丸め）に丸める。
+// numeric(p,s)列のsを超える桁がスケールオーバーフローエラーを起こすのを防ぐために使う。
+// NaN/inf はそのまま素通しする（丸めても意味が無いため）。
+fn round_half_to_even(value: f64, digits: u32) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    let factor = 10f64.powi(digits as i32);
+    (value * factor).round_ties_even() / factor
+}
+
+// round_to テーブルオプションで指定された列に対してround_half_to_evenを適用する。
+// 指定が無い列はno-opとしてそのまま返す。
+fn apply_round_to(value: f64, tgt_col_name: &str, round_to_columns: &[(String, u32)]) -> f64 {
+    match round_to_columns.iter().find(|(c, _)| c == tgt_col_name) {
+        Some((_, digits)) => round_half_to_even(value, *digits),
+        None => value,
+    }
+}
+
+// range_columns = 'Sheet1!A1:C10=col_a|col_b|col_c,Sheet1!E1:F10=col_d|col_e' をパースする。
+// 外側はcol=value系オプションと同じくカンマ区切りだが、値そのものが複数列の並びになるため、
+// カンマと衝突しない区切り文字として「|」を使う（A1範囲文字列にはカンマも「|」も現れない）。
+fn parse_range_column_map_option(value: Option<&str>) -> Result<Vec<(String, Vec<String>)>, FdwError> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (range, cols) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid range_columns entry '{}': expected 'range=col1|col2|...'", pair))?;
+            let cols: Vec<String> = cols.split('|').map(|c| c.trim().to_owned()).filter(|c| !c.is_empty()).collect();
+            if cols.is_empty() {
+                return Err(format!("range_columns entry '{}' lists no columns", pair));
+            }
+            Ok((range.trim().to_owned(), cols))
+        })
+        .collect()
+}
+
+// rangeに対応するrange_columnsの列名一覧を、(範囲内の列インデックス → 対象列番号) の対応表に
+// 変換する。mappingがNone（rangesにその範囲の対応が無い、またはrange_columns自体が未指定）の
+// 場合は位置対応（範囲の1列目→対象スキーマの1列目…）にフォールバックする。
+fn resolve_range_column_positions(mapping: Option<&[String]>, tgt_columns: &[(String, u32)]) -> Result<Vec<u32>, FdwError> {
+    match mapping {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                tgt_columns
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, num)| *num)
+                    .ok_or_else(|| format!("range_columns references unknown column '{}'", name))
+            })
+            .collect(),
+        None => Ok((1..=tgt_columns.len() as u32).collect()),
+    }
+}
+
+// 範囲から取得した1行（範囲内の列順に並んだV4Cell）を、position_mapに従って対象スキーマ全体の
+// 幅tgt_widthに並べ替える。position_mapが指す対象列番号以外の位置はV4Cell::default()
+// （=null相当）で埋まる。異種スキーマの複数範囲を1つのv4_rowsへ連結できるのはこの並べ替えのため。
+fn realign_v4_range_row(src_row: &[V4Cell], position_map: &[u32], tgt_width: usize) -> Vec<V4Cell> {
+    let mut out = vec![V4Cell::default(); tgt_width];
+    for (src_idx, &tgt_num) in position_map.iter().enumerate() {
+        if let (Some(cell), Some(slot)) = (src_row.get(src_idx), out.get_mut((tgt_num - 1) as usize)) {
+            *slot = cell.clone();
+        }
+    }
+    out
+}
+
+// column_timezones テーブルオプションで指定された列単位のtimezone上書きを解決する。該当列の
+// 指定が無ければテーブル既定のtimezoneオプションをそのまま返す。
+fn resolve_column_timezone<'a>(
+    tgt_col_name: &str,
+    column_timezones: &'a [(String, String)],
+    table_timezone: &'a str,
+) -> &'a str {
+    column_timezones
+        .iter()
+        .find(|(c, _)| c == tgt_col_name)
+        .map(|(_, tz)| tz.as_str())
+        .unwrap_or(table_timezone)
+}
+
+// 数値列の最終値がNaN/Infinity等の非有限値になっていないか確認する。壊れた数式のセルや、
+// "NaN"/"Infinity"のような表示用文字列をRustのf64::from_strがそのまま受理してしまうケースが
+// 対象。nonfinite = 'error' ならエラーで中断し、既定の'null'ならこの列だけNULLにして先に進む。
+// f64::is_finite()での判定を挟まずCell::I64へ`as`キャストすると、NaNは0に、無限大は
+// i64::MAX/MINに化けて数値として紛れ込んでしまうため、キャストの前に必ずここを通す。
+fn resolve_nonfinite(value: f64, tgt_col_name: &str, nonfinite: &str) -> Result<Option<f64>, FdwError> {
+    if value.is_finite() {
+        Ok(Some(value))
+    } else if nonfinite == "error" {
+        Err(format!(
+            "column '{}' has a non-finite value ({}); set nonfinite = 'null' to allow this to become NULL instead",
+            tgt_col_name, value
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+// collect_errors = 'true' かつ on_row_error が 'null'/'skip' のとき、本来なら即座にiter_scanを
+// エラー終了させていたセル単位の変換失敗を、NULL（Ok(None)）へ読み替えつつcollected_cell_errorsに
+// 記録する。on_row_error = 'error'（既定）のままcollect_errorsだけtrueにした場合は、どちらを
+// 優先したのか曖昧にならないよう、従来通り即座にエラーを返す。記録後の行の扱い（そのまま通すか
+// スキップするか）は、このNULLを受け取った呼び出し元のrequired_columns/on_row_error判定に委ねる。
+fn record_or_propagate_cell_error(
+    this: &mut SpreadsheetsFdw,
+    reason: String,
+    row_idx: usize,
+    tgt_col_name: &str,
+    value: &str,
+) -> Result<Option<Cell>, FdwError> {
+    if !this.collect_errors || this.on_row_error == "error" {
+        return Err(reason);
+    }
+    if this.collected_cell_errors.len() < MAX_COLLECTED_CELL_ERRORS {
+        this.collected_cell_errors.push(format!(
+            "row {} column '{}' value '{}': {}",
+            row_idx, tgt_col_name, value, reason
+        ));
+    } else {
+        this.cell_errors_dropped += 1;
+    }
+    Ok(None)
+}
+
+// gvizの数式エラー（#REF!, #DIV/0! 等）を示すセルかどうかを判定する。エラーセルは"v"がnullの
+// うえで"f"にエラーコード文字列が入るため、単なる空セル（"v"も"f"も無い/null）とはここで区別できる。
+const GVIZ_ERROR_CODES: &[&str] = &[
+    "#REF!", "#DIV/0!", "#VALUE!", "#NAME?", "#NULL!", "#NUM!", "#N/A", "#ERROR!",
+];
+
+// cell_value_pointer/cell_formatted_pointer テンプレート中の"{i}"をソース列インデックスへ
+// 置換して、実際に使うJSON Pointer文字列を組み立てる。
+fn resolve_cell_pointer(template: &str, col_idx: usize) -> String {
+    template.replace("{i}", &col_idx.to_string())
+}
+
+fn gviz_cell_error(src_row: &JsonValue, value_pointer: &str, formatted_pointer: &str) -> Option<String> {
+    let v_is_null = src_row.pointer(value_pointer).is_some_and(|v| v.is_null());
+    if !v_is_null {
+        return None;
+    }
+    let f = src_row.pointer(formatted_pointer).and_then(|v| v.as_str())?;
+    GVIZ_ERROR_CODES
+        .iter()
+        .any(|code| f.starts_with(code))
+        .then(|| f.to_owned())
+}
+
+// bool_true_values/bool_false_values テーブルオプション（大文字小文字を区別しない）で指定された
+// トークン集合に照らして、真偽値列の文字列セルをCell::Boolへ解決する。どちらの集合にも
+// 一致しなければNoneを返し、呼び出し側でon_cell_errorポリシーに従わせる。
+fn parse_bool_token(raw: &str, true_values: &[String], false_values: &[String]) -> Option<bool> {
+    let lower = raw.trim().to_lowercase();
+    if true_values.iter().any(|v| v == &lower) {
+        Some(true)
+    } else if false_values.iter().any(|v| v == &lower) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// ⭐️ここから constant による、シートを参照しない列固定値の出力
+
+// constantテーブルオプション（"col=value,col2=value2"形式）で指定された生の文字列値を、
+// 対象列のtype_oidに応じたCellへ変換する。defaultオプションと異なりgvizルックアップを
+// 一切経由しない（シートに同名列が無くても、あっても常にこの値で上書きする）。
+fn coerce_constant_value(raw: &str, type_oid: TypeOid) -> Result<Cell, FdwError> {
+    match type_oid {
+        TypeOid::String => Ok(Cell::String(raw.to_owned())),
+        TypeOid::I64 => raw
+            .trim()
+            .parse::<i64>()
+            .map(Cell::I64)
+            .map_err(|_| format!("invalid constant value '{}': expected an integer", raw)),
+        TypeOid::F64 => raw
+            .trim()
+            .parse::<f64>()
+            .map(Cell::F64)
+            .map_err(|_| format!("invalid constant value '{}': expected a number", raw)),
+        TypeOid::Bool => match raw.trim().to_lowercase().as_str() {
+            "true" => Ok(Cell::Bool(true)),
+            "false" => Ok(Cell::Bool(false)),
+            _ => Err(format!("invalid constant value '{}': expected 'true' or 'false'", raw)),
+        },
+        TypeOid::Json => Ok(Cell::Json(raw.to_owned())),
+        other => Err(format!("constant option does not support column type {:?}", other)),
+    }
+}
+
+// 対象列がconstantオプションの対象であれば、その列のCellを組み立てて返す。対象でなければNoneを返し、
+// 呼び出し側は従来通りシートからの値の取り出しに進む。
+fn constant_cell_for_column(tgt_col: &Column, constant_columns: &[(String, String)]) -> Result<Option<Cell>, FdwError> {
+    let Some((_, raw)) = constant_columns.iter().find(|(c, _)| c == &tgt_col.name()) else {
+        return Ok(None);
+    };
+    coerce_constant_value(raw, tgt_col.type_oid()).map(Some)
+}
+
+// ⭐️ここまで constant による、シートを参照しない列固定値の出力
+
+// ⭐️ここから default / default_number / default_text / default_bool による欠損値の穴埋め
+
+// tgt_col_nameがdefault_columns（列単位のdefault）に一致すればその生の値文字列を、一致しなければ
+// type_oidに応じたテーブル全体のデフォルト（default_number/default_text/default_bool）の生の値文字列を
+// 返す（列単位の指定がテーブル全体の型別デフォルトより優先される）。Cellへの型変換はColumnが要る
+// coerce_constant_valueに任せ、この関数自体はColumnに依存しない純粋なルックアップに留める。
+fn resolve_default_raw<'a>(
+    tgt_col_name: &str,
+    type_oid: TypeOid,
+    default_columns: &'a [(String, String)],
+    default_number: Option<&'a str>,
+    default_text: Option<&'a str>,
+    default_bool: Option<&'a str>,
+) -> Option<&'a str> {
+    if let Some((_, raw)) = default_columns.iter().find(|(c, _)| c == tgt_col_name) {
+        return Some(raw.as_str());
+    }
+    match type_oid {
+        TypeOid::I64 | TypeOid::F64 | TypeOid::Numeric => default_number,
+        TypeOid::String => default_text,
+        TypeOid::Bool => default_bool,
+        _ => None,
+    }
+}
+
+// ソース由来のcellがNone（欠損/null）だった場合にのみ呼ばれ、resolve_default_rawが返した値を
+// coerce_constant_valueでCellに変換して穴埋めする。cellが既にSomeなら（constant/シートの実値問わず）
+// 何もせずそのまま返す。
+fn apply_default_cell(
+    cell: Option<Cell>,
+    tgt_col: &Column,
+    default_columns: &[(String, String)],
+    default_number: Option<&str>,
+    default_text: Option<&str>,
+    default_bool: Option<&str>,
+) -> Result<Option<Cell>, FdwError> {
+    if cell.is_some() {
+        return Ok(cell);
+    }
+    match resolve_default_raw(&tgt_col.name(), tgt_col.type_oid(), default_columns, default_number, default_text, default_bool) {
+        Some(raw) => coerce_constant_value(raw, tgt_col.type_oid()).map(Some),
+        None => Ok(None),
+    }
+}
+
+// ⭐️ここまで default / default_number / default_text / default_bool による欠損値の穴埋め
+
+// gvizのDate(...)/DateTime(...)形式のリテラル文字列（例: "Date(2023,0,15,10,30,0)"）を
+// (year, month, day, hour, minute, second) にパースする。gvizはJavaScriptの流儀でmonthを0始まりで
+// 返すため、ここで1を足して人間にとって自然な1始まりの月に直す。時刻部分（4〜6番目の引数）は
+// 日付のみのDate(...)では省略されるため、無い場合は0（真夜中）として扱う。
+fn parse_gviz_date_value(s: &str) -> Result<(i32, u32, u32, u32, u32, u32), FdwError> {
+    let inner = s
+        .strip_prefix("Date(")
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("expected a gviz Date(...) literal, got '{}'", s))?;
+    let parts: Vec<i64> = inner
+        .split(',')
+        .map(|p| {
+            p.trim()
+                .parse::<i64>()
+                .map_err(|_| format!("invalid gviz date literal '{}'", s))
+        })
+        .collect::<Result<_, _>>()?;
+    if parts.len() < 3 {
+        return Err(format!(
+            "invalid gviz date literal '{}': expected at least year, month, day",
+            s
+        ));
+    }
+    Ok((
+        parts[0] as i32,
+        parts[1] as u32 + 1,
+        parts[2] as u32,
+        *parts.get(3).unwrap_or(&0) as u32,
+        *parts.get(4).unwrap_or(&0) as u32,
+        *parts.get(5).unwrap_or(&0) as u32,
+    ))
+}
+
+// (year, month, day, hour, minute, second) を、timezoneテーブルオプション（固定UTCオフセット、
+// 例 "+09:00"）で解釈した上でUnixエポックミリ秒に変換する。gviz Date(...)リテラル由来・
+// date_formatでのテキストパース由来のどちらの日時部品からも共通して使う。
+fn epoch_ms_from_date_parts(parts: (i32, u32, u32, u32, u32, u32), timezone: &str) -> Result<i64, FdwError> {
+    let (year, month, day, hour, minute, second) = parts;
+    let rfc3339 = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year, month, day, hour, minute, second, timezone
+    );
+    let epoch_secs = time_parse_from_rfc3339(&rfc3339)?;
+    Ok(epoch_secs * 1000)
+}
+
+// datetime_as = 'epoch_ms' が指定された列向けに、gvizのDate(...)リテラルをtimezoneテーブルオプション
+// （固定UTCオフセット、例 "+09:00"）で解釈した上でUnixエポックミリ秒に変換する。
+// 時刻部分を持たない日付（Date(y,m,d)のみ）は、そのタイムゾーンでの真夜中として変換する。
+fn gviz_date_value_to_epoch_ms(s: &str, timezone: &str) -> Result<i64, FdwError> {
+    epoch_ms_from_date_parts(parse_gviz_date_value(s)?, timezone)
+}
+
+// duration_as = 'total_seconds' が指定された列向けに、`[h]:mm:ss`（24時間を超えられる経過時間書式）
+// の表示用文字列を総秒数にパースする。時間部分の桁数・値域は問わない（[h]は24時間の壁を超える
+// ことがGoogle Sheetsの経過時間書式の定義そのもののため）が、分・秒は0-59でなければ不正とみなす。
+fn parse_duration_string_to_seconds(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.trim().splitn(3, ':').collect();
+    let [h, m, sec] = parts.as_slice() else {
+        return None;
+    };
+    let h: i64 = h.parse().ok()?;
+    let m: i64 = m.parse().ok()?;
+    let sec: f64 = sec.parse().ok()?;
+    if h < 0 || !(0..60).contains(&m) || !(0.0..60.0).contains(&sec) {
+        return None;
+    }
+    Some(h * 3600 + m * 60 + sec.round() as i64)
+}
+
+// ⭐️ここから date_format による、Date(...)形式でないテキスト日付のパース
+
+// date_formatテーブルオプションに現れる月名ディレクティブ（%b, %B）用の、英語の月名一覧
+// （添字0=1月）。略称（3文字）は完全な月名の接頭辞として、完全名は大文字小文字を無視して照合する。
+const MONTH_NAMES: &[&str] = &[
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+
+fn month_name_to_number(name: &str) -> Option<u32> {
+    let lower = name.to_lowercase();
+    MONTH_NAMES
+        .iter()
+        .position(|full| *full == lower || (lower.len() == 3 && full.starts_with(&lower)))
+        .map(|i| i as u32 + 1)
+}
+
+// date_formatが対応していないディレクティブを含んでいないか、begin_scanの時点で先に検査する。
+fn validate_date_format(format: &str) -> Result<(), FdwError> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('Y' | 'y' | 'm' | 'd' | 'H' | 'M' | 'S' | 'b' | 'B') => {}
+                Some(other) => return Err(format!("unsupported date_format directive '%{}'", other)),
+                None => return Err(format!("invalid date_format '{}': trailing '%'", format)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// 入力の先頭から、1桁以上max_width桁以下の数字列を読み取る（"1/15/2023"のようにゼロ埋めされて
+// いない日付にも対応するため、固定幅ではなく可能な限り貪欲に読み取る）。
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, max_width: usize) -> Result<u32, FdwError> {
+    let mut out = String::new();
+    while out.len() < max_width && chars.peek().is_some_and(char::is_ascii_digit) {
+        out.push(chars.next().unwrap());
+    }
+    out.parse().map_err(|_| "expected a number".to_owned())
+}
+
+// 入力の先頭から、アルファベットが続く限り読み取る（%b/%Bの月名用）。
+fn take_alpha(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut out = String::new();
+    while chars.peek().is_some_and(|c| c.is_alphabetic()) {
+        out.push(chars.next().unwrap());
+    }
+    out
+}
+
+// date_formatテーブルオプション（strftime風、%Y/%y/%m/%d/%H/%M/%Sおよび月名の%b/%Bに対応）に従って、
+// gvizのDate(...)リテラルでない素のテキスト日付（例 "15/01/2023", "Jan 15, 2023"）を
+// (year, month, day, hour, minute, second) にパースする。時刻部分のディレクティブが無ければ0（真夜中）とする。
+fn parse_custom_date_string(s: &str, format: &str) -> Result<(i32, u32, u32, u32, u32, u32), FdwError> {
+    let (mut year, mut month, mut day, mut hour, mut minute, mut second) = (1970i32, 1u32, 1u32, 0u32, 0u32, 0u32);
+    let mut chars = s.chars().peekable();
+    let mut fmt_chars = format.chars().peekable();
+    let mk_err = || format!("date value '{}' does not match date_format '{}'", s, format);
+    while let Some(fc) = fmt_chars.next() {
+        if fc == '%' {
+            match fmt_chars.next() {
+                Some('Y') => year = take_digits(&mut chars, 4).map_err(|_| mk_err())? as i32,
+                Some('y') => year = 2000 + take_digits(&mut chars, 2).map_err(|_| mk_err())? as i32,
+                Some('m') => month = take_digits(&mut chars, 2).map_err(|_| mk_err())?,
+                Some('d') => day = take_digits(&mut chars, 2).map_err(|_| mk_err())?,
+                Some('H') => hour = take_digits(&mut chars, 2).map_err(|_| mk_err())?,
+                Some('M') => minute = take_digits(&mut chars, 2).map_err(|_| mk_err())?,
+                Some('S') => second = take_digits(&mut chars, 2).map_err(|_| mk_err())?,
+                Some('b' | 'B') => {
+                    month = month_name_to_number(&take_alpha(&mut chars)).ok_or_else(mk_err)?;
+                }
+                _ => return Err(format!("unsupported date_format directive in '{}'", format)),
+            }
+        } else {
+            match chars.next() {
+                Some(c) if c == fc => {}
+                _ => return Err(mk_err()),
+            }
+        }
+    }
+    if chars.peek().is_some() {
+        return Err(mk_err());
+    }
+    Ok((year, month, day, hour, minute, second))
+}
+
+// TypeOid::Date/Timestamp/Timestamptz列の値を解決する。gvizのDate(...)リテラルであればそのまま
+// パースし、そうでない素のテキストであればdate_formatが設定されている場合に限りそれで解釈する。
+// いずれも成功しなければ、date_formatを設定するよう促すエラーを返す（呼び出し側でon_cell_error
+// ポリシーに従って扱う）。
+fn parse_date_cell_to_epoch_ms(src: &str, date_format: &str, timezone: &str) -> Result<i64, FdwError> {
+    let parts = match parse_gviz_date_value(src) {
+        Ok(parts) => parts,
+        Err(_) if !date_format.is_empty() => parse_custom_date_string(src, date_format)?,
+        Err(_) => {
+            return Err(format!(
+                "cannot parse date value '{}': not a gviz Date(...) literal and no 'date_format' option is configured",
+                src
+            ));
+        }
+    };
+    epoch_ms_from_date_parts(parts, timezone)
+}
+
+// ⭐️ここまで date_format による、Date(...)形式でないテキスト日付のパース
+
+// スプレッドシートの列アルファベット（A, B, ..., Z, AA, AB, ...）を0始まりのソース列インデックスに変換する。
+fn column_letter_to_index(letters: &str) -> Result<usize, FdwError> {
+    let letters = letters.trim();
+    if letters.is_empty() || !letters.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return Err(format!("invalid spreadsheet column letters '{}'", letters));
+    }
+    let mut index: usize = 0;
+    for b in letters.bytes() {
+        let digit = (b.to_ascii_uppercase() - b'A') as usize + 1;
+        index = index * 26 + digit;
+    }
+    Ok(index - 1)
+}
+
+// 0始まりのソース列インデックスをスプレッドシートの列アルファベット（A, B, ..., Z, AA, AB, ...）に
+// 変換する。column_letter_to_indexの逆変換。
+fn column_index_to_letters(mut index: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap()
+}
+
+// column_order テーブルオプション（"C,A,B"のような列アルファベットのカンマ区切りリスト）を、
+// 宣言済みPostgres列の序数ごとの読み取り先ソース列インデックス（0始まり）にパースする。
+// 要素数はctx.get_columns().len()と一致していなければならない（begin_scan側で検証する）。
+fn parse_column_order_option(value: &str) -> Result<Vec<usize>, FdwError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(column_letter_to_index)
+        .collect()
+}
+
+// tgt_col_num（1始まりの宣言順）を実際に読みに行くソース列インデックス（0始まり）へ解決する。
+// column_orderが指定されていればそれに従い、宣言順とシート側の列順を切り離す。未指定なら
+// 従来通りtgt_col_num - 1をそのまま使う。
+fn resolve_source_col_index(tgt_col_num: u32, column_order: &[usize]) -> usize {
+    let ordinal = (tgt_col_num - 1) as usize;
+    column_order.get(ordinal).copied().unwrap_or(ordinal)
+}
+
+// include_header_row = 'true' 用に、宣言済み列の並び（tgt_col_num順）それぞれに対応する
+// source labelを組み立てる。実データの値をsrc_col_idx経由で引くのと全く同じ列対応
+// （resolve_source_col_index）を使うため、ヘッダー行とその後のデータ行とで列の並びが
+// ずれることはない。ラベルが存在しない添字（シートの列数より宣言列数が多い等）は空文字にする。
+fn header_row_labels(column_labels: &[String], column_order: &[usize], tgt_col_nums: &[u32]) -> Vec<String> {
+    tgt_col_nums
+        .iter()
+        .map(|&num| {
+            let idx = resolve_source_col_index(num, column_order);
+            column_labels.get(idx).cloned().unwrap_or_default()
+        })
+        .collect()
+}
+
+// start_cell テーブルオプション（例 "A2"）を(列インデックス, 行番号)にパースする。単一セル参照
+// （列アルファベット+行番号）以外はエラーにする。
+fn parse_start_cell_option(value: &str) -> Result<(usize, u32), FdwError> {
+    let split_at = value.find(|c: char| c.is_ascii_digit());
+    let (letters, digits) = match split_at {
+        Some(i) if i > 0 => value.split_at(i),
+        _ => {
+            return Err(format!(
+                "invalid start_cell '{}': expected a single cell reference like 'A2'",
+                value
+            ))
+        }
+    };
+    let row: u32 = digits
+        .parse()
+        .map_err(|_| format!("invalid start_cell '{}': expected a single cell reference like 'A2'", value))?;
+    if row == 0 {
+        return Err(format!("invalid start_cell '{}': row must be 1 or greater", value));
+    }
+    let col = column_letter_to_index(letters)?;
+    Ok((col, row))
+}
+
+// start_cellと宣言済みの列数から、gvizのCSVエクスポート/v4 APIの両方で使えるA1形式の範囲
+// （例 "A2:D"、下方向の行数は指定せず開けておく）を組み立てる。これにより、ユーザーは
+// 終端の列アルファベットを自分で数える必要がなくなる。
+fn build_a1_range_from_start_cell(start_cell: &str, num_columns: usize) -> Result<String, FdwError> {
+    if num_columns == 0 {
+        return Err("start_cell requires at least one declared column to infer the range width".to_owned());
+    }
+    let (start_col, start_row) = parse_start_cell_option(start_cell)?;
+    let end_col = column_index_to_letters(start_col + num_columns - 1);
+    let start_col_letters = column_index_to_letters(start_col);
+    Ok(format!("{}{}:{}", start_col_letters, start_row, end_col))
+}
+
+// "tgt_col=C:F,tgt_col2=H:J" 形式のテーブルオプションを、(この列名, (開始インデックス, 終了インデックス))の
+// ベクタにパースする（source_lettersなど、隣接する複数のソース列をまとめて扱うオプションに使う）。
+// インデックスはcolumn_letter_to_indexと同じく0始まり、両端を含む(inclusive)。
+fn parse_column_range_map_option(value: Option<&str>) -> Result<Vec<(String, (usize, usize))>, FdwError> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (col, range) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid column range pair '{}': expected 'col=C:F'", pair))?;
+            let (start, end) = range
+                .split_once(':')
+                .ok_or_else(|| format!("invalid column range '{}': expected 'C:F'", range))?;
+            let start = column_letter_to_index(start)?;
+            let end = column_letter_to_index(end)?;
+            if start > end {
+                return Err(format!("invalid column range '{}': start comes after end", range));
+            }
+            Ok((col.trim().to_owned(), (start, end)))
+        })
+        .collect()
+}
+
+// gvizレスポンスから table_index 番目のテーブルを選び、以降のコードが従来通り"/table/cols"や
+// "/table/rows"を参照できるよう {"table": ...} の形に正規化して返す。通常のレスポンスは単一の
+// "table"オブジェクトしか持たないが、tqxの設定次第では複数テーブルの"tables"配列が返ることがある
+// ため、そのケースにも対応する。範囲外のインデックスは、実際に何個テーブルがあったかを含めてエラーにする。
+fn select_gviz_table(resp_json: &JsonValue, table_index: usize) -> Result<JsonValue, FdwError> {
+    if let Some(tables) = resp_json.get("tables").and_then(|v| v.as_array()) {
+        let table = tables.get(table_index).ok_or_else(|| {
+            format!(
+                "table_index {} out of range: response has {} table(s)",
+                table_index,
+                tables.len()
+            )
+        })?;
+        return Ok(json!({ "table": table }));
+    }
+    if table_index != 0 {
+        return Err(format!(
+            "table_index {} out of range: response has 1 table",
+            table_index
+        ));
+    }
+    Ok(resp_json.clone())
+}
+
+// expected_labelsテーブルオプションが指定されている場合、gvizレスポンスのcols labelの並びが
+// 期待通りかどうかを検証する。normalize_headersが真なら前後空白除去+小文字化した上で比較する。
+// 不一致の場合は欠落/余剰の両方をエラーメッセージに含め、シート側のレイアウト変更を早期に検出できるようにする。
+fn validate_expected_labels(
+    resp_json: &JsonValue,
+    expected_labels: &[String],
+    normalize_headers: bool,
+) -> Result<(), FdwError> {
+    if expected_labels.is_empty() {
+        return Ok(());
+    }
+    let cols = resp_json
+        .pointer("/table/cols")
+        .and_then(|v| v.as_array())
+        .ok_or("cannot get column metadata from response")?;
+    let actual_labels: Vec<String> = cols
+        .iter()
+        .map(|c| c.get("label").and_then(|v| v.as_str()).unwrap_or_default().to_owned())
+        .collect();
+    let normalize = |s: &str| {
+        if normalize_headers {
+            s.trim().to_lowercase()
+        } else {
+            s.to_owned()
+        }
+    };
+    let actual_norm: Vec<String> = actual_labels.iter().map(|s| normalize(s)).collect();
+    let expected_norm: Vec<String> = expected_labels.iter().map(|s| normalize(s)).collect();
+    if actual_norm == expected_norm {
+        return Ok(());
+    }
+    let missing: Vec<&String> = expected_labels
+        .iter()
+        .zip(expected_norm.iter())
+        .filter(|(_, en)| !actual_norm.contains(en))
+        .map(|(e, _)| e)
+        .collect();
+    let extra: Vec<&String> = actual_labels
+        .iter()
+        .zip(actual_norm.iter())
+        .filter(|(_, an)| !expected_norm.contains(an))
+        .map(|(a, _)| a)
+        .collect();
+    Err(format!(
+        "expected_labels mismatch: expected {:?}, got {:?} (missing: {:?}, extra: {:?})",
+        expected_labels, actual_labels, missing, extra
+    ))
+}
+
+// "this_col=other_col,this_col2=other_col2" 形式のテーブルオプションを、(この列名, 参照先列名)の
+// ペアのベクタにパースする（hyperlink_ofなど、列同士の関係を表すオプションに使う）。
+fn parse_column_pair_list_option(value: Option<&str>) -> Result<Vec<(String, String)>, FdwError> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (this_col, other_col) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid column pair '{}': expected 'this_col=other_col'", pair))?;
+            Ok((this_col.trim().to_owned(), other_col.trim().to_owned()))
+        })
+        .collect()
+}
+
+// ⭐️ここから source_format = 'csv' 対応（export?format=csv経由での読み込み）
+
+// RFC4180に沿った最小限のCSVパーサ。ダブルクォートで囲まれたフィールド内のカンマ・改行・
+// エスケープされた("")ダブルクォートを扱う。改行を含むCSV全体を渡すこと。
+fn parse_csv(body: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else {
+            match c {
+                '"' => in_quotes = true,
+                ',' => {
+                    row.push(std::mem::take(&mut field));
+                }
+                '\r' => {}
+                '\n' => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                _ => field.push(c),
+            }
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+// ragged（列数が不揃い）なCSV行を、期待する列数に揃える。
+// 短い行は空文字のフィールドで埋め、長い行はstrictなら列数不一致としてエラーにし、
+// そうでなければ末尾の余分なフィールドを切り捨てる。
+fn normalize_csv_row(mut row: Vec<String>, expected_cols: usize, strict: bool, row_idx: usize) -> Result<Vec<String>, FdwError> {
+    if row.len() < expected_cols {
+        row.resize(expected_cols, String::new());
+    } else if row.len() > expected_cols {
+        if strict {
+            return Err(format!(
+                "CSV row {} has {} fields, expected {} (csv_strict is enabled)",
+                row_idx,
+                row.len(),
+                expected_cols
+            ));
+        }
+        row.truncate(expected_cols);
+    }
+    Ok(row)
+}
+
+// ⭐️ここまで source_format = 'csv' 対応
+
+// ⭐️ここから source_format = 'html' 対応（公開ページのpubhtmlをスクレイピングする最後の手段）
+
+// html内から、開始タグnameのbody範囲（同名タグのネストを深さで数えて正しく閉じタグに対応付ける）を
+// 出現順にすべて返す。厳密なHTMLパーサではなく、Googleスプレッドシートの公開ページが実際に
+// 出す整った構造にだけ対応する最小限のスキャナ（大文字小文字は区別しない）。
+fn find_tag_bodies(html: &str, tag: &str) -> Vec<(usize, usize)> {
+    let lower = html.to_ascii_lowercase();
+    let open_needle = format!("<{}", tag);
+    let close_needle = format!("</{}>", tag);
+    let mut spans = Vec::new();
+    let mut search_from = 0;
+    while let Some(open_rel) = lower[search_from..].find(&open_needle) {
+        let open_start = search_from + open_rel;
+        let Some(tag_end_rel) = lower[open_start..].find('>') else {
+            break;
+        };
+        let body_start = open_start + tag_end_rel + 1;
+        let mut depth = 1;
+        let mut pos = body_start;
+        let mut body_end = None;
+        loop {
+            let next_open = lower[pos..].find(&open_needle).map(|i| pos + i);
+            let next_close = lower[pos..].find(&close_needle).map(|i| pos + i);
+            match (next_open, next_close) {
+                (Some(o), Some(c)) if o < c => {
+                    depth += 1;
+                    pos = o + open_needle.len();
+                }
+                (_, Some(c)) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        body_end = Some(c);
+                        search_from = c + close_needle.len();
+                        break;
+                    }
+                    pos = c + close_needle.len();
+                }
+                _ => {
+                    search_from = html.len();
+                    break;
+                }
+            }
+        }
+        match body_end {
+            Some(end) => spans.push((body_start, end)),
+            None => break,
+        }
+    }
+    spans
+}
+
+// タグの中身からHTMLタグを取り除いた、プレーンテキストだけを返す。
+fn strip_html_tags(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut in_tag = false;
+    for c in s.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+// Googleスプレッドシートの公開ページが実際に出す範囲でよく使われるHTML実体参照だけをデコードする
+// （汎用的なHTML実体参照テーブルは持たない）。
+fn decode_html_entities(s: &str) -> String {
+    s.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// tr要素のHTML断片から、td/thセルのテキストだけを出現順にすべて抜き出す（ネストしたtd/thは想定しない）。
+fn extract_html_cells(row_html: &str) -> Vec<String> {
+    let lower = row_html.to_ascii_lowercase();
+    let mut cells = Vec::new();
+    let mut pos = 0;
+    loop {
+        let next_td = lower[pos..].find("<td").map(|i| pos + i);
+        let next_th = lower[pos..].find("<th").map(|i| pos + i);
+        let (open_start, close_needle) = match (next_td, next_th) {
+            (Some(td), Some(th)) if th < td => (th, "</th>"),
+            (Some(td), Some(_)) => (td, "</td>"),
+            (Some(td), None) => (td, "</td>"),
+            (None, Some(th)) => (th, "</th>"),
+            (None, None) => break,
+        };
+        let Some(tag_end_rel) = lower[open_start..].find('>') else {
+            break;
+        };
+        let content_start = open_start + tag_end_rel + 1;
+        let Some(close_rel) = lower[content_start..].find(close_needle) else {
+            break;
+        };
+        let content_end = content_start + close_rel;
+        cells.push(decode_html_entities(&strip_html_tags(&row_html[content_start..content_end])).trim().to_owned());
+        pos = content_end + close_needle.len();
+    }
+    cells
+}
+
+// 公開HTMLページの本文からtable_index番目の<table>を選び、その中のtr行をtd/thセルのテキストの
+// Vecへとパースする。table_indexが範囲外、またはページ内に<table>が1つも無ければエラーにする。
+fn parse_html_table(body: &str, table_index: usize) -> Result<Vec<Vec<String>>, FdwError> {
+    let tables = find_tag_bodies(body, "table");
+    let (start, end) = *tables.get(table_index).ok_or_else(|| {
+        format!(
+            "table_index {} out of range: page has {} <table> element(s)",
+            table_index,
+            tables.len()
+        )
+    })?;
+    let table_html = &body[start..end];
+    let rows = find_tag_bodies(table_html, "tr");
+    Ok(rows
+        .iter()
+        .map(|&(rs, re)| extract_html_cells(&table_html[rs..re]))
+        .collect())
+}
+
+// ⭐️ここまで source_format = 'html' 対応
+
+// ⭐️ここから Google Sheets API v4 対応（noteやhyperlinkなど、gvizでは取得できないメタデータ用）
+
+// サービスアカウントのJWTをGoogleのトークンエンドポイントに交換し、v4 API呼び出し用のアクセストークンを得る。
+// max_retriesが1以上の場合、トークンエンドポイントへのリクエストが失敗するたびに指数バックオフ
+// （200ms, 400ms, 800ms, ...）を挟みながらリトライする。エラーメッセージには"v4 auth error: "を
+// 前置し、データ取得エラーと見分けられるようにしている（FdwErrorが文字列型のため、専用の構造化
+// エラー型の代わりにこの接頭辞による区別を採用している）。
+fn get_v4_access_token(service_account_json: &str, max_retries: u32) -> Result<String, FdwError> {
+    let sa: JsonValue = serde_json::from_str(service_account_json)
+        .map_err(|e| format!("v4 auth error: invalid service_account JSON: {}", e))?;
+    let client_email = sa
+        .get("client_email")
+        .and_then(|v| v.as_str())
+        .ok_or("v4 auth error: service_account is missing 'client_email'")?;
+    let private_key = sa
+        .get("private_key")
+        .and_then(|v| v.as_str())
+        .ok_or("v4 auth error: service_account is missing 'private_key'")?;
+    let token_uri = sa
+        .get("token_uri")
+        .and_then(|v| v.as_str())
+        .unwrap_or("https://oauth2.googleapis.com/token");
+
+    let assertion = jwt::encode(
+        vec![
+            ("iss".to_owned(), client_email.to_owned()),
+            (
+                "scope".to_owned(),
+                "https://www.googleapis.com/auth/spreadsheets.readonly".to_owned(),
+            ),
+            ("aud".to_owned(), token_uri.to_owned()),
+        ],
+        "RS256".to_owned(),
+        private_key.to_owned(),
+        1,
+    )
+    .map_err(|e| format!("v4 auth error: failed to sign service account JWT: {}", e))?;
+
+    let body = format!(
+        "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer&assertion={}",
+        gviz_url_encode(&assertion)
+    );
+    let req = http::Request {
+        method: http::Method::Post,
+        url: token_uri.to_owned(),
+        headers: vec![(
+            "content-type".to_owned(),
+            "application/x-www-form-urlencoded".to_owned(),
+        )],
+        body,
+    };
+
+    let mut attempt = 0;
+    loop {
+        check_scan_deadline()?;
+        let result = http_post(&req)
+            .map_err(|e| format!("v4 auth error: {}", e))
+            .and_then(|resp| {
+                let token_json: JsonValue =
+                    serde_json::from_str(&resp.body).map_err(|e| format!("v4 auth error: {}", e))?;
+                token_json
+                    .get("access_token")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_owned())
+                    .ok_or_else(|| {
+                        format!("v4 auth error: token endpoint did not return an access_token: {}", resp.body)
+                    })
+            });
+        match result {
+            Ok(token) => return Ok(token),
+            Err(e) if attempt < max_retries => match retry_policy().try_consume_budget() {
+                Some(remaining) => {
+                    attempt += 1;
+                    stats().retries += 1;
+                    report_warning(&format!(
+                        "token endpoint request failed (attempt {}/{}): {}; retrying{}",
+                        attempt,
+                        max_retries,
+                        e,
+                        format_remaining_budget(remaining)
+                    ));
+                    time::sleep(200u64 * (1u64 << (attempt - 1)));
+                }
+                None => return Err(format!("{}; total_retry_budget exhausted", e)),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// キャッシュされたアクセストークンが有効期限内ならそれを返し、無ければget_v4_access_token
+// （リトライ/バックオフ付き）で新規取得してキャッシュする。flakyな認証エンドポイントが
+// スキャンのたびにブロックしないようにするための仕組み。
+fn get_cached_v4_access_token(this: &mut SpreadsheetsFdw, service_account_json: &str) -> Result<String, FdwError> {
+    let now = time::epoch_secs();
+    if let Some(token) = &this.cached_access_token {
+        if now < this.cached_access_token_expiry {
+            return Ok(token.clone());
+        }
+    }
+    let token = get_v4_access_token(service_account_json, this.auth_max_retries)?;
+    // jwt::encodeにはttl_hours=1を渡しているため、安全マージンを取って55分でキャッシュを失効させる。
+    this.cached_access_token = Some(token.clone());
+    this.cached_access_token_expiry = now + 55 * 60;
+    Ok(token)
+}
+
+// v4 APIでスプレッドシート全体（プロパティとセル値/note/hyperlink）を取得する。
+fn fetch_v4_spreadsheet(
+    spread_sheet_id: &str,
+    access_token: &str,
+    fields: &str,
+    ranges: Option<&str>,
+    query_params: &[(String, String)],
+) -> Result<JsonValue, FdwError> {
+    let mut url = format!(
+        "https://sheets.googleapis.com/v4/spreadsheets/{}?fields={}",
+        spread_sheet_id,
+        gviz_url_encode(fields),
+    );
+    if let Some(ranges) = ranges {
+        url.push_str(&format!("&ranges={}", gviz_url_encode(ranges)));
+    }
+    let req = http::Request {
+        method: http::Method::Get,
+        url,
+        headers: vec![("authorization".to_owned(), format!("Bearer {}", access_token))],
+        body: String::default(),
+    };
+    let resp = get_following_redirects(req, query_params)?;
+    serde_json::from_str(&resp.body).map_err(|e| e.to_string())
+}
+
+// page_size指定時のページング用に、対象シートのタイトルを解決する。rangesパラメータに渡す
+// A1形式の範囲（"タイトル!開始:終了"）はgidではなくシートタイトルを要求するため、事前に軽量な
+// リクエスト（値は取得せずpropertiesのみ）で解決しておく。
+fn fetch_v4_sheet_title(
+    spread_sheet_id: &str,
+    access_token: &str,
+    sheet_id: Option<&str>,
+    query_params: &[(String, String)],
+) -> Result<String, FdwError> {
+    let resp_json = fetch_v4_spreadsheet(spread_sheet_id, access_token, "sheets.properties(sheetId,title)", None, query_params)?;
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = match sheet_id {
+        Some(gid) => sheets
+            .iter()
+            .find(|s| {
+                s.pointer("/properties/sheetId")
+                    .and_then(|v| v.as_i64())
+                    .map(|v| v.to_string())
+                    == Some(gid.to_owned())
+            })
+            .ok_or_else(|| format!("no sheet found with gid {}", gid))?,
+        None => sheets.first().ok_or("spreadsheet has no sheets")?,
+    };
+    sheet
+        .pointer("/properties/title")
+        .and_then(|v| v.as_str())
+        .map(str::to_owned)
+        .ok_or_else(|| "sheet is missing 'title'".to_owned())
+}
+
+// skip_frozen_rows = 'true' の場合に、対象シートのfrozenRowCount（ウィンドウ枠固定行数）を解決する。
+// プロパティ自体が省略されている場合、frozenRowCountは0（固定行なし）を意味する。
+fn fetch_v4_frozen_row_count(
+    spread_sheet_id: &str,
+    access_token: &str,
+    sheet_id: Option<&str>,
+    query_params: &[(String, String)],
+) -> Result<usize, FdwError> {
+    let resp_json = fetch_v4_spreadsheet(
+        spread_sheet_id,
+        access_token,
+        "sheets.properties(sheetId,frozenRowCount)",
+        None,
+        query_params,
+    )?;
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = match sheet_id {
+        Some(gid) => sheets
+            .iter()
+            .find(|s| {
+                s.pointer("/properties/sheetId")
+                    .and_then(|v| v.as_i64())
+                    .map(|v| v.to_string())
+                    == Some(gid.to_owned())
+            })
+            .ok_or_else(|| format!("no sheet found with gid {}", gid))?,
+        None => sheets.first().ok_or("spreadsheet has no sheets")?,
+    };
+    Ok(sheet.pointer("/properties/frozenRowCount").and_then(|v| v.as_u64()).unwrap_or(0) as usize)
+}
+
+// introspect = 'rowcount'（api_mode = 'v4'）用に、対象シートのgridProperties.rowCountを解決する。
+// これはシートの「宣言済みグリッドの行数」であり、Googleスプレッドシートは新規シート作成時に
+// 実データより多くの行（既定1000行）を確保するため、末尾の空行（trailing blank rows）も
+// そのまま含む値になる。
+fn fetch_v4_grid_row_count(
+    spread_sheet_id: &str,
+    access_token: &str,
+    sheet_id: Option<&str>,
+    query_params: &[(String, String)],
+) -> Result<i64, FdwError> {
+    let resp_json = fetch_v4_spreadsheet(
+        spread_sheet_id,
+        access_token,
+        "sheets.properties(sheetId,gridProperties(rowCount))",
+        None,
+        query_params,
+    )?;
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = match sheet_id {
+        Some(gid) => sheets
+            .iter()
+            .find(|s| {
+                s.pointer("/properties/sheetId")
+                    .and_then(|v| v.as_i64())
+                    .map(|v| v.to_string())
+                    == Some(gid.to_owned())
+            })
+            .ok_or_else(|| format!("no sheet found with gid {}", gid))?,
+        None => sheets.first().ok_or("spreadsheet has no sheets")?,
+    };
+    sheet
+        .pointer("/properties/gridProperties/rowCount")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| "sheet is missing 'gridProperties.rowCount'".to_owned())
+}
+
+// introspect = 'rowcount'（api_mode = 'v4'、rowcount_include_trailing_blanks = 'false'（既定））用に、
+// gvizの"select count(A)"相当のことをv4のvalues.getで行う。対象シートのA列だけを読み、
+// 空文字/欠損セルを除いた実際に値の入っている行数を数える。gvizと違いv4にはcount()のような
+// 集計クエリが無いため、A列という最小限のデータだけを読んで自前で数える。
+fn fetch_v4_column_a_non_blank_count(
+    spread_sheet_id: &str,
+    access_token: &str,
+    sheet_title: &str,
+    query_params: &[(String, String)],
+) -> Result<i64, FdwError> {
+    let url = format!(
+        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}!A:A?valueRenderOption=UNFORMATTED_VALUE",
+        spread_sheet_id,
+        gviz_url_encode(sheet_title),
+    );
+    let req = http::Request {
+        method: http::Method::Get,
+        url,
+        headers: vec![("authorization".to_owned(), format!("Bearer {}", access_token))],
+        body: String::default(),
+    };
+    let resp = get_following_redirects(req, query_params)?;
+    let json: JsonValue = serde_json::from_str(&resp.body).map_err(|e| e.to_string())?;
+    let count = json
+        .get("values")
+        .and_then(|v| v.as_array())
+        .map(|rows| {
+            rows.iter()
+                .filter(|row| {
+                    row.as_array()
+                        .and_then(|cells| cells.first())
+                        .map(|cell| !matches!(cell, JsonValue::Null) && cell.as_str() != Some(""))
+                        .unwrap_or(false)
+                })
+                .count()
+        })
+        .unwrap_or(0);
+    Ok(count as i64)
+}
+
+// sheet_index（0始まり、シートが並ぶ順＝タブの表示順）で指定されたN番目のシートのgidを解決する。
+// 範囲外のindexが指定された場合は、実際のシート数を含めたエラーを返す。
+fn resolve_sheet_id_by_index(
+    spread_sheet_id: &str,
+    access_token: &str,
+    index: usize,
+    query_params: &[(String, String)],
+) -> Result<String, FdwError> {
+    let resp_json = fetch_v4_spreadsheet(spread_sheet_id, access_token, "sheets.properties(sheetId)", None, query_params)?;
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = sheets.get(index).ok_or_else(|| {
+        format!(
+            "sheet_index {} is out of range: spreadsheet has {} sheet(s)",
+            index,
+            sheets.len()
+        )
+    })?;
+    sheet
+        .pointer("/properties/sheetId")
+        .and_then(|v| v.as_i64())
+        .map(|v| v.to_string())
+        .ok_or_else(|| "sheet is missing 'sheetId'".to_owned())
+}
+
+// filter_view_id テーブルオプションで指定されたフィルタビューのgridRangeを、rangesパラメータに
+// そのまま渡せるA1形式（"シートタイトル!A2:D10"）へ変換する。フィルタビューはスプレッドシート内の
+// どのシートにあるか事前にわからないため、全シートのfilterViewsを横断して探す。gridRangeの
+// endIndexは半開区間（対象範囲を含まない）なので、A1形式へ変換する際に-1する。行/列の
+// startIndex/endIndexが省略された場合はシート全体（0/シートの行数・列数）を意味するが、
+// このFDWは事前に行数・列数を把握していないため、省略時は該当軸を無指定（シート全体）のままにする。
+fn resolve_filter_view_range(
+    spread_sheet_id: &str,
+    access_token: &str,
+    filter_view_id: &str,
+    query_params: &[(String, String)],
+) -> Result<String, FdwError> {
+    let resp_json = fetch_v4_spreadsheet(
+        spread_sheet_id,
+        access_token,
+        "sheets.properties.title,sheets.filterViews(filterViewId,range)",
+        None,
+        query_params,
+    )?;
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    for sheet in sheets {
+        let title = sheet.pointer("/properties/title").and_then(|v| v.as_str()).unwrap_or_default();
+        let Some(filter_views) = sheet.get("filterViews").and_then(|v| v.as_array()) else {
+            continue;
+        };
+        for filter_view in filter_views {
+            let id = filter_view
+                .get("filterViewId")
+                .and_then(|v| v.as_i64())
+                .map(|v| v.to_string());
+            if id.as_deref() != Some(filter_view_id) {
+                continue;
+            }
+            let range = filter_view.get("range").ok_or("filter view has no range")?;
+            let mut a1 = title.to_owned();
+            if let Some(start_col) = range.get("startColumnIndex").and_then(|v| v.as_i64()) {
+                let start_row = range.get("startRowIndex").and_then(|v| v.as_i64()).unwrap_or(0);
+                a1.push('!');
+                a1.push_str(&column_index_to_letters(start_col as usize));
+                a1.push_str(&(start_row + 1).to_string());
+                if let Some(end_col) = range.get("endColumnIndex").and_then(|v| v.as_i64()) {
+                    a1.push(':');
+                    a1.push_str(&column_index_to_letters((end_col - 1) as usize));
+                    if let Some(end_row) = range.get("endRowIndex").and_then(|v| v.as_i64()) {
+                        a1.push_str(&end_row.to_string());
+                    }
+                }
+            }
+            return Ok(a1);
+        }
+    }
+    Err(format!("no filter view found with filter_view_id {}", filter_view_id))
+}
+
+// introspect = 'protected_ranges' 用に、v4のgridRange（{startRowIndex,endRowIndex,
+// startColumnIndex,endColumnIndex}）をA1形式（"シートタイトル!A1:C10"）へ変換する。
+// rangeがNone（シート全体が保護されている場合、gridRangeそのものが省略される）の場合は
+// タイトルのみを返す。endIndexは半開区間（対象範囲を含まない）なので、A1形式へ変換する際に-1する。
+fn protected_range_to_a1(title: &str, range: Option<&JsonValue>) -> String {
+    let Some(range) = range else {
+        return title.to_owned();
+    };
+    let mut a1 = title.to_owned();
+    if let Some(start_col) = range.get("startColumnIndex").and_then(|v| v.as_i64()) {
+        let start_row = range.get("startRowIndex").and_then(|v| v.as_i64()).unwrap_or(0);
+        a1.push('!');
+        a1.push_str(&column_index_to_letters(start_col as usize));
+        a1.push_str(&(start_row + 1).to_string());
+        if let Some(end_col) = range.get("endColumnIndex").and_then(|v| v.as_i64()) {
+            a1.push(':');
+            a1.push_str(&column_index_to_letters((end_col - 1) as usize));
+            if let Some(end_row) = range.get("endRowIndex").and_then(|v| v.as_i64()) {
+                a1.push_str(&end_row.to_string());
+            }
+        }
+    }
+    a1
+}
+
+// [start, start + page_size) 行目（0始まり、ヘッダー行を含めた全行が対象）をrangesで絞り込んで
+// 1ページ分だけ取得する。
+fn fetch_v4_page(
+    spread_sheet_id: &str,
+    access_token: &str,
+    sheet_title: &str,
+    start: usize,
+    page_size: usize,
+    query_params: &[(String, String)],
+) -> Result<Vec<Vec<V4Cell>>, FdwError> {
+    let range = format!("{}!{}:{}", sheet_title, start + 1, start + page_size);
+    let fields = "sheets.data.rowData.values(formattedValue,note,hyperlink,userEnteredFormat.backgroundColor,userEnteredFormat.numberFormat.pattern,userEnteredValue.formulaValue,effectiveValue,dataValidation)";
+    let resp_json = fetch_v4_spreadsheet(spread_sheet_id, access_token, fields, Some(&range), query_params)?;
+    // rangesで既に対象シートへ絞り込まれているため、レスポンスのsheetsは1件のみ想定で先頭を使う。
+    extract_v4_rows(&resp_json, None)
+}
+
+// v4のColor型（red/green/blue、各0.0〜1.0の浮動小数点。省略されたチャンネルは0扱い）を
+// "#rrggbb"形式の16進数文字列に変換する。
+fn rgb_json_to_hex_color(color: &JsonValue) -> String {
+    let channel = |name: &str| -> u8 {
+        let v = color.get(name).and_then(|v| v.as_f64()).unwrap_or(0.0).clamp(0.0, 1.0);
+        (v * 255.0).round() as u8
+    };
+    format!("#{:02x}{:02x}{:02x}", channel("red"), channel("green"), channel("blue"))
+}
+
+// value_render = 'unformatted' 向けに、effectiveValue（number/string/boolのoneof）を文字列化する。
+// 数式セルの場合effectiveValueは計算結果であり、formulaValueとは別物。
+fn v4_cell_unformatted_value(cell: &JsonValue) -> Option<String> {
+    let effective_value = cell.get("effectiveValue")?;
+    if let Some(v) = effective_value.get("numberValue").and_then(|v| v.as_f64()) {
+        Some(format_f64_without_scientific_notation(v))
+    } else if let Some(v) = effective_value.get("stringValue").and_then(|v| v.as_str()) {
+        Some(v.to_owned())
+    } else if let Some(v) = effective_value.get("boolValue").and_then(|v| v.as_bool()) {
+        Some(v.to_string())
+    } else {
+        None
+    }
+}
+
+// v4レスポンスから、対象シート（gid指定があればそれ、なければ先頭シート）の行データをV4Cellに変換する。
+fn extract_v4_rows(resp_json: &JsonValue, sheet_id: Option<&str>) -> Result<Vec<Vec<V4Cell>>, FdwError> {
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = match sheet_id {
+        Some(gid) => sheets
+            .iter()
+            .find(|s| {
+                s.pointer("/properties/sheetId")
+                    .and_then(|v| v.as_i64())
+                    .map(|v| v.to_string())
+                    == Some(gid.to_owned())
+            })
+            .ok_or_else(|| format!("no sheet found with gid {}", gid))?,
+        None => sheets.first().ok_or("spreadsheet has no sheets")?,
+    };
+    let row_data = sheet
+        .pointer("/data/0/rowData")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    Ok(row_data
+        .iter()
+        .map(|row| {
+            row.get("values")
+                .and_then(|v| v.as_array())
+                .cloned()
+                .unwrap_or_default()
+                .iter()
+                .map(|cell| V4Cell {
+                    formatted_value: cell.get("formattedValue").and_then(|v| v.as_str()).map(str::to_owned),
+                    note: cell.get("note").and_then(|v| v.as_str()).map(str::to_owned),
+                    hyperlink: cell.get("hyperlink").and_then(|v| v.as_str()).map(str::to_owned),
+                    background_color: cell
+                        .pointer("/userEnteredFormat/backgroundColor")
+                        .map(rgb_json_to_hex_color),
+                    formula: cell
+                        .pointer("/userEnteredValue/formulaValue")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_owned),
+                    unformatted_value: v4_cell_unformatted_value(cell),
+                    data_validation: cell.get("dataValidation").cloned(),
+                    number_format_pattern: cell
+                        .pointer("/userEnteredFormat/numberFormat/pattern")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_owned),
+                })
+                .collect()
+        })
+        .collect())
+}
+
+// 結合セルの1範囲（0始まり、開始含む・終了含まない半開区間。v4メタデータのmergesと同じ表現）。
+#[derive(Debug, Clone, Copy)]
+struct MergeRange {
+    start_row: usize,
+    end_row: usize,
+    start_col: usize,
+    end_col: usize,
+}
+
+// v4レスポンスから、対象シート（gid指定があればそれ、なければ先頭シート）の結合範囲一覧を取り出す。
+// fields=sheets.mergesを付けてfetch_v4_spreadsheetした場合にのみ存在する。
+fn extract_v4_merges(resp_json: &JsonValue, sheet_id: Option<&str>) -> Result<Vec<MergeRange>, FdwError> {
+    let sheets = resp_json
+        .get("sheets")
+        .and_then(|v| v.as_array())
+        .ok_or("v4 response is missing 'sheets'")?;
+    let sheet = match sheet_id {
+        Some(gid) => sheets
+            .iter()
+            .find(|s| {
+                s.pointer("/properties/sheetId")
+                    .and_then(|v| v.as_i64())
+                    .map(|v| v.to_string())
+                    == Some(gid.to_owned())
+            })
+            .ok_or_else(|| format!("no sheet found with gid {}", gid))?,
+        None => sheets.first().ok_or("spreadsheet has no sheets")?,
+    };
+    Ok(sheet
+        .get("merges")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|m| {
+            Some(MergeRange {
+                start_row: m.get("startRowIndex")?.as_i64()? as usize,
+                end_row: m.get("endRowIndex")?.as_i64()? as usize,
+                start_col: m.get("startColumnIndex")?.as_i64()? as usize,
+                end_col: m.get("endColumnIndex")?.as_i64()? as usize,
+            })
+        })
+        .collect())
+}
+
+// fill_merged = 'true' のとき、各結合範囲の左上セルの値を範囲内の全セル（左上自身を含む）へ
+// コピーする。v4のvaluesレスポンスは結合セルのうち左上以外を空にするため、そのままでは
+// nullとして読めてしまう値を埋める。
+fn fill_merged_cells(rows: &mut Vec<Vec<V4Cell>>, merges: &[MergeRange]) {
+    for m in merges {
+        let top_left = rows
+            .get(m.start_row)
+            .and_then(|r| r.get(m.start_col))
+            .cloned()
+            .unwrap_or_default();
+        for row_idx in m.start_row..m.end_row {
+            if rows.len() <= row_idx {
+                rows.resize(row_idx + 1, Vec::new());
+            }
+            let row = &mut rows[row_idx];
+            for col_idx in m.start_col..m.end_col {
+                if row.len() <= col_idx {
+                    row.resize(col_idx + 1, V4Cell::default());
+                }
+                row[col_idx] = top_left.clone();
+            }
+        }
+    }
+}
+
+// v4モードの1行分（V4Cellの配列）を対象カラムへ変換してrowへpushする。全行バッファ/ページング
+// いずれのモードでも共通で使う。
+fn push_v4_row(ctx: &Context, row: &Row, this: &SpreadsheetsFdw, src_row: &[V4Cell]) -> Result<(), FdwError> {
+    for tgt_col in ctx.get_columns() {
+        let (tgt_col_num, tgt_col_name) = (tgt_col.num(), tgt_col.name());
+        let v4_cell = src_row.get((tgt_col_num - 1) as usize);
+        let hyperlink_src_col = this
+            .hyperlink_of
+            .iter()
+            .find(|(this_col, _)| this_col == &tgt_col_name)
+            .map(|(_, other_col)| other_col.as_str());
+        let color_src_col = this
+            .color_of
+            .iter()
+            .find(|(this_col, _)| this_col == &tgt_col_name)
+            .map(|(_, other_col)| other_col.as_str());
+        let validation_src_col = this
+            .validation_of
+            .iter()
+            .find(|(this_col, _)| this_col == &tgt_col_name)
+            .map(|(_, other_col)| other_col.as_str());
+        let format_src_col = this
+            .format_of
+            .iter()
+            .find(|(this_col, _)| this_col == &tgt_col_name)
+            .map(|(_, other_col)| other_col.as_str());
+        let cell = if let Some(c) = constant_cell_for_column(&tgt_col, &this.constant_columns)? {
+            Some(c)
+        } else {
+            match tgt_col.type_oid() {
+            TypeOid::String => {
+                if let Some(other_col) = format_src_col {
+                    let other_num = ctx
+                        .get_columns()
+                        .iter()
+                        .find(|c| c.name() == other_col)
+                        .map(|c| c.num())
+                        .ok_or_else(|| format!("format_of references unknown column '{}'", other_col))?;
+                    src_row
+                        .get((other_num - 1) as usize)
+                        .and_then(|c| c.number_format_pattern.clone())
+                        .map(Cell::String)
+                } else if let Some(other_col) = hyperlink_src_col {
+                    let other_num = ctx
+                        .get_columns()
+                        .iter()
+                        .find(|c| c.name() == other_col)
+                        .map(|c| c.num())
+                        .ok_or_else(|| format!("hyperlink_of references unknown column '{}'", other_col))?;
+                    src_row
+                        .get((other_num - 1) as usize)
+                        .and_then(|c| c.hyperlink.clone())
+                        .map(Cell::String)
+                } else if let Some(other_col) = color_src_col {
+                    let other_num = ctx
+                        .get_columns()
+                        .iter()
+                        .find(|c| c.name() == other_col)
+                        .map(|c| c.num())
+                        .ok_or_else(|| format!("color_of references unknown column '{}'", other_col))?;
+                    src_row
+                        .get((other_num - 1) as usize)
+                        .and_then(|c| c.background_color.clone())
+                        .map(Cell::String)
+                } else if this.note_of_columns.iter().any(|c| c == &tgt_col_name) {
+                    v4_cell.and_then(|c| c.note.clone()).map(Cell::String)
+                } else {
+                    let rendered = match this.value_render.as_str() {
+                        "formula" => v4_cell.and_then(|c| c.formula.clone().or_else(|| c.formatted_value.clone())),
+                        "unformatted" => {
+                            v4_cell.and_then(|c| c.unformatted_value.clone().or_else(|| c.formatted_value.clone()))
+                        }
+                        _ => v4_cell.and_then(|c| c.formatted_value.clone()),
+                    };
+                    rendered
+                        .filter(|v| !is_null_sentinel(v, &this.null_strings))
+                        .map(Cell::String)
+                }
+            }
+            TypeOid::I64 => v4_cell
+                .and_then(|c| c.formatted_value.as_deref())
+                .filter(|v| !is_null_sentinel(v, &this.null_strings))
+                .and_then(|s| s.parse::<i64>().ok())
+                .map(Cell::I64),
+            TypeOid::Json => {
+                let other_col = validation_src_col.ok_or_else(|| {
+                    format!("column {} is jsonb but is not configured via validation_of", tgt_col_name)
+                })?;
+                let other_num = ctx
+                    .get_columns()
+                    .iter()
+                    .find(|c| c.name() == other_col)
+                    .map(|c| c.num())
+                    .ok_or_else(|| format!("validation_of references unknown column '{}'", other_col))?;
+                src_row
+                    .get((other_num - 1) as usize)
+                    .and_then(|c| c.data_validation.clone())
+                    .map(|v| Cell::Json(v.to_string()))
+            }
+            _ => {
+                return Err(format!(
+                    "column {} data type is not supported in v4 mode",
+                    tgt_col_name
+                ));
+            }
+            }
+        };
+        let cell = apply_default_cell(
+            cell,
+            &tgt_col,
+            &this.default_columns,
+            this.default_number.as_deref(),
+            this.default_text.as_deref(),
+            this.default_bool.as_deref(),
+        )?;
+        row.push(cell.as_ref());
+    }
+    Ok(())
+}
+
+// ⭐️ここまで Google Sheets API v4 対応
+
+// gvizが返す列の型（"string"/"number"/"boolean"/"date"/"datetime"/"timeofday"）を、
+// IMPORT FOREIGN SCHEMA相当の道具が使えるようPostgresの型名にマッピングする。
+fn gviz_type_to_pg_type(gviz_type: &str) -> &'static str {
+    match gviz_type {
+        "number" => "numeric",
+        "boolean" => "boolean",
+        "date" => "date",
+        "datetime" => "timestamp",
+        "timeofday" => "text",
+        _ => "text",
+    }
+}
+
+// spread_sheet_id / sheet_id はそのままURLに埋め込まれるため、想定外の文字（`?`, `&`, `/`, 空白など）を
+// 混入させるとクエリパラメータやパスセグメントの注入につながる。
+// Googleのスプレッドシート/シートIDは英数字・`-`・`_`のみで構成されるため、それ以外の文字を拒否する。
+fn validate_spread_sheet_id(id: &str) -> Result<(), FdwError> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid spread_sheet_id '{}': only ASCII letters, digits, '-' and '_' are allowed",
+            id
+        ))
+    }
+}
+
+// sheet_id はgvizの `gid` パラメータに渡されるシートID（タブの並び順ではない）で、数字のみを想定している。
+fn validate_sheet_id(id: &str) -> Result<(), FdwError> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(format!("invalid sheet_id '{}': only digits are allowed", id))
+    }
+}
+
+// v4専用オプション群（note_of_columns/hyperlink_of/color_of/validation_of/value_render/
+// sheet_index/filter_view_id/page_size/fill_merged/skip_frozen_rows/ranges）が、選ばれた
+// api_modeや互いの併用可否と矛盾していないかをまとめて検証する。begin_scanが1つずつ
+// require_or/get + return Errしていた頃は、利用者が設定ミスを1件直しては再実行し、
+// 次のエラーに当たってまた直す……を繰り返す羽目になっていたため、見つかった問題を
+// 全て集めて1回のエラーで返す。
+fn validate_v4_only_options(
+    api_mode: &str,
+    has_service_account: bool,
+    note_of_columns_set: bool,
+    hyperlink_of_set: bool,
+    color_of_set: bool,
+    validation_of_set: bool,
+    value_render: &str,
+    sheet_index_set: bool,
+    filter_view_id_set: bool,
+    start_cell_set: bool,
+    page_size: usize,
+    fill_merged: bool,
+    skip_frozen_rows: bool,
+    ranges_set: bool,
+    skip_rows_set: bool,
+    reverse_rows: bool,
+) -> Result<(), FdwError> {
+    let is_v4 = api_mode == "v4";
+    let mut problems = Vec::new();
+    if is_v4 && !has_service_account {
+        problems.push("api_mode = 'v4' requires a 'service_account' server option".to_owned());
+    }
+    if note_of_columns_set && !is_v4 {
+        problems.push("note_of_columns requires api_mode = 'v4' (notes are not available via gviz)".to_owned());
+    }
+    if hyperlink_of_set && !is_v4 {
+        problems.push("hyperlink_of requires api_mode = 'v4' (hyperlinks are not available via gviz)".to_owned());
+    }
+    if color_of_set && !is_v4 {
+        problems.push("color_of requires api_mode = 'v4' (background color is not available via gviz)".to_owned());
+    }
+    if validation_of_set && !is_v4 {
+        problems.push("validation_of requires api_mode = 'v4' (data validation rules are not available via gviz)".to_owned());
+    }
+    if value_render != "formatted" && !is_v4 {
+        problems.push(format!(
+            "value_render = '{}' requires api_mode = 'v4' (gviz only exposes formatted display values)",
+            value_render
+        ));
+    }
+    if sheet_index_set && !is_v4 {
+        problems.push("sheet_index requires api_mode = 'v4' (tab-order lookup is only implemented for the v4 API)".to_owned());
+    }
+    if filter_view_id_set && !is_v4 {
+        problems.push("filter_view_id requires api_mode = 'v4' (filter views are not exposed via gviz)".to_owned());
+    }
+    if filter_view_id_set && start_cell_set {
+        problems.push("filter_view_id cannot be combined with start_cell (the filter view already defines its range)".to_owned());
+    }
+    if page_size > 0 && !is_v4 {
+        problems.push("page_size requires api_mode = 'v4' (pagination is only implemented for the v4 API)".to_owned());
+    }
+    if filter_view_id_set && page_size > 0 {
+        problems.push("filter_view_id cannot be combined with page_size (pagination is not implemented for filter view ranges)".to_owned());
+    }
+    if fill_merged && !is_v4 {
+        problems.push("fill_merged requires api_mode = 'v4' (merge ranges are not exposed via gviz)".to_owned());
+    }
+    if fill_merged && page_size > 0 {
+        problems.push("fill_merged cannot be combined with page_size (a merged region can span page boundaries)".to_owned());
+    }
+    if fill_merged && start_cell_set {
+        problems.push("fill_merged cannot be combined with start_cell (merge ranges are reported relative to the whole sheet)".to_owned());
+    }
+    if skip_frozen_rows && !is_v4 {
+        problems.push("skip_frozen_rows requires api_mode = 'v4' (frozenRowCount is not exposed via gviz)".to_owned());
+    }
+    if skip_frozen_rows && start_cell_set {
+        problems.push("skip_frozen_rows cannot be combined with start_cell".to_owned());
+    }
+    if skip_frozen_rows && filter_view_id_set {
+        problems.push("skip_frozen_rows cannot be combined with filter_view_id".to_owned());
+    }
+    if ranges_set && !is_v4 {
+        problems.push("ranges requires api_mode = 'v4' (multiple ranges are not exposed via gviz)".to_owned());
+    }
+    if ranges_set && filter_view_id_set {
+        problems.push("ranges cannot be combined with filter_view_id".to_owned());
+    }
+    if ranges_set && start_cell_set {
+        problems.push("ranges cannot be combined with start_cell (each entry in ranges is already a full A1 range)".to_owned());
+    }
+    if ranges_set && skip_frozen_rows {
+        problems.push("ranges cannot be combined with skip_frozen_rows".to_owned());
+    }
+    if ranges_set && page_size > 0 {
+        problems.push("ranges cannot be combined with page_size (pagination is not implemented for multi-range reads)".to_owned());
+    }
+    if skip_rows_set && page_size > 0 {
+        problems.push("skip_rows cannot be combined with page_size (a global skip can't be applied to a stream of on-demand pages)".to_owned());
+    }
+    if reverse_rows && page_size > 0 {
+        problems.push("reverse_rows cannot be combined with page_size (reversing requires the whole sheet, defeating the point of pagination)".to_owned());
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} option conflict(s) found:\n- {}", problems.len(), problems.join("\n- ")))
+    }
+}
+
+// SpreadsheetsFdw構造体に対してGuestトレイトを実装しています。
+// GuestトレイトはFDWの各種操作に対応するためのインターフェースを提供しており、
+// これにより外部データソースをPostgreSQLに統合するための機能を定義します。以下に、各メソッドの説明を示します。
+impl Guest for SpreadsheetsFdw {
+    fn host_version_requirement() -> String {
+        // semver expression for Wasm FDW host version requirement
+        // ref: https://docs.rs/semver/latest/semver/enum.Op.html
+        //
+        // ビルド時に環境変数 FDW_HOST_VERSION_REQUIREMENT が設定されていればそれを使い、
+        // 無ければ既定の "^0.1.0" を使う。ワッパーズホストが新しい（が互換性のある）
+        // バージョンを要求する環境でも、このファイルを編集せずに再ビルドだけで追従できるようにする。
+        option_env!("FDW_HOST_VERSION_REQUIREMENT")
+            .unwrap_or("^0.1.0")
+            .to_string()
+    }
+
+    // 初期化
+    fn init(ctx: &Context) -> FdwResult {
+        Self::init_instance();
+        let this = Self::this_mut();
+        // 外部サーバーオプションからAPI URLを取得する（指定されている場合）
+        let opts = ctx.get_options(OptionsType::Server);
+
+        this.base_url = opts.require_or("base_url", "https://docs.google.com/spreadsheets/d");
+        // gviz_path で、gvizエンドポイントのIDとクエリ文字列の間に挟むパスセグメントを差し替える。
+        // 先頭にスラッシュを含めると意図せず絶対パス扱いになり、クエリ文字列を含めると
+        // 後続の"?tqx=..."と衝突するため、どちらも拒否する。
+        this.gviz_path = opts.require_or("gviz_path", "gviz/tq");
+        if this.gviz_path.starts_with('/') {
+            return Err("gviz_path must not start with a leading slash".to_owned());
+        }
+        if this.gviz_path.contains('?') {
+            return Err("gviz_path must not contain a query string".to_owned());
+        }
+        // サーバーオプションの http_headers は、全テーブル共通のデフォルトヘッダーとして扱う。
+        this.server_headers = parse_http_headers_option(opts.get("http_headers").as_deref())?;
+        // query_params は、プロキシ等が要求する固定のトークン/トラッキングパラメータを、FDWが
+        // 組み立てる全リクエストURLへ末尾から付与するためのオプション。http_headersと同じく
+        // built-in（FDW自身のtqx/tq等） < server < table の優先順でマージするが、FDW自身の
+        // パラメータは常に最優先（=上書きされない）である点がheadersと違う（append_query_params参照）。
+        this.server_query_params = parse_query_params_option(opts.get("query_params").as_deref())?;
+        // api_mode = 'v4' を使うテーブルのための認証情報。JSON文字列のまま保持し、実際の利用時にパースする。
+        this.service_account_json = opts.get("service_account");
+        // トークンエンドポイントが一時的に落ちていてもスキャン全体を巻き込んで失敗させないための
+        // リトライ回数。データ取得側のリトライとは独立に設定できる。
+        this.auth_max_retries = opts.require_or("auth_max_retries", "3").parse().unwrap_or(3);
+        Ok(())
+    }
+
+    // データスキャンの開始時に行う準備作業を担当します。具体的には、ソースデータの取得や初期化処理などを行います。
+    fn begin_scan(ctx: &Context) -> FdwResult {
+        let this = Self::this_mut();
+         // ↓ SQLのスキーマで渡されたoptionの値を読み込む。
+         let opts = ctx.get_options(OptionsType::Table);
+
+         // scan_deadline_ms（既定0=無効）で、begin_scan開始からの累積経過時間に
+         // ハードな上限を設ける。以降のリトライ・ページングを含む全てのネットワークI/O直前で
+         // check_scan_deadline()を呼び、超過していれば即座にエラーで打ち切る。
+         let scan_deadline_ms: u64 = opts.require_or("scan_deadline_ms", "0").parse().unwrap_or(0);
+         start_scan_deadline(scan_deadline_ms);
+
+         // partial_ok = 'true'（既定false）で、v4モードのpage_sizeページングが次ページの取得中に
+         // scan_deadline_msの超過やHTTPエラーで打ち切られた場合、そこで即座にエラー終了する代わりに
+         // それまでに取得済みの行だけを返してスキャンを終える。report_infoでどこまで取れたかを
+         // 警告として出す。ダッシュボードのように「正確だが失敗」より「概算でも速い」方が望ましい
+         // 用途向け。
+         this.partial_ok = opts.require_or("partial_ok", "false") == "true";
+
+         // transport_max_retries（既定0）/ status_max_retries（既定0）で、DNS/TLSのような接続層の
+         // エラーとHTTPステータスエラー（5xx）とを別々にリトライできるようにする。操作者が
+         // 一過性のネットワーク不調には強気に、サーバー側の障害には控えめにリトライ回数を
+         // 分けて調整できる、基本のリトライ機能をより細かくした版。
+         let transport_max_retries: u32 = opts.require_or("transport_max_retries", "0").parse().unwrap_or(0);
+         let status_max_retries: u32 = opts.require_or("status_max_retries", "0").parse().unwrap_or(0);
+         // total_retry_budget（既定0=無制限）は、上記の個別リトライ上限とは別に、この1回の
+         // begin_scan中に発生する全てのリトライ（gvizのページ取得、v4のページネーション/複数シート
+         // 取得、認証トークンの再取得を含む）を横断した累計回数の上限を設ける。ページネーションや
+         // 複数シートにまたがるほど個々のリトライ上限が掛け算式に積み重なってしまうのを、
+         // オペレーターが1つのノブで頭打ちにできるようにするための安全弁。使い切ると、それ以降の
+         // 一過性エラーは即座に諦めて表面化する。
+         let total_retry_budget: u32 = opts.require_or("total_retry_budget", "0").parse().unwrap_or(0);
+         start_retry_policy(transport_max_retries, status_max_retries, total_retry_budget);
+
+         // query_params: server < table の優先順でマージする（built-inのFDW自身のtqx/tq等の
+         // パラメータには一切触れない。append_query_params側で既存キーを常に優先するため十分）。
+         // introspectの各モードを含め、このFDWが組み立てる全てのリクエストで使い回すため、
+         // begin_scanの早い段階、他の分岐に入る前に確定させておく。
+         let table_query_params = parse_query_params_option(opts.get("query_params").as_deref())?;
+         this.query_params = merge_headers(&[this.server_query_params.clone(), table_query_params]);
+
+         // introspect = 'stats' は、特定のスプレッドシートとは無関係に、このFDWインスタンスが
+         // 生存している間に蓄積した運用統計カウンタ（Statsシングルトン）を1行だけ返すモード。
+         // 他のintrospectモードと違いspread_sheet_idを必要としない。reset = 'true'を併せて
+         // 指定すると、このスナップショットを返した直後にカウンタをゼロへ戻す
+         // （＝「集計期間」をSQL側のクエリタイミングで区切れるようにする）。
+         if opts.get("introspect").as_deref() == Some("stats") {
+             let snapshot = *stats();
+             if opts.require_or("reset", "false") == "true" {
+                 *stats() = Stats::default();
+             }
+             this.introspect_stats = Some(snapshot);
+             report_info("introspect stats mode: returning accumulated instrumentation counters");
+             return Ok(());
+         }
+         this.introspect_stats = None;
+         stats().total_scans += 1;
+
+         // introspect = 'version' も 'stats' 同様にspread_sheet_idを必要としない。
+         // デプロイされているwasmがどのビルドか、どのソースモード/型をサポートしているかを
+         // 問い合わせるための、運用者向けのヘルスチェック用モード。
+         if opts.get("introspect").as_deref() == Some("version") {
+             this.introspect_version = true;
+             report_info("introspect version mode: returning build/version metadata");
+             return Ok(());
+         }
+         this.introspect_version = false;
+
+         // spread_sheet_ids（カンマ区切り）が指定されていれば、同じスキーマを持つ複数の
+         // スプレッドシートファイルを1つの外部テーブルとして読む。無指定なら従来通り単一の
+         // spread_sheet_id を使う。
+         let spread_sheet_ids: Vec<String> = match opts.get("spread_sheet_ids") {
+             Some(ids) => parse_column_list_option(Some(&ids)),
+             None => vec![opts.require("spread_sheet_id")?],
+         };
+         if spread_sheet_ids.is_empty() {
+             return Err("spread_sheet_ids must contain at least one id".to_owned());
+         }
+         let spread_sheet_id = spread_sheet_ids[0].clone();
+         let mut sheet_id = opts.get("sheet_id");
+
+         // URLへ埋め込む前に、注入に使われうる文字が含まれていないか検証する。
+         for id in &spread_sheet_ids {
+             validate_spread_sheet_id(id)?;
+         }
+         if let Some(sheet_id) = &sheet_id {
+             validate_sheet_id(sheet_id)?;
+         }
+
+         let api_mode = opts.require_or("api_mode", "gviz");
+         // note_of_columns/hyperlink_of/color_of/validation_of/value_render/sheet_index/
+         // filter_view_id/page_size/fill_merged/skip_frozen_rows/rangesはいずれもv4専用（一部は
+         // 互いに排他）のオプション群で、以前はここで1つずつrequire_or/get + return Errしていたため、
+         // 利用者が「直しては再実行」を何度も繰り返す羽目になっていた。validate_v4_only_optionsで
+         // まとめて検証し、api_modeの選択に対して矛盾/不足しているオプションを1回のエラーで
+         // 全て報告する。
+         let note_of_columns = parse_column_list_option(opts.get("note_of_columns").as_deref());
+         // hyperlink_of = 'label_col=url_col,...' の形で、label_col自身の値の代わりに
+         // url_colと同じソース列のhyperlinkを返す列を指定する（=HYPERLINK()のURLを取り出す用途）。
+         let hyperlink_of = parse_column_pair_list_option(opts.get("hyperlink_of").as_deref())?;
+         // color_of = 'status_col=src_col,...' の形で、status_col自身の値の代わりに
+         // src_colと同じソース列の背景色（"#rrggbb"）を返す列を指定する（色分けによる状態表現を
+         // SQLから読めるようにする用途）。
+         let color_of = parse_column_pair_list_option(opts.get("color_of").as_deref())?;
+         // validation_of = 'rule_col=src_col,...' の形で、rule_col自身の値の代わりにsrc_colと同じ
+         // ソース列のdataValidationルール（プルダウンの選択肢、数値範囲等）をjsonbとして返す列を
+         // 指定する（フォーム的なシートの入力制約をそのまま下流へミラーする用途）。この列は
+         // TypeOid::Jsonで定義する必要がある。
+         let validation_of = parse_column_pair_list_option(opts.get("validation_of").as_deref())?;
+         // value_render = 'formatted'（既定）| 'formula' | 'unformatted'。String列のデフォルトの
+         // 値の出どころを切り替える（hyperlink_of/color_of/note_of_columnsのような列単位の
+         // 上書きより後、formatted_valueフォールバックより前に評価される）。
+         let value_render = opts.require_or("value_render", "formatted");
+         if !["formatted", "formula", "unformatted"].contains(&value_render.as_str()) {
+             return Err(format!(
+                 "invalid value_render option '{}' (expected 'formatted', 'formula', or 'unformatted')",
+                 value_render
+             ));
+         }
+         // sheet_index（0始まり）で、gidやタイトルを知らなくてもタブの並び順でシートを指定できる
+         // ようにする。解決にv4 APIの追加呼び出しが必要なためv4モード専用。sheet_idが明示されて
+         // いればそちらが優先され、sheet_indexは無視される（実際の解決はapi_mode='v4'の分岐内で
+         // アクセストークン取得後に行う）。なお本FDWにはsheet_nameオプションは存在せず、シートは
+         // gid（sheet_id）かタブ位置（sheet_index）のいずれかでのみ選択できる。
+         let sheet_index: Option<usize> = match opts.get("sheet_index") {
+             Some(v) => Some(
+                 v.parse()
+                     .map_err(|_| "sheet_index must be a non-negative integer".to_owned())?,
+             ),
+             None => None,
+         };
+         // filter_view_id で、シート上に保存済みのフィルタビュー（範囲/並び替え/フィルタ条件の
+         // プリセット）が定義する範囲だけを読む。gvizのtqクエリ言語と違い、フィルタビューの
+         // 存在自体がv4のsheets.filterViewsメタデータでしか取得できないためv4モード専用。
+         // 対象範囲の絞り込みという点でstart_cellと重なるため併用できない。
+         let filter_view_id = opts.get("filter_view_id");
+         let start_cell_set = opts.get("start_cell").is_some();
+         // page_size = 'N' で、v4モードのスキャンを「全行バッファ」から「1ページずつ遅延取得」に
+         // 切り替える（巨大なシートでもメモリ使用量を1ページ分に抑えるため）。メモリ削減だけでなく
+         // 体感レイテンシ（TTFB）の保証でもある: begin_scanは最初の1ページだけを取得し、
+         // iter_scanはそのページの行を即座に返し始める。全ページ分のフェッチを待ってから
+         // 最初の行を返すことはない。2ページ目以降は、現在のページを使い切った時点でオンデマンドに
+         // 取得する（iter_scan_inner内のthis.v4_page_size > 0の分岐を参照）。
+         // 注意: これは「途中で失敗しうる」設計でもある。後続ページの取得がエラーになった場合、
+         // それまでに返した行はそのままに、スキャンの途中でエラーが表面化する
+         // （partial_ok = 'true'ならエラーにせずそこまでの行で打ち切る。partial_okのドキュメント参照）。
+         let page_size: usize = match opts.get("page_size") {
+             Some(v) => v
+                 .parse()
+                 .map_err(|_| "page_size must be a non-negative integer".to_owned())?,
+             None => 0,
+         };
+         // fill_merged = 'true' で、結合セル（v4のvaluesレスポンスでは左上以外が空になる）を
+         // 左上セルの値で埋める。結合範囲はv4メタデータ（sheets.merges）からしか取得できない
+         // ためv4モード専用。page_size（ページングで結合範囲が分断され得る）やstart_cell
+         // （メタデータの結合範囲はシート全体基準だが、その場合のrowDataは絞り込んだ範囲しか
+         // 返らず添字がずれる）とは現状併用できない。
+         let fill_merged = opts.require_or("fill_merged", "false") == "true";
+         // skip_frozen_rows = 'true' で、シートのfrozenRowCount（ウィンドウ枠固定行数）を
+         // v4メタデータから読み取り、実質的なskip_rowsとして使う。ヘッダー行数を数えてskip_rowsに
+         // 手打ちする必要が無くなる。frozenRowCountはgvizのtqレスポンスには含まれずv4専用の
+         // メタデータのため、gvizモードで指定された場合はエラーにする。範囲の絞り込みという点で
+         // start_cell/filter_view_idと重なるため併用できない。
+         let skip_frozen_rows = opts.require_or("skip_frozen_rows", "false") == "true";
+         // ranges = 'Sheet1!A1:C10,Sheet1!E1:F10' で、単一範囲ではなく複数のA1範囲をそれぞれ
+         // 個別に取得し、行として連結する（範囲をまたいだ「複数ブロックのシート」を1つのテーブル
+         // として読むための機能）。gvizのtqクエリ言語には複数範囲の概念が無く、また複数範囲を
+         // 1回のリクエストへまとめるbatchGet相当のヘルパーも本FDWには無いため、range毎に
+         // fetch_v4_spreadsheetを呼び直す（他のv4メタデータ解決と同じ、素朴な複数リクエスト方式）。
+         // start_cell/filter_view_id/skip_frozen_rows/page_sizeは単一範囲を組み立てる/前提とする
+         // 仕組みのため併用できない。
+         let ranges = parse_column_list_option(opts.get("ranges").as_deref());
+         // skip_rows = 'N'（既定0）で、取得したsrc_rowsの先頭からN行を無条件に捨てる。
+         // reverse_rows = 'true'で、残った行をsrc_rowsの末尾から先頭へ向かって読むようにする
+         // （新しい行ほど下に追記されるシートで、ORDER BYの往復無しに新しい順に読みたい場合向け）。
+         // skip_rowsは「先頭から捨てる」処理なので、reverse_rowsと組み合わせた場合も常に
+         // 反転前（＝元の並び）の先頭から数えてN行をまず捨ててから反転する。page_size（v4の
+         // オンデマンドページング）とは、ページ単位でしか行を保持しないため併用できない。
+         let skip_rows: usize = opts.require_or("skip_rows", "0").parse().unwrap_or(0);
+         let reverse_rows = opts.require_or("reverse_rows", "false") == "true";
+
+         validate_v4_only_options(
+             &api_mode,
+             this.service_account_json.is_some(),
+             !note_of_columns.is_empty(),
+             !hyperlink_of.is_empty(),
+             !color_of.is_empty(),
+             !validation_of.is_empty(),
+             &value_render,
+             sheet_index.is_some(),
+             filter_view_id.is_some(),
+             start_cell_set,
+             page_size,
+             fill_merged,
+             skip_frozen_rows,
+             !ranges.is_empty(),
+             skip_rows > 0,
+             reverse_rows,
+         )?;
+
+         this.api_mode = api_mode;
+         this.note_of_columns = note_of_columns;
+         this.hyperlink_of = hyperlink_of;
+         this.color_of = color_of;
+         this.validation_of = validation_of;
+         // format_of = 'fmt_col=src_col,...' の形で、fmt_col自身の値の代わりにsrc_colと同じソース列の
+         // 数値表示形式パターン（通貨/パーセント等）を返す列を指定する。hyperlink_of/color_of/
+         // validation_ofと異なりgviz/v4どちらのモードでも使える（gvizは元々/table/colsのpatternを
+         // number_as_text_columns等の内部処理で使っており、それをそのまま列として公開するだけ）。
+         this.format_of = parse_column_pair_list_option(opts.get("format_of").as_deref())?;
+         this.value_render = value_render;
+         // sheet_id/sheet_indexのどちらも指定されていない場合、gvizは無条件にスプレッドシートの
+         // 先頭（並び順が最初）のシートを読む。これはユーザーが選択肢を忘れたときに、意図しない
+         // タブを静かに読んでしまう典型的な落とし穴なので、常にreport_infoで明示する。
+         // require_sheet = 'true' を指定すると、この曖昧なデフォルト読み取りをエラーに格上げできる。
+         if sheet_id.is_none() && sheet_index.is_none() {
+             if opts.require_or("require_sheet", "false") == "true" {
+                 return Err(
+                     "require_sheet = 'true' but neither sheet_id nor sheet_index was given (the default sheet is ambiguous)".to_owned(),
+                 );
+             }
+             report_info("no sheet_id or sheet_index given; reading the default (first) sheet");
+         }
+         // null_strings = 'N/A,-,NULL' のように、人間が空欄代わりに入力しがちな文字列セルを
+         // SQLのNULLとして扱うためのテーブルオプション。型変換より前に判定するので、
+         // 数値/日付列など全ての型に対して機能する。
+         this.null_strings = parse_column_list_option(opts.get("null_strings").as_deref());
+         // limit_rows = 'N'（既定0=無制限）で、SQLのLIMITとは独立に「最初のN行を出力したら
+         // それ以上読み進めない」というテーブル側のハード上限を課す。プッシュダウンが効かない
+         // ルックアップ（id = X の1行だけ欲しい、等）での無駄な読み込みを避ける安全弁。
+         this.limit_rows = opts.require_or("limit_rows", "0").parse().unwrap_or(0);
+         this.rows_emitted = 0;
+         // scale = 'col=1.5,...' / offset = 'col=-2,...' で、数値列に対してvalue * scale + offset
+         // を適用する（単位変換用）。text/date列には適用されないno-op。
+         this.scale_columns = parse_column_float_map_option(opts.get("scale").as_deref())?;
+         this.offset_columns = parse_column_float_map_option(opts.get("offset").as_deref())?;
+         // round_to = 'col=2,...' で、numeric型の列に対してhalf-to-even丸めを適用する
+         // （numeric(p,s)のsを超える桁で発生するスケールオーバーフローを防ぐ）。他の型には適用されない。
+         this.round_to_columns = parse_column_u32_map_option(opts.get("round_to").as_deref())?;
+         // range_columns = 'Sheet1!A1:C10=col_a|col_b|col_c,Sheet1!E1:F10=col_d|col_e' で、
+         // rangesの各範囲を対象スキーマのどの列へ割り当てるかを指定する（範囲ごとに列の並びや
+         // 列数が異なる、真に異種な複数ブロックのシート向け）。rangesに登録が無い範囲、または
+         // range_columns自体が省略された範囲は位置対応（範囲の1列目→対象スキーマの1列目…）に
+         // フォールバックする。マッピングに含まれない対象列はその範囲由来の行ではnullになる。
+         this.range_columns = parse_range_column_map_option(opts.get("range_columns").as_deref())?;
+         if !this.range_columns.is_empty() && ranges.is_empty() {
+             return Err("range_columns requires ranges to be set".to_owned());
+         }
+         // strip_prefix = 'col=affix,...' / strip_suffix = 'col=affix,...' で、引用符や通貨記号のような
+         // 一貫した包み込み文字列を型変換より前に取り除く（SQL側のtrim()/replace()を1段省くための
+         // 軽量な正規化ステップ）。
+         this.strip_prefix_columns = parse_column_pair_list_option(opts.get("strip_prefix").as_deref())?;
+         this.strip_suffix_columns = parse_column_pair_list_option(opts.get("strip_suffix").as_deref())?;
+         this.strip_leading_apostrophe = opts.require_or("strip_leading_apostrophe", "false") == "true";
+         // use_formatted = 'col1,col2' で、bigint列を表示用文字列"f"（桁区切り付き）経由で
+         // パースする（number_as_text_columnsのI64版: v(f64)では安全に扱えない、あるいは
+         // v自体が桁区切り付き文字列になっている大きな整数列向け）。
+         this.use_formatted_columns = parse_column_list_option(opts.get("use_formatted").as_deref());
+         // column_order = 'C,A,B' で、宣言済みPostgres列の並び（DDL側の序数）に対して実際に
+         // 読みに行くソース列を列アルファベットで入れ替える。CSV/exportのような位置ベースの
+         // マッピングが支配的な形式でシート側の列順を変えずに列を並べ替えたい場合向け。
+         // 要素数は宣言済み列数と一致していなければならず、note_of_columns/hyperlink_of/color_of
+         // が別途ソース列参照を行うapi_mode = 'v4'とは併用できない。
+         this.column_order = match opts.get("column_order") {
+             Some(v) => {
+                 if this.api_mode == "v4" {
+                     return Err(
+                         "column_order cannot be combined with api_mode = 'v4' (note_of_columns/hyperlink_of/color_of resolve source columns independently)"
+                             .to_owned(),
+                     );
+                 }
+                 let column_order = parse_column_order_option(&v)?;
+                 if column_order.len() != ctx.get_columns().len() {
+                     return Err(format!(
+                         "column_order has {} entries but the table declares {} columns",
+                         column_order.len(),
+                         ctx.get_columns().len()
+                     ));
+                 }
+                 column_order
+             }
+             None => Vec::new(),
+         };
+         // column_group = 'A:E' で、1枚のワイドなシートを列の帯（バンド）ごとに複数の外部テーブルへ
+         // 分割して読む。1枚のマスターシートに、空列で区切った複数の論理テーブルが横並びに
+         // 詰め込まれているケース向け。この帯の幅は宣言済み列数と一致していなければならず、
+         // column_orderと同様の仕組み（this.column_orderへの帯オフセット付きソース列マッピングの
+         // 書き込み）で実現するため、column_order自体と、v4が独自にソース列を解決する
+         // api_mode = 'v4'とは併用できない。行はシート全体から変わらず読み、列だけをこの帯に絞る
+         // （行を絞りたい場合はstart_cell/rangesと組み合わせる）。
+         if let Some(v) = opts.get("column_group") {
+             if this.api_mode == "v4" {
+                 return Err(
+                     "column_group cannot be combined with api_mode = 'v4' (note_of_columns/hyperlink_of/color_of resolve source columns independently)"
+                         .to_owned(),
+                 );
+             }
+             if !this.column_order.is_empty() {
+                 return Err("column_group cannot be combined with column_order".to_owned());
+             }
+             let (start_letters, end_letters) = v
+                 .split_once(':')
+                 .ok_or_else(|| format!("invalid column_group '{}': expected a letter range like 'A:E'", v))?;
+             let start = column_letter_to_index(start_letters)?;
+             let end = column_letter_to_index(end_letters)?;
+             if end < start {
+                 return Err(format!("invalid column_group '{}': end column must not precede the start column", v));
+             }
+             let declared_cols = ctx.get_columns().len();
+             if end - start + 1 != declared_cols {
+                 return Err(format!(
+                     "column_group '{}' spans {} column(s) but the table declares {} columns",
+                     v,
+                     end - start + 1,
+                     declared_cols
+                 ));
+             }
+             this.column_order = (start..=end).collect();
+         }
+         // include_header_row = 'true' で、iter_scanの最初の1回だけ実データの代わりにsource
+         // labelから成るヘッダー行を返す（CSVのようなヘッダー行前提の消費者へパイプする用途）。
+         // ヘッダーはlabel文字列をそのまま返すので、宣言済み列が1つでもtext以外の型だと
+         // 型変換のしようがなくエラーになる。api_mode = 'v4'/introspect各種/keyvalue/unpivotとは
+         // 別レイヤーの機能で、それらのモードでは適用しない（通常のgvizデータ行モード限定）。
+         this.include_header_row = opts.require_or("include_header_row", "false") == "true";
+         if this.include_header_row {
+             if let Some(bad_col) = ctx.get_columns().iter().find(|c| c.type_oid() != TypeOid::String) {
+                 return Err(format!(
+                     "include_header_row requires every declared column to be text/string (column '{}' is not)",
+                     bad_col.name()
+                 ));
+             }
+         }
+         this.header_row_emitted = false;
+         // source_letters = 'wide_col=C:F,...' で、隣接する複数のソース列をまとめて
+         // jsonb配列としてこの列に格納する（幅広フォーマットのシートを配列列に畳み込む用途）。
+         this.source_letters = parse_column_range_map_option(opts.get("source_letters").as_deref())?;
+         // timezone = '+09:00' のように固定UTCオフセットを指定する（IANAタイムゾーンDBは無いため）。
+         this.timezone = opts.require_or("timezone", "+00:00");
+         // column_timezones = 'col=+09:00,col2=+00:00' で、混在タイムゾーンのシート向けに列ごとに
+         // timezoneを上書きする。指定の無い列は引き続きtimezoneテーブルオプション（既定"+00:00"）を使う。
+         this.column_timezones = parse_column_pair_list_option(opts.get("column_timezones").as_deref())?;
+         // datetime_as = 'col=epoch_ms,...' で、bigint列にgvizのDate(...)値をエポックミリ秒として返す。
+         this.datetime_as = parse_column_pair_list_option(opts.get("datetime_as").as_deref())?;
+         for (col, mode) in &this.datetime_as {
+             if mode != "epoch_ms" {
+                 return Err(format!(
+                     "unsupported datetime_as mode '{}' for column '{}' (only 'epoch_ms' is supported)",
+                     mode, col
+                 ));
+             }
+         }
+         // duration_as = 'col=total_seconds,...' で、`[h]:mm:ss`（24時間を超えられる経過時間書式）の
+         // セルを総秒数のbigintとして返す。WITのtype-oidにはinterval型が無くTypeOid::Intervalを
+         // 追加できないため、intervalそのものではなく総秒数を返し、呼び出し側で
+         // `(col || ' seconds')::interval` のようにキャストしてもらう形にする。
+         this.duration_as = parse_column_pair_list_option(opts.get("duration_as").as_deref())?;
+         for (col, mode) in &this.duration_as {
+             if mode != "total_seconds" {
+                 return Err(format!(
+                     "unsupported duration_as mode '{}' for column '{}' (only 'total_seconds' is supported)",
+                     mode, col
+                 ));
+             }
+         }
+         // strict_column_bounds = 'true' で、列の対応するソースインデックスがシートの総列数を
+         // 超えている（=スキーマ側の問題）場合にエラーで中断する。既定はreport_infoでの警告のみ。
+         this.strict_column_bounds = opts.require_or("strict_column_bounds", "false") == "true";
+         this.oob_reported_columns.clear();
+         // lenient_text = 'true' で、text列にgviz側の型が一致しない値が来てもnullにせず文字列化する。
+         this.lenient_text = opts.require_or("lenient_text", "false") == "true";
+         // nonfinite = 'null' | 'error' で、数値列がNaN/Infinity等の非有限値になった場合の扱いを決める。
+         this.nonfinite = opts.require_or("nonfinite", "null");
+         if this.nonfinite != "null" && this.nonfinite != "error" {
+             return Err(format!(
+                 "invalid nonfinite option '{}' (expected 'null' or 'error')",
+                 this.nonfinite
+             ));
+         }
+         // on_cell_error = 'null' | 'string' | 'error' で、gvizの数式エラーセル（#REF!等）の扱いを決める。
+         this.on_cell_error = opts.require_or("on_cell_error", "null");
+         if !["null", "string", "error"].contains(&this.on_cell_error.as_str()) {
+             return Err(format!(
+                 "invalid on_cell_error option '{}' (expected 'null', 'string', or 'error')",
+                 this.on_cell_error
+             ));
+         }
+         // bool_true_values/bool_false_values = 'yes,y,on,...' で、TypeOid::Boolの文字列セルを
+         // 真偽値として受理するトークン集合をTRUE/FALSE以外にも広げる（大文字小文字は区別しない）。
+         // 既定はtrue/false・yes/no・on/offを両方認識する。
+         this.bool_true_values = parse_column_list_option(Some(&opts.require_or("bool_true_values", "true,yes,on")))
+             .into_iter()
+             .map(|v| v.to_lowercase())
+             .collect();
+         this.bool_false_values = parse_column_list_option(Some(&opts.require_or("bool_false_values", "false,no,off")))
+             .into_iter()
+             .map(|v| v.to_lowercase())
+             .collect();
+         // collect_errors = 'true' で、セル単位の変換失敗を即座にエラー終了させる代わりに
+         // （on_row_errorが'null'/'skip'の場合に限り）収集し、end_scanでreport_infoとして
+         // まとめて報告する。データ品質を1回のスキャンで棚卸ししたい用途向け。
+         this.collect_errors = opts.require_or("collect_errors", "false") == "true";
+         this.collected_cell_errors.clear();
+         this.cell_errors_dropped = 0;
+         // date_format = strftime風パターン（例 "%d/%m/%Y"）で、Date(...)リテラルでない素のテキスト日付を
+         // date/timestamp/timestamptz列向けにパースできるようにする。未指定なら従来通りDate(...)のみ対応する。
+         this.date_format = opts.require_or("date_format", "");
+         if !this.date_format.is_empty() {
+             validate_date_format(&this.date_format)?;
+         }
+         // constant = 'col=value,...' で、列ごとにシートを参照しない固定値を出力する。
+         this.constant_columns = parse_column_pair_list_option(opts.get("constant").as_deref())?;
+         // default = 'col=value,...' で、列ごとにソースセルが欠損/nullの場合だけ使う穴埋め値を
+         // 指定する（constantと違い、シートに実際の値があればそちらを優先する）。
+         this.default_columns = parse_column_pair_list_option(opts.get("default").as_deref())?;
+         // default_number/default_text/default_bool で、default_columnsに一致する列指定が無い場合の
+         // テーブル全体の型別デフォルトを指定する（例: 数値列は全部0、テキスト列は全部空文字にする等、
+         // スパースなシートをNOT NULL制約のあるスキーマへ取り込む際の簡易な穴埋め用途）。
+         this.default_number = opts.get("default_number");
+         this.default_text = opts.get("default_text");
+         this.default_bool = opts.get("default_bool");
+
+         // introspect/keyvalue/csv/v4の各モードは1レスポンスに対する固定スキーマ変換なので、
+         // 複数ファイルの連結（spread_sheet_ids）とは組み合わせられない。
+         if spread_sheet_ids.len() > 1
+             && (opts.require_or("source_format", "gviz") == "csv"
+                 || opts.get("format").as_deref() == Some("export_csv")
+                 || opts.get("introspect").as_deref() == Some("columns")
+                 || opts.get("introspect").as_deref() == Some("meta")
+                 || opts.get("introspect").as_deref() == Some("spreadsheet")
+                 || opts.get("introspect").as_deref() == Some("rowcount")
+                 || opts.require_or("keyvalue", "false") == "true"
+                 || opts.require_or("unpivot", "false") == "true"
+                 || this.include_header_row
+                 || this.api_mode == "v4")
+         {
+             return Err(
+                 "spread_sheet_ids does not support source_format=csv, format=export_csv, introspect, keyvalue, unpivot, include_header_row or api_mode=v4"
+                     .to_owned(),
+             );
+         }
+
+         // introspect = 'spreadsheet' は行データではなく、スプレッドシート単位のtitle/locale/
+         // timeZone/sheet_namesを1行だけ返すモードにする。これらはgvizのtqレスポンスには含まれず
+         // v4 APIでしか取得できないため、api_modeの設定に関わらずservice_accountを要求する。
+         if opts.get("introspect").as_deref() == Some("spreadsheet") {
+             let service_account_json = this.service_account_json.clone().ok_or(
+                 "introspect = 'spreadsheet' requires a 'service_account' server option (this metadata is only available via the v4 API)",
+             )?;
+             let access_token = get_cached_v4_access_token(this, &service_account_json)?;
+             let resp_json = fetch_v4_spreadsheet(
+                 &spread_sheet_id,
+                 &access_token,
+                 "properties(title,locale,timeZone),sheets.properties.title",
+                 None,
+                 &this.query_params,
+             )?;
+             let sheet_names = resp_json
+                 .get("sheets")
+                 .and_then(|v| v.as_array())
+                 .map(|sheets| {
+                     sheets
+                         .iter()
+                         .filter_map(|s| s.pointer("/properties/title").and_then(|v| v.as_str()).map(str::to_owned))
+                         .collect()
+                 })
+                 .unwrap_or_default();
+             this.introspect_spreadsheet = Some(SpreadsheetMetaInfo {
+                 title: resp_json.pointer("/properties/title").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                 locale: resp_json.pointer("/properties/locale").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                 time_zone: resp_json.pointer("/properties/timeZone").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                 sheet_names,
+             });
+             report_info("introspect spreadsheet mode: fetched spreadsheet-level metadata via v4");
+             return Ok(());
+         }
+         this.introspect_spreadsheet = None;
+
+         // introspect = 'developer_metadata' も同様にv4専用。フィールドマスクは
+         // developerMetadata(metadataId,metadataKey,metadataValue,visibility,location) に絞り、
+         // 値そのもの（スプレッドシートの実データ）は取得しない。開発者メタデータが1件も
+         // 付与されていないシートに対しては、エラーにせず0行を返す。
+         if opts.get("introspect").as_deref() == Some("developer_metadata") {
+             let service_account_json = this.service_account_json.clone().ok_or(
+                 "introspect = 'developer_metadata' requires a 'service_account' server option (this metadata is only available via the v4 API)",
+             )?;
+             let access_token = get_cached_v4_access_token(this, &service_account_json)?;
+             let resp_json = fetch_v4_spreadsheet(
+                 &spread_sheet_id,
+                 &access_token,
+                 "developerMetadata(metadataId,metadataKey,metadataValue,visibility,location)",
+                 None,
+                 &this.query_params,
+             )?;
+             let entries: Vec<DeveloperMetadataInfo> = resp_json
+                 .get("developerMetadata")
+                 .and_then(|v| v.as_array())
+                 .map(|entries| {
+                     entries
+                         .iter()
+                         .map(|e| DeveloperMetadataInfo {
+                             id: e.get("metadataId").and_then(|v| v.as_i64()).unwrap_or_default(),
+                             key: e.get("metadataKey").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                             value: e.get("metadataValue").and_then(|v| v.as_str()).map(str::to_owned),
+                             location: e.get("location").cloned().unwrap_or(JsonValue::Null),
+                             visibility: e.get("visibility").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                         })
+                         .collect()
+                 })
+                 .unwrap_or_default();
+             report_info(&format!(
+                 "introspect developer_metadata mode: found {} entr{}",
+                 entries.len(),
+                 if entries.len() == 1 { "y" } else { "ies" }
+             ));
+             this.introspect_developer_metadata = Some(entries);
+             return Ok(());
+         }
+         this.introspect_developer_metadata = None;
+
+         // introspect = 'protected_ranges' もv4専用。保護範囲（個別のセル範囲、またはgridRange省略
+         // によるシート全体保護）をシートタイトルとともにA1形式へ変換し、description/editors
+         // （usersId/groups/domainUsersCanEditは形が状況により変わるためjsonbで加工せず保持）を
+         // 添えて返す。gvizのtqクエリ言語には保護範囲を返す仕組みが無いため、gvizモードでの
+         // 利用はservice_accountが無い旨のエラーで拒否する（developer_metadataと同様の扱い）。
+         if opts.get("introspect").as_deref() == Some("protected_ranges") {
+             let service_account_json = this.service_account_json.clone().ok_or(
+                 "introspect = 'protected_ranges' requires a 'service_account' server option (protected ranges are only available via the v4 API)",
+             )?;
+             let access_token = get_cached_v4_access_token(this, &service_account_json)?;
+             let resp_json = fetch_v4_spreadsheet(
+                 &spread_sheet_id,
+                 &access_token,
+                 "sheets(properties(sheetId,title),protectedRanges(protectedRangeId,range,description,editors))",
+                 None,
+                 &this.query_params,
+             )?;
+             let sheets = resp_json
+                 .get("sheets")
+                 .and_then(|v| v.as_array())
+                 .ok_or("v4 response is missing 'sheets'")?;
+             let mut entries = Vec::new();
+             for sheet in sheets {
+                 let title = sheet.pointer("/properties/title").and_then(|v| v.as_str()).unwrap_or_default();
+                 let Some(protected_ranges) = sheet.get("protectedRanges").and_then(|v| v.as_array()) else {
+                     continue;
+                 };
+                 for pr in protected_ranges {
+                     entries.push(ProtectedRangeInfo {
+                         id: pr.get("protectedRangeId").and_then(|v| v.as_i64()).unwrap_or_default(),
+                         sheet_title: title.to_owned(),
+                         range: protected_range_to_a1(title, pr.get("range")),
+                         description: pr.get("description").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                         editors: pr.get("editors").cloned().unwrap_or(JsonValue::Null),
+                     });
+                 }
+             }
+             report_info(&format!(
+                 "introspect protected_ranges mode: found {} protected range(s)",
+                 entries.len()
+             ));
+             this.introspect_protected_ranges = Some(entries);
+             return Ok(());
+         }
+         this.introspect_protected_ranges = None;
+        this.introspect_rowcount = None;
+
+         // api_mode = 'v4' の場合はgvizのtqエンドポイントではなく、Google Sheets API v4を使う。
+         // notes/hyperlinkなど、gvizのvalueレスポンスには出てこないメタデータが必要な場合に使うモード。
+         if this.api_mode == "v4" {
+             let service_account_json = this
+                 .service_account_json
+                 .clone()
+                 .ok_or("api_mode = 'v4' requires a 'service_account' server option")?;
+             let access_token = get_cached_v4_access_token(this, &service_account_json)?;
+             if sheet_id.is_none() {
+                 if let Some(idx) = sheet_index {
+                     sheet_id = Some(resolve_sheet_id_by_index(&spread_sheet_id, &access_token, idx, &this.query_params)?);
+                 }
+             }
+             // introspect = 'columns' はv4モードでも使えるようにする（gviz専用だったこれまでと違い、
+             // ヘッダーセルのメモ(note)はv4のsheets.data.rowData経由でしか取得できないため）。
+             // v4にはgvizのcolsメタデータに相当する型情報が無いため、gviz_type/pg_typeは
+             // 常に"string"/"text"を返す（実データの型推論はしない）。
+             if opts.get("introspect").as_deref() == Some("columns") {
+                 let sheet_title = fetch_v4_sheet_title(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                 let header_range = format!("{}!1:1", sheet_title);
+                 let fields = "sheets.data.rowData.values(formattedValue,note)";
+                 let resp_json = fetch_v4_spreadsheet(&spread_sheet_id, &access_token, fields, Some(&header_range), &this.query_params)?;
+                 let header_row = extract_v4_rows(&resp_json, None)?.into_iter().next().unwrap_or_default();
+                 let mut col_infos: Vec<ColumnInfo> = header_row
+                     .iter()
+                     .enumerate()
+                     .map(|(i, cell)| {
+                         let label = cell.formatted_value.clone().unwrap_or_default();
+                         let header_note = cell.note.clone();
+                         let column_comment = build_column_comment(&label, header_note.as_deref());
+                         ColumnInfo {
+                             ordinal: (i + 1) as i64,
+                             label,
+                             gviz_type: "string".to_owned(),
+                             pg_type: "text".to_owned(),
+                             header_note,
+                             column_comment,
+                         }
+                     })
+                     .collect();
+                 if opts.require_or("trim_trailing_empty", "false") == "true" {
+                     while col_infos.last().is_some_and(|c| c.label.is_empty()) {
+                         col_infos.pop();
+                     }
+                 }
+                 if let Some(labels) = opts.get("columns") {
+                     let keep = parse_column_list_option(Some(&labels));
+                     col_infos.retain(|c| keep.contains(&c.label));
+                 }
+                 if let Some(max_columns) = opts.get("max_columns") {
+                     let max_columns: usize = max_columns
+                         .parse()
+                         .map_err(|_| "max_columns must be a non-negative integer".to_owned())?;
+                     col_infos.truncate(max_columns);
+                 }
+                 this.introspect_cols = Some(col_infos);
+                 report_info(&format!(
+                     "introspect mode (v4): found {} source columns",
+                     this.introspect_cols.as_ref().unwrap().len()
+                 ));
+                 return Ok(());
+             }
+             if !ranges.is_empty() {
+                 // 複数範囲モード: 範囲毎にfetch_v4_spreadsheetを呼び、range_columnsの指定
+                 // （無ければ位置対応）でrealign_v4_range_rowが対象スキーマの列位置へ並べ替えた
+                 // 行をv4_rowsへ連結する。push_v4_row自体は変更不要で、連結済みv4_rowsを
+                 // 単一範囲の場合と同じようにそのまま読める。
+                 let tgt_columns: Vec<(String, u32)> = ctx.get_columns().iter().map(|c| (c.name(), c.num())).collect();
+                 let tgt_width = tgt_columns.len();
+                 let fields = "sheets.properties.sheetId,sheets.data.rowData.values(formattedValue,note,hyperlink,userEnteredFormat.backgroundColor,userEnteredFormat.numberFormat.pattern,userEnteredValue.formulaValue,effectiveValue,dataValidation)";
+                 this.v4_rows.clear();
+                 for range in &ranges {
+                     let resp_json = fetch_v4_spreadsheet(&spread_sheet_id, &access_token, fields, Some(range), &this.query_params)?;
+                     let range_rows = extract_v4_rows(&resp_json, sheet_id.as_deref())?;
+                     let mapping = this.range_columns.iter().find(|(r, _)| r == range).map(|(_, cols)| cols.as_slice());
+                     let position_map = resolve_range_column_positions(mapping, &tgt_columns)?;
+                     this.v4_rows.extend(
+                         range_rows
+                             .iter()
+                             .map(|row| realign_v4_range_row(row, &position_map, tgt_width)),
+                     );
+                 }
+                 apply_skip_and_reverse_rows(&mut this.v4_rows, &mut Vec::new(), skip_rows, reverse_rows);
+                 this.v4_page_size = 0;
+                 report_info(&format!(
+                     "v4 mode: got {} rows from {} range(s)",
+                     this.v4_rows.len(),
+                     ranges.len()
+                 ));
+             } else if page_size > 0 {
+                 // ページングモード: 最初のページのみ取得する。残りはiter_scanがsrc_idxが
+                 // ページ境界を跨いだタイミングでオンデマンドに取得する。skip_frozen_rowsが
+                 // 指定されていれば、frozenRowCount分だけ先頭ページの開始位置を後ろにずらす。
+                 let sheet_title = fetch_v4_sheet_title(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                 let page_start = if skip_frozen_rows {
+                     fetch_v4_frozen_row_count(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?
+                 } else {
+                     0
+                 };
+                 this.v4_page_size = page_size;
+                 this.v4_page_start = 0;
+                 this.v4_physical_row_offset = page_start;
+                 this.v4_page_rows = fetch_v4_page(&spread_sheet_id, &access_token, &sheet_title, page_start, page_size, &this.query_params)?;
+                 this.v4_access_token = access_token;
+                 this.v4_page_spread_sheet_id = spread_sheet_id.clone();
+                 this.v4_sheet_title = sheet_title;
+                 this.v4_rows.clear();
+                 report_info(&format!(
+                     "v4 mode: paginating with page_size={} ({} rows in first page, page_start={})",
+                     page_size,
+                     this.v4_page_rows.len(),
+                     page_start
+                 ));
+             } else {
+                 let fields = if fill_merged {
+                     "sheets.properties.sheetId,sheets.data.rowData.values(formattedValue,note,hyperlink,userEnteredFormat.backgroundColor,userEnteredFormat.numberFormat.pattern,userEnteredValue.formulaValue,effectiveValue,dataValidation),sheets.merges"
+                 } else {
+                     "sheets.properties.sheetId,sheets.data.rowData.values(formattedValue,note,hyperlink,userEnteredFormat.backgroundColor,userEnteredFormat.numberFormat.pattern,userEnteredValue.formulaValue,effectiveValue,dataValidation)"
+                 };
+                 // start_cell（例 "A2"）が指定されていれば、宣言済みの列数から終端列を自動算出し、
+                 // シート全体ではなくその範囲だけをrangesで絞り込んで取得する。filter_view_idが
+                 // 指定されていれば、代わりにそのフィルタビューが定義する範囲を使う。skip_frozen_rows
+                 // が指定されていれば、frozenRowCount+1行目を起点のstart_cellとして扱う
+                 // （begin_scanの検証でこの3つは互いに排他になっている）。
+                 let ranges = match &filter_view_id {
+                     Some(filter_view_id) => Some(resolve_filter_view_range(&spread_sheet_id, &access_token, filter_view_id, &this.query_params)?),
+                     None if skip_frozen_rows => {
+                         let sheet_title = fetch_v4_sheet_title(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                         let frozen_row_count =
+                             fetch_v4_frozen_row_count(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                         let start_cell = format!("A{}", frozen_row_count + 1);
+                         let a1_range = build_a1_range_from_start_cell(&start_cell, ctx.get_columns().len())?;
+                         Some(format!("{}!{}", sheet_title, a1_range))
+                     }
+                     None => match opts.get("start_cell") {
+                         Some(start_cell) => {
+                             let sheet_title = fetch_v4_sheet_title(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                             let a1_range = build_a1_range_from_start_cell(&start_cell, ctx.get_columns().len())?;
+                             Some(format!("{}!{}", sheet_title, a1_range))
+                         }
+                         None => None,
+                     },
+                 };
+                 let resp_json = fetch_v4_spreadsheet(&spread_sheet_id, &access_token, fields, ranges.as_deref(), &this.query_params)?;
+                 this.v4_rows = extract_v4_rows(&resp_json, sheet_id.as_deref())?;
+                 if fill_merged {
+                     let merges = extract_v4_merges(&resp_json, sheet_id.as_deref())?;
+                     fill_merged_cells(&mut this.v4_rows, &merges);
+                 }
+                 apply_skip_and_reverse_rows(&mut this.v4_rows, &mut Vec::new(), skip_rows, reverse_rows);
+                 this.v4_page_size = 0;
+                 report_info(&format!("v4 mode: got {} rows", this.v4_rows.len()));
+             }
+             return Ok(());
+         }
+         this.v4_rows.clear();
+         this.v4_page_size = 0;
+
+        // API通信のためのヘッダーを定義。
+        // 認証プロキシ等の都合でテーブルごとに異なるヘッダーが必要な場合があるため、
+        // built-in < サーバーオプション < テーブルオプション の優先順でマージする。
+        let built_in_headers: Vec<(String, String)> = vec![
+            ("user-agent".to_owned(), "Sheets FDW".to_owned()),
+            // header to make JSON response more cleaner
+            ("x-datasource-auth".to_owned(), "true".to_owned()),
+        ];
+        let table_headers = parse_http_headers_option(opts.get("http_headers").as_deref())?;
+        let headers_public = built_in_headers.clone();
+        let headers = merge_headers(&[built_in_headers, this.server_headers.clone(), table_headers]);
+
+        // auth_mode = 'public' | 'token' | 'jwt' | 'api_key' | 'auto'（既定'token'、今までの挙動と
+        // 同じく常にheaders_token=マージ済みheadersを使う）。'auto'は無認証を先に試し、
+        // ログインページ等（")]}'\n"プレフィックス無し応答）を検知したら利用可能な認証情報で
+        // 順にフォールバックする。jwtはservice_account経由のOAuthトークンをgvizへのBearerとして使う。
+        let auth_mode = opts.require_or("auth_mode", "token");
+        if !["public", "token", "jwt", "api_key", "auto"].contains(&auth_mode.as_str()) {
+            return Err(format!(
+                "invalid auth_mode option '{}' (expected 'public', 'token', 'jwt', 'api_key', or 'auto')",
+                auth_mode
+            ));
+        }
+        let api_key = opts.get("api_key");
+        let jwt_header = if auth_mode == "jwt" || auth_mode == "auto" {
+            match &this.service_account_json {
+                Some(sa) => {
+                    let sa = sa.clone();
+                    let token = get_cached_v4_access_token(this, &sa)?;
+                    Some(("authorization".to_owned(), format!("Bearer {}", token)))
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        // table_index テーブルオプション（既定0）。tqxの設定次第でgvizが複数テーブルのDataTableを
+        // 返すことがある稀なケースや、source_format = 'html'で公開ページに複数の<table>が
+        // 含まれる場合に、単一の"table"だけを前提にせず対象を選べるようにする。
+        let table_index: usize = opts
+            .require_or("table_index", "0")
+            .parse()
+            .map_err(|_| "table_index must be a non-negative integer".to_owned())?;
+        // source_format = 'html'（またはそのエイリアスformat = 'html'）の場合、公開されたHTMLページを
+        // 取得し、その中のtable_index番目の<table>を、csvモードと同じ「行=文字列のVec」の形に
+        // パースしてsrc_rowsの代わりに使う（この後のiter_scan_innerは既存のcsv_rows経由の分岐を
+        // そのまま使い回すため、ここでは値を詰めるだけで良い）。gvizのAPIそのものが使えない/
+        // 認証が壊れているシートに対する最後の手段であり、公開ページのHTML構造依存という
+        // 意味で最も壊れやすい読み取り経路になる。
+        if opts.require_or("source_format", "gviz") == "html" || opts.get("format").as_deref() == Some("html") {
+            let html_url = resolve_templated_url(
+                &this.base_url,
+                &spread_sheet_id,
+                sheet_id.as_deref(),
+                "{base_url}/{id}/pubhtml?gid={gid}",
+                "{base_url}/{id}/pubhtml",
+            )?;
+            let req = http::Request {
+                method: http::Method::Get,
+                url: html_url,
+                headers: headers.clone(),
+                body: String::default(),
+            };
+            let resp = get_following_redirects(req, &this.query_params)?;
+            let rows = parse_html_table(&resp.body, table_index)?;
+            let expected_cols: usize = match opts.get("csv_columns") {
+                Some(v) => v.parse().map_err(|_| "csv_columns must be a positive integer".to_owned())?,
+                None => ctx.get_columns().len(),
+            };
+            let strict = opts.require_or("csv_strict", "false") == "true";
+            let mut csv_rows = rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| normalize_csv_row(row, expected_cols, strict, i))
+                .collect::<Result<Vec<_>, _>>()?;
+            apply_skip_and_reverse_rows(&mut csv_rows, &mut Vec::new(), skip_rows, reverse_rows);
+            this.csv_rows = Some(csv_rows);
+            report_info(&format!(
+                "html mode: got {} rows from table_index={}",
+                this.csv_rows.as_ref().unwrap().len(),
+                table_index
+            ));
+            return Ok(());
+        }
+
+        // source_format = 'csv'（またはそのエイリアスformat = 'export_csv'）の場合、gvizのtqエンドポイント
+        // ではなくexport?format=csvを使う。http_headers等のマージ済みヘッダーを付与するので、
+        // gvizがHTMLを返してしまう認証必須のシートでも、認証ヘッダー経由でCSVを取得できる。
+        // 列数はcsv_columns（明示指定）が無ければ、外部テーブル定義の列数から推測する。
+        if opts.require_or("source_format", "gviz") == "csv" || opts.get("format").as_deref() == Some("export_csv") {
+            let expected_cols: usize = match opts.get("csv_columns") {
+                Some(v) => v.parse().map_err(|_| "csv_columns must be a positive integer".to_owned())?,
+                None => ctx.get_columns().len(),
+            };
+            let mut csv_url = resolve_templated_url(
+                &this.base_url,
+                &spread_sheet_id,
+                sheet_id.as_deref(),
+                "{base_url}/{id}/export?format=csv&gid={gid}",
+                "{base_url}/{id}/export?format=csv",
+            )?;
+            // start_cell（例 "A2"）と列数から、終端列を手計算せずにエクスポート範囲を絞り込めるようにする。
+            if let Some(start_cell) = opts.get("start_cell") {
+                let a1_range = build_a1_range_from_start_cell(&start_cell, expected_cols)?;
+                csv_url.push_str(&format!("&range={}", gviz_url_encode(&a1_range)));
+            }
+            let req = http::Request {
+                method: http::Method::Get,
+                url: csv_url,
+                headers: headers.clone(),
+                body: String::default(),
+            };
+            let resp = get_following_redirects(req, &this.query_params)?;
+            let raw_rows = parse_csv(&resp.body);
+            let strict = opts.require_or("csv_strict", "false") == "true";
+            let mut csv_rows = raw_rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| normalize_csv_row(row, expected_cols, strict, i))
+                .collect::<Result<Vec<_>, _>>()?;
+            apply_skip_and_reverse_rows(&mut csv_rows, &mut Vec::new(), skip_rows, reverse_rows);
+            this.csv_rows = Some(csv_rows);
+            report_info(&format!(
+                "csv mode: got {} rows",
+                this.csv_rows.as_ref().unwrap().len()
+            ));
+            return Ok(());
+        }
+        this.csv_rows = None;
+
+        // debug = 'raw_body' は、gvizのtqxプレフィックス除去やJSONパースを一切行わず、
+        // Googleから返ってきたレスポンスをstatus_code/bodyの2列だけの1行として返す最終手段の
+        // 診断モード。パーサーがレスポンスを拒否している原因（ログインページへのリダイレクト、
+        // 想定外のHTML、クォータ超過のエラーページ等）をSQLクエリ1回で目視確認できるようにする。
+        // debug_max_bytes（既定65536）を超える本文はUTF-8境界を壊さない範囲で切り詰める。
+        if opts.get("debug").as_deref() == Some("raw_body") {
+            let debug_max_bytes: usize = opts.require_or("debug_max_bytes", "65536").parse().unwrap_or(65536);
+            let url = resolve_templated_url(
+                &this.base_url,
+                &spread_sheet_id,
+                sheet_id.as_deref(),
+                &format!("{{base_url}}/{{id}}/{}?gid={{gid}}&tqx=out:json", this.gviz_path),
+                &format!("{{base_url}}/{{id}}/{}?tqx=out:json", this.gviz_path),
+            )?;
+            let candidates = build_auth_candidates(&url, &auth_mode, &headers_public, &headers, jwt_header.clone(), api_key.as_deref())?;
+            let (_, candidate_url, candidate_headers) = candidates
+                .first()
+                .ok_or("debug = 'raw_body' has no auth candidate to try")?;
+            let req = http::Request {
+                method: http::Method::Get,
+                url: candidate_url.clone(),
+                headers: candidate_headers.clone(),
+                body: String::default(),
+            };
+            let resp = get_following_redirects(req, &this.query_params)?;
+            let (body, truncated) = truncate_body_for_debug(&resp.body, debug_max_bytes);
+            report_info(&format!(
+                "debug = 'raw_body': status {}, {} byte(s) fetched{}",
+                resp.status_code,
+                resp.body.len(),
+                if truncated { " (truncated for output)" } else { "" }
+            ));
+            this.debug_raw_body = Some(RawBodyDebug {
+                status_code: resp.status_code,
+                body,
+                truncated,
+            });
+            return Ok(());
+        }
+        this.debug_raw_body = None;
+
+        // cache_ttl_secsが正の値の場合のみ、URL単位のTTL付きLRUキャッシュを利用する。
+        let cache_ttl_secs: i64 = opts.require_or("cache_ttl_secs", "0").parse().unwrap_or(0);
+        let cache_max_entries: usize = opts.require_or("cache_max_entries", "32").parse().unwrap_or(32);
+        let cache_max_bytes: usize = opts
+            .require_or("cache_max_bytes", "10485760")
+            .parse()
+            .unwrap_or(10_485_760);
+        // 継続的に失敗しているシートへの無駄なリクエストを避けるためのサーキットブレーカー設定。
+        let circuit_threshold: u32 = opts
+            .require_or("circuit_breaker_threshold", "5")
+            .parse()
+            .unwrap_or(5);
+        let circuit_window_secs: u64 = opts
+            .require_or("circuit_breaker_window_secs", "300")
+            .parse()
+            .unwrap_or(300);
+        let circuit_cooldown_secs: u64 = opts
+            .require_or("circuit_breaker_cooldown_secs", "60")
+            .parse()
+            .unwrap_or(60);
+        // circuit_breaker_max_entries（既定256）: クールダウンを挟まず失敗し続ける/一度きりしか
+        // 問い合わせないURLがクリーンなsuccessを挟まずに溜まり続け、サーキットブレーカーの
+        // 内部状態が無制限に肥大化するのを防ぐ上限。ResponseCacheのcache_max_entriesと同じ
+        // 「古いものから間引くLRU」方式で管理する。
+        let circuit_max_entries: usize = opts
+            .require_or("circuit_breaker_max_entries", "256")
+            .parse()
+            .unwrap_or(256);
+        // snapshot = 'true' は、セッション内の全クエリが同じ取得結果を見続けるダッシュボード用途向けに、
+        // 上記のTTL付きキャッシュを実質無期限として扱う（＝ttl_secsの経過を待たない）モード。
+        // refresh = 'true' は、そのスナップショットを1回だけ明示的に取り直して置き換えるための
+        // 手動無効化フラグで、snapshotとは独立したオプション。プッシュダウンされたtq句を含めた
+        // URL単位でキャッシュしているため、異なるプッシュダウン結果（＝異なるURL）は別々の
+        // スナップショットとして扱われる点に注意（例えばWHERE句が変わるqualの組み合わせごとに
+        // 個別にsnapshot/refreshが効く）。
+        let snapshot = opts.require_or("snapshot", "false") == "true";
+        let refresh = opts.require_or("refresh", "false") == "true";
+        let modified_column = opts.get("modified_column");
+        // gviz_headers = N を、gvizの"headers"パラメータとしてそのまま渡す（下記fetch_gviz_resp_json参照）。
+        let gviz_headers: Option<u32> = match opts.get("gviz_headers") {
+            Some(v) => Some(v.parse().map_err(|_| "gviz_headers must be a non-negative integer".to_owned())?),
+            None => None,
+        };
+        // expected_labels = 'col_a,col_b,...' で、gvizが返す列labelの並びが合意した契約通りかを
+        // begin_scan時点で検証する。normalize_headers = 'true' なら前後空白除去+小文字化した上で
+        // 比較する（見た目の表記ゆれは許容しつつ、追加/欠落/並び替えは検出したい場合向け）。
+        let expected_labels = parse_column_list_option(opts.get("expected_labels").as_deref());
+        let normalize_headers = opts.require_or("normalize_headers", "false") == "true";
+        // verbose = 'true' でプッシュダウンの内訳を、explain_url = 'true' で最終的なリクエストURLを
+        // それぞれreport_infoに出す。クエリのチューニング用のデバッグオプションなので既定はfalse。
+        let verbose = opts.require_or("verbose", "false") == "true";
+        this.verbose = verbose;
+        let explain_url = opts.require_or("explain_url", "false") == "true";
+        // pushdown_limit = 'true'（既定false）で、プランナーのLIMITヒント（ctx.get_limit）をgvizの
+        // tq句へ"limit N offset M"としてプッシュダウンする。JOINなどでプランナーのLIMITが実際の
+        // 必要行数と一致しない場合、素朴にプッシュダウンすると取得すべき行が足りなくなり得るため
+        // 既定は無効。有効時はend_scanで、出力行数がちょうどこのLIMITに達していれば
+        // 「本当はもっと必要だったかもしれない」旨をverbose時に警告する。
+        // pushdown_limitはgvizのtq句にしか実装していない（v4 APIのfields/rangesにLIMIT相当の
+        // 概念が無いため）。api_mode = 'v4'ではフラグ自体は受理しつつ何も起きない（既存の
+        // filter_view_id等とは逆に、gvizだけに存在する最適化なのでv4側をエラーにする理由が無い）。
+        let pushdown_limit = opts.require_or("pushdown_limit", "false") == "true" && this.api_mode != "v4";
+        this.pushed_limit = if pushdown_limit { ctx.get_limit().map(|l| l.count()) } else { None };
+        // pushdown_order_by = 'true'（既定false）で、プランナーが要求したソート順（ctx.get_sorts）を
+        // gvizのtq句へ"order by"としてプッシュダウンする。pushdown_limitと同じくgvizのtq句にしか
+        // 実装していない（v4 APIのfields/rangesにORDER BY相当の概念が無い）ためapi_mode = 'v4'では
+        // 何も起きない。重要: このオプションはgvizに実際に並べ替えさせて出力行の順序を変えるだけで、
+        // このFDWの実装しているWASMゲストインターフェースにはプランナーへスキャン結果のソート済みを
+        // 広告する手段（ネイティブFDWのGetForeignPaths相当）が無いため、merge joinを成立させることは
+        // できない（詳しくはbuild_order_by_clauseのドキュメント参照）。ORDER BYを要求するクエリでは
+        // プランナーは相変わらず自前でSortノードを追加する。
+        let pushdown_order_by = opts.require_or("pushdown_order_by", "false") == "true" && this.api_mode != "v4";
+        // pushdown_column_ref（既定'letter' | 'label'）: build_modified_since_clause/
+        // build_bool_equality_clause/build_like_clause/build_order_by_clauseがgvizのtq句へ列を
+        // 書き出す際に使う識別子の形式。既定のgviz（gviz_headers未使用）では列はシート上の
+        // レター（A, B, ...）でしか参照できず、gviz_headersを有効にしてヘッダー行をラベルとして
+        // 解釈させたときだけヘッダーテキストをラベルとして参照できるようになる。この2つの参照形式は
+        // 排他的で、噛み合わない組み合わせ（gviz_headers未使用なのに'label'、使っているのに
+        // 'letter'）で参照すると、gviz側でその識別子が見つからずtq全体がエラーになったり
+        // （url_without_tqへの自動フォールバックで気付かれないまま）沈黙的にプッシュダウンが
+        // 失敗する。既定は'letter'（gviz_headers未使用というgvizの標準状態に合わせる）。
+        let pushdown_column_ref = opts.require_or("pushdown_column_ref", "letter");
+        if !["letter", "label"].contains(&pushdown_column_ref.as_str()) {
+            return Err(format!(
+                "invalid pushdown_column_ref option '{}' (expected 'letter' or 'label')",
+                pushdown_column_ref
+            ));
+        }
+        // max_pushed_quals（既定3 = 現状存在するプッシュダウン可能なqualの種類数: modified_since/
+        // bool equality/LIKE）。非常に複雑なWHERE句が多数のqualを生んでtq句が巨大になり
+        // URL長超過でリクエスト自体が失敗する事態を避けるための上限。超過分はローカル評価へ
+        // フォールバックする（fetch_gviz_resp_json内、URL長そのものによる二段目のガードと併用）。
+        let max_pushed_quals: usize = opts
+            .require_or("max_pushed_quals", "3")
+            .parse()
+            .map_err(|_| "max_pushed_quals must be a non-negative integer".to_owned())?;
+        // rows_pointer（既定"/table/rows"）とcell_value_pointer（既定"/c/{i}/v"）。gvizのレスポンスを
+        // そのまま右で受けられないプロキシ（別のエンベロープに包み直す等）越しに使う場合の
+        // 逃げ道として、行配列/セル値を取り出すJSON Pointerを差し替え可能にする。cell_value_pointer
+        // は"{i}"をソース列インデックスへ置換するテンプレートで、末尾が"/v"である必要がある
+        // （"/f"（表示用文字列）側のポインタは、その末尾を"/f"に置き換えて機械的に導出するため）。
+        this.rows_pointer = opts.require_or("rows_pointer", "/table/rows");
+        if !this.rows_pointer.starts_with('/') {
+            return Err("rows_pointer must be a JSON Pointer starting with '/'".to_owned());
+        }
+        this.cell_value_pointer = opts.require_or("cell_value_pointer", "/c/{i}/v");
+        if !this.cell_value_pointer.contains("{i}") {
+            return Err("cell_value_pointer must contain the '{i}' placeholder".to_owned());
+        }
+        this.cell_formatted_pointer = this
+            .cell_value_pointer
+            .strip_suffix("/v")
+            .map(|prefix| format!("{}/f", prefix))
+            .ok_or("cell_value_pointer must end with '/v' (the sibling formatted-value pointer is derived from it)")?;
+
+        // introspect = 'rowcount' は行データを一切走査せず、シートのデータ行数だけをrow_count列1本の
+        // 1行として返す軽量モード（COUNT(*)プッシュダウンとは別物で、こちらはWHERE句を考慮しない
+        // シート全体の行数）。rowcount_include_trailing_blanks（既定'false'）で、Googleが確保している
+        // グリッドの宣言サイズ（末尾の未入力行を含む）まで数えるか、実際に値が入っている行だけを
+        // 数えるかを切り替える。
+        //   'false'（既定）: A列に実際の値が入っている行数を数える（gviz: tq=select count(A)、
+        //                    v4: values.get(A:A)で非空セルを数える）。
+        //   'true'         : シートのグリッドとして確保されている行数（v4のgridProperties.rowCount）
+        //                    からfrozenRowCountを引いた値。gvizにはグリッドの宣言サイズという概念が
+        //                    無いため、gvizモードでこちらを指定するとエラーにする（api_mode = 'v4'へ誘導）。
+        if opts.get("introspect").as_deref() == Some("rowcount") {
+            let include_trailing_blanks = opts.require_or("rowcount_include_trailing_blanks", "false") == "true";
+            let row_count = if this.api_mode == "v4" {
+                let service_account_json = this.service_account_json.clone().ok_or(
+                    "introspect = 'rowcount' with api_mode = 'v4' requires a 'service_account' server option",
+                )?;
+                let access_token = get_cached_v4_access_token(this, &service_account_json)?;
+                if include_trailing_blanks {
+                    let grid_row_count = fetch_v4_grid_row_count(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                    let frozen_row_count = fetch_v4_frozen_row_count(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                    grid_row_count - frozen_row_count as i64
+                } else {
+                    let sheet_title = fetch_v4_sheet_title(&spread_sheet_id, &access_token, sheet_id.as_deref(), &this.query_params)?;
+                    fetch_v4_column_a_non_blank_count(&spread_sheet_id, &access_token, &sheet_title, &this.query_params)?
+                }
+            } else {
+                if include_trailing_blanks {
+                    return Err(
+                        "rowcount_include_trailing_blanks = 'true' requires api_mode = 'v4' (gviz has no concept of the sheet's declared grid size)"
+                            .to_owned(),
+                    );
+                }
+                let url = resolve_templated_url(
+                    &this.base_url,
+                    &spread_sheet_id,
+                    sheet_id.as_deref(),
+                    &format!("{{base_url}}/{{id}}/{}?gid={{gid}}&tq={}", this.gviz_path, gviz_url_encode("select count(A)")),
+                    &format!("{{base_url}}/{{id}}/{}?tq={}", this.gviz_path, gviz_url_encode("select count(A)")),
+                )?;
+                let candidates = build_auth_candidates(&url, &auth_mode, &headers_public, &headers, jwt_header.clone(), api_key.as_deref())?;
+                let (body, _, _) = fetch_gviz_body_with_auth_fallback(
+                    &candidates,
+                    cache_ttl_secs,
+                    cache_max_entries,
+                    cache_max_bytes,
+                    circuit_threshold,
+                    circuit_window_secs,
+                    circuit_cooldown_secs,
+                    circuit_max_entries,
+                    verbose,
+                    snapshot,
+                    refresh,
+                    &this.query_params,
+                )?;
+                let stripped = strip_gviz_prefix(&body)?;
+                let resp_json: JsonValue = serde_json::from_str(stripped).map_err(|e| e.to_string())?;
+                resp_json
+                    .pointer("/table/rows/0/c/0/v")
+                    .and_then(|v| v.as_i64())
+                    .ok_or("cannot find the count(A) result in the gviz response")?
+            };
+            report_info(&format!("introspect rowcount mode: {} row(s)", row_count));
+            this.introspect_rowcount = Some(row_count);
+            return Ok(());
+        }
+        this.introspect_rowcount = None;
+
+        if spread_sheet_ids.len() > 1 {
+            // 複数のspread_sheet_idを、on_source_errorポリシーに従いながら1つのsrc_rowsに連結する。
+            // "error"（既定）はどれか1つでも失敗したら中断、"skip"は失敗したファイルを読み飛ばす。
+            let on_source_error = opts.require_or("on_source_error", "error");
+            // max_concurrency（既定1）: spread_sheet_ids（複数ファイル）取得を並列化したいという
+            // 要望向けのオプション。しかしsupabase-wrappers-witのhttpインターフェースは
+            // get/post/put/patch/deleteの全てが同期のfunc(request) -> http-resultとして
+            // 定義されており、pollable/futureのようなノンブロッキングI/Oの仕組みを一切
+            // 介さない（http.wit参照）。つまりゲスト側からホストへのHTTP呼び出しは常に
+            // 1本ずつ完了を待つブロッキング呼び出しであり、tokio::runtime::Builder（このクレートが
+            // 依存関係に持つが未使用）を使っても、ポーリングできる非同期I/Oソースが存在しない
+            // 以上スケジューリングする対象が無く、並行実行の助けにはならない
+            // （wasm32ゲストにOSスレッドが無いことも同様の理由になる）。そのためこの値は
+            // 検証のみ行い、実際の取得は引き続きspread_sheet_idsに列挙した順序で1件ずつ
+            // 順番に行う。これにより出力順は常に完了順ではなく指定順のまま決定的になる。
+            let max_concurrency: usize = opts.require_or("max_concurrency", "1").parse().unwrap_or(0);
+            if max_concurrency == 0 {
+                return Err("max_concurrency must be a positive integer".to_owned());
+            }
+            if max_concurrency > 1 {
+                report_warning(
+                    "max_concurrency > 1 has no effect yet: the http host binding only exposes synchronous, one-at-a-time requests, so spread_sheet_ids are still fetched sequentially in order",
+                );
+            }
+            this.src_rows.clear();
+            this.row_spread_sheet_ids.clear();
+            let mut first_cols: Option<Vec<JsonValue>> = None;
+            for id in &spread_sheet_ids {
+                let resp_json = match fetch_gviz_resp_json(
+                    &this.base_url,
+                    &this.gviz_path,
+                    id,
+                    sheet_id.as_deref(),
+                    gviz_headers,
+                    modified_column.as_deref(),
+                    ctx,
+                    &headers_public,
+                    &headers,
+                    &auth_mode,
+                    jwt_header.clone(),
+                    api_key.as_deref(),
+                    cache_ttl_secs,
+                    cache_max_entries,
+                    cache_max_bytes,
+                    circuit_threshold,
+                    circuit_window_secs,
+                    circuit_cooldown_secs,
+                    circuit_max_entries,
+                    verbose,
+                    explain_url,
+                    false,
+                    // spread_sheet_ids（複数ファイル連結）とpushdown_limitは、ファイル毎に独立して
+                    // LIMITを適用すると必要な行の偏りによって取得不足になり得るため併用しない。
+                    false,
+                    // pushdown_order_byはファイル単位でソートしても複数ファイル分を連結した後の
+                    // 全体順序が保証されないため、spread_sheet_idsとは併用しない。
+                    false,
+                    &this.column_order,
+                    &pushdown_column_ref,
+                    max_pushed_quals,
+                    snapshot,
+                    refresh,
+                    &this.query_params,
+                ) {
+                    Ok(v) => v,
+                    Err(e) if on_source_error == "skip" => {
+                        report_warning(&format!(
+                            "skipping spreadsheet '{}' because it failed to load: {}",
+                            id, e
+                        ));
+                        continue;
+                    }
+                    Err(e) => {
+                        return Err(format!("failed to fetch spreadsheet '{}': {}", id, e));
+                    }
+                };
+                let resp_json = select_gviz_table(&resp_json, table_index)
+                    .map_err(|e| format!("spreadsheet '{}': {}", id, e))?;
+                validate_expected_labels(&resp_json, &expected_labels, normalize_headers)
+                    .map_err(|e| format!("spreadsheet '{}': {}", id, e))?;
+                if first_cols.is_none() {
+                    first_cols = resp_json
+                        .pointer("/table/cols")
+                        .and_then(|v| v.as_array())
+                        .cloned();
+                }
+                let rows = resp_json
+                    .pointer(&this.rows_pointer)
+                    .and_then(|v| v.as_array())
+                    .ok_or_else(|| format!("cannot find rows at pointer '{}' in response (check rows_pointer)", this.rows_pointer))?
+                    .to_owned();
+                this.row_spread_sheet_ids
+                    .extend(std::iter::repeat(id.clone()).take(rows.len()));
+                this.src_rows.extend(rows);
+            }
+            this.introspect_cols = None;
+            this.introspect_meta = None;
+            this.introspect_spreadsheet = None;
+            this.introspect_developer_metadata = None;
+            this.introspect_protected_ranges = None;
+            this.introspect_rowcount = None;
+            this.column_patterns = first_cols
+                .map(|cols| {
+                    cols.iter()
+                        .map(|col| col.get("pattern").and_then(|v| v.as_str()).map(str::to_owned))
+                        .collect()
+                })
+                .unwrap_or_default();
+            this.locale = opts.require_or("locale", "en");
+            this.number_as_text_columns =
+                parse_column_list_option(opts.get("number_as_text_columns").as_deref());
+            this.required_columns = parse_column_list_option(opts.get("required_columns").as_deref());
+            this.on_row_error = opts.require_or("on_row_error", "error");
+            this.allowed_values = parse_column_value_set_option(opts.get("allowed_values").as_deref());
+            this.allowed_values_case_sensitive = opts.require_or("allowed_values_case_sensitive", "true") == "true";
+            this.max_row_errors = opts.require_or("max_row_errors", "0").parse().unwrap_or(0);
+            this.rows_skipped_for_errors = 0;
+            if this.max_row_errors == 0 && this.on_row_error == "skip" {
+                report_warning(
+                    "max_row_errors is unset (0 = unlimited); on_row_error = 'skip' can silently return zero rows for a broadly malformed sheet",
+                );
+            }
+            this.on_short_row = opts.require_or("on_short_row", "null");
+            if !["null", "skip", "error"].contains(&this.on_short_row.as_str()) {
+                return Err(format!(
+                    "invalid on_short_row option '{}' (expected 'null', 'skip' or 'error')",
+                    this.on_short_row
+                ));
+            }
+            this.keyvalue_rows = None;
+            this.unpivot_rows = None;
+            this.preserve_source_order = opts.require_or("preserve_source_order", "true") == "true";
+            this.synthetic_key_spread_sheet_id = spread_sheet_id.clone();
+            this.synthetic_key_sheet_id = sheet_id.clone().unwrap_or_default();
+            apply_skip_and_reverse_rows(
+                &mut this.src_rows,
+                &mut this.row_spread_sheet_ids,
+                skip_rows,
+                reverse_rows,
+            );
+            report_info(&format!(
+                "We got response array length: {} (from {} spreadsheets, order preserved: {})",
+                this.src_rows.len(),
+                spread_sheet_ids.len(),
+                this.preserve_source_order
+            ));
+            return Ok(());
+        }
+        this.row_spread_sheet_ids.clear();
+
+        // introspect = 'columns'/'meta' はcolsメタデータしか見ないため、スキーマ推論のために
+        // シート全体を取得しないよう"limit 1"相当のtq句を付けてgvizへ投げる（巨大シートでも
+        // 高速化するため）。v4側にはintrospect = 'columns'/'meta'相当のスキーマ推論経路が
+        // 存在しないため、この最適化はgvizモード限定。
+        let schema_only = matches!(opts.get("introspect").as_deref(), Some("columns") | Some("meta"));
+        let resp_json = fetch_gviz_resp_json(
+            &this.base_url,
+            &this.gviz_path,
+            &spread_sheet_id,
+            sheet_id.as_deref(),
+            gviz_headers,
+            modified_column.as_deref(),
+            ctx,
+            &headers_public,
+            &headers,
+            &auth_mode,
+            jwt_header,
+            api_key.as_deref(),
+            cache_ttl_secs,
+            cache_max_entries,
+            cache_max_bytes,
+            circuit_threshold,
+            circuit_window_secs,
+            circuit_cooldown_secs,
+            circuit_max_entries,
+            verbose,
+            explain_url,
+            schema_only,
+            pushdown_limit,
+            pushdown_order_by,
+            &this.column_order,
+            &pushdown_column_ref,
+            max_pushed_quals,
+            snapshot,
+            refresh,
+            &this.query_params,
+        )?;
+        let resp_json = select_gviz_table(&resp_json, table_index)?;
+        validate_expected_labels(&resp_json, &expected_labels, normalize_headers)?;
+
+        // introspect = 'meta' の場合は、column情報の推論結果ではなくgvizレスポンスの生メタデータ
+        // （id/label/type/pattern）とシート単位のstatus/warningsをそのまま返すモードにする。
+        // ツール構築や、列の型推論がおかしい原因を調べる際に使う。
+        if opts.get("introspect").as_deref() == Some("meta") {
+            let cols = resp_json
+                .pointer("/table/cols")
+                .and_then(|v| v.as_array())
+                .ok_or("cannot get column metadata from response")?;
+            let status = resp_json.get("status").cloned().unwrap_or(JsonValue::Null);
+            let warnings = resp_json.get("warnings").cloned().unwrap_or(JsonValue::Null);
+            this.introspect_meta = Some(
+                cols.iter()
+                    .enumerate()
+                    .map(|(i, col)| ColumnMetaInfo {
+                        ordinal: (i + 1) as i64,
+                        id: col.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+                        label: col
+                            .get("label")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or_default()
+                            .to_owned(),
+                        gviz_type: col
+                            .get("type")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("string")
+                            .to_owned(),
+                        pattern: col.get("pattern").cloned().unwrap_or(JsonValue::Null),
+                        status: status.clone(),
+                        warnings: warnings.clone(),
+                    })
+                    .collect(),
+            );
+            report_info(&format!(
+                "introspect meta mode: found {} source columns",
+                this.introspect_meta.as_ref().unwrap().len()
+            ));
+            return Ok(());
+        }
+        this.introspect_meta = None;
+        this.introspect_rowcount = None;
+
+        // introspect = 'columns' の場合は行データではなく列メタデータを返すモードにする。
+        // IMPORT FOREIGN SCHEMAで使うDDL生成ツール向けに、推測なしでソース列の型を確認できるようにする。
+        //
+        // 数百列ある雑多なシートをそのままインポートすると扱いづらいテーブルになるため、以下の
+        // 3つのオプションで結果を絞り込める（適用順: trim_trailing_empty -> columns -> max_columns）。
+        //   trim_trailing_empty = 'true' : 末尾の空labelが連続する列ブロックを丸ごと除外する
+        //                                  （シート上の未使用列を切り落とす想定）。
+        //   columns = 'A,B,...'          : labelがこのカンマ区切りリストに含まれる列だけを残す。
+        //   max_columns = 'N'            : 残った列の先頭からN列だけを残す。
+        // ここで返すordinalは常に元のソース上の列位置（1始まり）であり、絞り込みで詰め直されない。
+        // このモードはlabelをそのまま返すだけで正規化やdedup（同名列へのサフィックス付与等）は行わない。
+        // それらはこの結果を消費する側のDDL生成ツールの責務であり、絞り込みオプションを使った場合でも
+        // 変わらずlabel文字列はソースそのままなので、重複や記号混じりのlabelへの対処はそちら側で行う。
+        if opts.get("introspect").as_deref() == Some("columns") {
+            let cols = resp_json
+                .pointer("/table/cols")
+                .and_then(|v| v.as_array())
+                .ok_or("cannot get column metadata from response")?;
+            let mut col_infos: Vec<ColumnInfo> = cols
+                .iter()
+                .enumerate()
+                .map(|(i, col)| {
+                    let gviz_type = col
+                        .get("type")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("string")
+                        .to_owned();
+                    let pg_type = gviz_type_to_pg_type(&gviz_type).to_owned();
+                    let label = col
+                        .get("label")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_owned();
+                    let column_comment = build_column_comment(&label, None);
+                    ColumnInfo {
+                        ordinal: (i + 1) as i64,
+                        label,
+                        gviz_type,
+                        pg_type,
+                        header_note: None,
+                        column_comment,
+                    }
+                })
+                .collect();
+
+            if opts.require_or("trim_trailing_empty", "false") == "true" {
+                while col_infos.last().is_some_and(|c| c.label.is_empty()) {
+                    col_infos.pop();
+                }
+            }
+            if let Some(labels) = opts.get("columns") {
+                let keep = parse_column_list_option(Some(&labels));
+                col_infos.retain(|c| keep.contains(&c.label));
+            }
+            if let Some(max_columns) = opts.get("max_columns") {
+                let max_columns: usize = max_columns
+                    .parse()
+                    .map_err(|_| "max_columns must be a non-negative integer".to_owned())?;
+                col_infos.truncate(max_columns);
+            }
+
+            this.introspect_cols = Some(col_infos);
+            report_info(&format!(
+                "introspect mode: found {} source columns",
+                this.introspect_cols.as_ref().unwrap().len()
+            ));
+            return Ok(());
+        }
+        this.introspect_cols = None;
+        this.introspect_rowcount = None;
+        // 各列のpatternを覚えておき、iter_scanでF64列を"f"から復元する際の正規化に使う。
+        this.column_patterns = resp_json
+            .pointer("/table/cols")
+            .and_then(|v| v.as_array())
+            .map(|cols| {
+                cols.iter()
+                    .map(|col| col.get("pattern").and_then(|v| v.as_str()).map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // include_header_row用に、パターンと並行してlabelも保持しておく。
+        this.column_labels = resp_json
+            .pointer("/table/cols")
+            .and_then(|v| v.as_array())
+            .map(|cols| {
+                cols.iter()
+                    .map(|col| col.get("label").and_then(|v| v.as_str()).unwrap_or_default().to_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        this.locale = opts.require_or("locale", "en");
+        this.number_as_text_columns = parse_column_list_option(opts.get("number_as_text_columns").as_deref());
+        this.required_columns = parse_column_list_option(opts.get("required_columns").as_deref());
+        this.on_row_error = opts.require_or("on_row_error", "error");
+        this.allowed_values = parse_column_value_set_option(opts.get("allowed_values").as_deref());
+        this.allowed_values_case_sensitive = opts.require_or("allowed_values_case_sensitive", "true") == "true";
+        this.max_row_errors = opts.require_or("max_row_errors", "0").parse().unwrap_or(0);
+        this.rows_skipped_for_errors = 0;
+        if this.max_row_errors == 0 && this.on_row_error == "skip" {
+            report_warning(
+                "max_row_errors is unset (0 = unlimited); on_row_error = 'skip' can silently return zero rows for a broadly malformed sheet",
+            );
+        }
+        this.on_short_row = opts.require_or("on_short_row", "null");
+        if !["null", "skip", "error"].contains(&this.on_short_row.as_str()) {
+            return Err(format!(
+                "invalid on_short_row option '{}' (expected 'null', 'skip' or 'error')",
+                this.on_short_row
+            ));
+        }
+        this.preserve_source_order = opts.require_or("preserve_source_order", "true") == "true";
+        this.synthetic_key_spread_sheet_id = spread_sheet_id.clone();
+        this.synthetic_key_sheet_id = sheet_id.clone().unwrap_or_default();
+
+        // レスポンスからソースの行を抽出する（rows_pointerでパスを差し替え可能）
+        this.src_rows = resp_json
+            .pointer(&this.rows_pointer)
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| format!("cannot find rows at pointer '{}' in response (check rows_pointer)", this.rows_pointer))?
+            .to_owned();
+        apply_skip_and_reverse_rows(&mut this.src_rows, &mut this.row_spread_sheet_ids, skip_rows, reverse_rows);
+        // stop_at_blank_rows = 'true' : blank_run行以上連続する全空行が現れた時点をデータ末尾とみなし、
+        // それ以降を切り捨てる。書式だけが残る大きめのシートで空行を延々と返さないようにするための対策。
+        if opts.require_or("stop_at_blank_rows", "false") == "true" {
+            let blank_run: usize = opts
+                .require_or("blank_run", "1")
+                .parse()
+                .map_err(|_| "blank_run must be a positive integer".to_owned())?;
+            if blank_run == 0 {
+                return Err("blank_run must be a positive integer".to_owned());
+            }
+            let before = this.src_rows.len();
+            truncate_at_blank_run(&mut this.src_rows, blank_run);
+            if this.src_rows.len() < before {
+                report_info(&format!(
+                    "stop_at_blank_rows: truncated {} trailing row(s) after finding a run of {} blank row(s)",
+                    before - this.src_rows.len(),
+                    blank_run
+                ));
+            }
+        }
+        // Postgres INFO をユーザーに出力する（psql で表示可能）、デバッグにも便利
+        report_info(&format!(
+            "We got response array length: {} (order preserved: {})",
+            this.src_rows.len(),
+            this.preserve_source_order
+        ));
+
+        // keyvalue = 'true' の場合、1列目をkey・2列目をvalueとして解釈し、key/valueの2列固定スキーマで返す。
+        // 設定シート（key, value の2カラムだけのシート）をそのまま読める汎用モード。空のkeyは無視する。
+        this.keyvalue_rows = if opts.require_or("keyvalue", "false") == "true" {
+            let key_pointer = resolve_cell_pointer(&this.cell_value_pointer, 0);
+            let value_pointer = resolve_cell_pointer(&this.cell_value_pointer, 1);
+            Some(
+                this.src_rows
+                    .iter()
+                    .filter_map(|src_row| {
+                        let key = src_row.pointer(&key_pointer).and_then(gviz_cell_as_string)?;
+                        if key.trim().is_empty() {
+                            return None;
+                        }
+                        let value = src_row
+                            .pointer(&value_pointer)
+                            .and_then(gviz_cell_as_string)
+                            .unwrap_or_default();
+                        Some((key, value))
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        // unpivot = 'true' : ワイド形式（1行=1回答者、多数の設問列）の行を、(id列..., variable, value)の
+        // ロング形式に展開する。variableは元の列ラベル、valueはそのセルの値。unpivot_value_columnsに
+        // 指定した各ラベルにつき出力行を1行生成するため、出力行数は元の行数×melt対象列数になる。
+        // melt対象セルがgviz的に空（vが無い/空文字）の場合、valueはNULLとして返す（keyvalueモードの
+        // ような空文字への丸め込みはしない。「未回答」と「空文字で回答」を区別できるようにするため）。
+        this.unpivot_rows = if opts.require_or("unpivot", "false") == "true" {
+            if this.keyvalue_rows.is_some() {
+                return Err("unpivot and keyvalue cannot be used together".to_owned());
+            }
+            let id_columns = parse_column_list_option(opts.get("unpivot_id_columns").as_deref());
+            let value_columns = parse_column_list_option(opts.get("unpivot_value_columns").as_deref());
+            if value_columns.is_empty() {
+                return Err(
+                    "unpivot = 'true' requires unpivot_value_columns naming at least one source column label to melt"
+                        .to_owned(),
+                );
+            }
+            let cols = resp_json
+                .pointer("/table/cols")
+                .and_then(|v| v.as_array())
+                .ok_or("cannot get column metadata from response")?;
+            let index_of_label = |label: &str| {
+                cols.iter().position(|c| c.get("label").and_then(|v| v.as_str()) == Some(label))
+            };
+            let mut id_indices = Vec::with_capacity(id_columns.len());
+            for label in &id_columns {
+                let idx = index_of_label(label)
+                    .ok_or_else(|| format!("unpivot_id_columns: no source column labeled '{}'", label))?;
+                id_indices.push((label.clone(), idx));
+            }
+            let mut value_indices = Vec::with_capacity(value_columns.len());
+            for label in &value_columns {
+                let idx = index_of_label(label)
+                    .ok_or_else(|| format!("unpivot_value_columns: no source column labeled '{}'", label))?;
+                value_indices.push((label.clone(), idx));
+            }
+            let mut out = Vec::with_capacity(this.src_rows.len() * value_indices.len());
+            for src_row in &this.src_rows {
+                let id_values: Vec<(String, Option<String>)> = id_indices
+                    .iter()
+                    .map(|(label, idx)| {
+                        let pointer = resolve_cell_pointer(&this.cell_value_pointer, *idx);
+                        (label.clone(), src_row.pointer(&pointer).and_then(gviz_cell_as_string))
+                    })
+                    .collect();
+                for (label, idx) in &value_indices {
+                    let pointer = resolve_cell_pointer(&this.cell_value_pointer, *idx);
+                    let value = src_row.pointer(&pointer).and_then(gviz_cell_as_string);
+                    out.push(UnpivotRow { id_values: id_values.clone(), variable: label.clone(), value });
+                }
+            }
+            report_info(&format!(
+                "unpivot mode: expanded {} source row(s) into {} row(s) over {} melted column(s)",
+                this.src_rows.len(),
+                out.len(),
+                value_indices.len()
+            ));
+            Some(out)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    // この関数 iter_scan は、PostgreSQLのFDW（Foreign Data Wrapper）におけるデータスキャンの処理を行う部分です。
+    // ここでは、外部データソースからデータを取得し、PostgreSQLに対して返すための変換を行います。
+    fn iter_scan(ctx: &Context, row: &Row) -> Result<Option<u32>, FdwError> {
+        let this = Self::this_mut();
+
+        // limit_rows テーブルオプション（SQLのLIMITとは別の、テーブル側のハード上限）に達したら
+        // それ以上ソースを読み進めずスキャンを終える。プッシュダウンが効かないルックアップ
+        // （"id = X の1行だけ欲しい"等）で無駄な読み込みを避けるための安全弁。
+        if this.limit_rows > 0 && this.rows_emitted >= this.limit_rows {
+            return Ok(None);
+        }
+        let emitted = Self::iter_scan_inner(ctx, row)?;
+        if emitted.is_some() {
+            this.rows_emitted += 1;
+        }
+        Ok(emitted)
+    }
+
+    // ここからエラーと未サポート機能の関数。
+
+    fn re_scan(_ctx: &Context) -> FdwResult {
+        Err("re_scan on foreign table is not supported".to_owned())
+    }
+
+    fn end_scan(_ctx: &Context) -> FdwResult {
+        let this = Self::this_mut();
+        // collect_errors = 'true' で記録されたセル単位の変換エラーを、データ品質スナップショットとして
+        // まとめてreport_infoに出す。何も収集されていなければ（collect_errors無効、または全セル正常）
+        // 何も出力しない。
+        if !this.collected_cell_errors.is_empty() || this.cell_errors_dropped > 0 {
+            let mut report = format!(
+                "collect_errors: {} cell conversion error(s) recorded this scan",
+                this.collected_cell_errors.len()
+            );
+            if this.cell_errors_dropped > 0 {
+                report.push_str(&format!(
+                    " ({} additional error(s) dropped after reaching the {}-entry cap)",
+                    this.cell_errors_dropped, MAX_COLLECTED_CELL_ERRORS
+                ));
+            }
+            for entry in &this.collected_cell_errors {
+                report.push_str("\n  - ");
+                report.push_str(entry);
+            }
+            report_info(&report);
+        }
+        this.collected_cell_errors.clear();
+        this.cell_errors_dropped = 0;
+        // pushdown_limit = 'true' でLIMITをプッシュダウンした場合、出力行数がちょうどそのLIMITに
+        // 達していれば「gvizから受け取った分だけで打ち切った」ことを意味する。プランナーのLIMIT
+        // ヒントがJOIN等の事情で実際の必要行数と一致していなければ本来より少ない行しか
+        // 返せていない可能性があるため、verbose時にその旨を警告する。
+        if let Some(pushed_limit) = this.pushed_limit {
+            if this.verbose && pushed_limit >= 0 && this.rows_emitted as i64 == pushed_limit {
+                report_info(&format!(
+                    "pushdown_limit: scan stopped after exactly the pushed LIMIT {} row(s); if the planner needed more rows than this (e.g. due to a join), results may be incomplete",
+                    pushed_limit
+                ));
+            }
+        }
+        this.pushed_limit = None;
+        this.src_rows.clear();
+        this.introspect_cols = None;
+        this.introspect_meta = None;
+        this.introspect_spreadsheet = None;
+        this.introspect_developer_metadata = None;
+        this.introspect_protected_ranges = None;
+        this.introspect_rowcount = None;
+        this.keyvalue_rows = None;
+        this.unpivot_rows = None;
+        this.csv_rows = None;
+        this.row_spread_sheet_ids.clear();
+        this.v4_rows.clear();
+        this.v4_page_size = 0;
+        this.v4_page_rows.clear();
+        this.v4_page_start = 0;
+        this.v4_physical_row_offset = 0;
+        this.rows_emitted = 0;
+        this.header_row_emitted = false;
+        this.column_labels.clear();
+        this.debug_raw_body = None;
+        Ok(())
+    }
+
+    // このFDWは読み取り専用として設計されている。Sheets APIへの書き込み（append-based INSERT、
+    // insert_batch_sizeによるチャンク分割送信、失敗チャンクでの打ち切りとコミット済み件数の報告、
+    // チャンク間でのレート制限/リトライ考慮など）は、そもそもINSERTの土台自体が無いため
+    // begin_modifyの時点で導入できない。誤ってINSERT/UPDATE/DELETEが実行されるのを防ぐため、
+    // ここで明示的に拒否する。
+    fn begin_modify(_ctx: &Context) -> FdwResult {
+        Err("modify on foreign table is not supported".to_owned())
+    }
+
+    fn insert(_ctx: &Context, _row: &Row) -> FdwResult {
+        Ok(())
+    }
+
+    fn update(_ctx: &Context, _rowid: Cell, _row: &Row) -> FdwResult {
+        Ok(())
+    }
+
+    fn delete(_ctx: &Context, _rowid: Cell) -> FdwResult {
+        Ok(())
+    }
+
+    fn end_modify(_ctx: &Context) -> FdwResult {
+        Ok(())
+    }
+}
+
+// ⭐️ここから パース・セルマッピングのユニットテスト
+//
+// 注意: `Context`/`Row`/`Options`/`Qual`はsupabase:wrappers/typesがインポートする
+// WITリソース型で、ホスト（Postgres側のランタイム）だけがインスタンスを作れる不透明ハンドルであり、
+// ゲスト側であるこのクレートのコードからは（テストも含めて）コンストラクトできない。そのため、
+// `iter_scan`/`begin_scan`やそれらが`ctx`を渡して呼ぶ`fetch_gviz_resp_json`自体をエンドツーエンドで
+// 動かすテストは、wasmtime等でホストをモックするような本クレートの範囲外の仕組みなしには構築できない。
+// 代わりに、ここでは`iter_scan`/`begin_scan`が内部で使っている、`ctx`に依存しない純粋な
+// パース・セルマッピング関数を直接テストする。型OIDごとの変換ロジックと代表的なエラー経路は
+// これらの関数に集約されているため、実質的にリクエストされたテスト対象をカバーできる。
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // --- Bool ---
+
+    #[test]
+    fn gviz_literal_bool() {
+        assert_eq!(gviz_literal(&Cell::Bool(true)).unwrap(), Some("true".to_owned()));
+        assert_eq!(gviz_literal(&Cell::Bool(false)).unwrap(), Some("false".to_owned()));
+    }
+
+    #[test]
+    fn parse_bool_token_default_true_false() {
+        let true_values = vec!["true".to_owned(), "yes".to_owned(), "on".to_owned()];
+        let false_values = vec!["false".to_owned(), "no".to_owned(), "off".to_owned()];
+        assert_eq!(parse_bool_token("TRUE", &true_values, &false_values), Some(true));
+        assert_eq!(parse_bool_token("false", &true_values, &false_values), Some(false));
+    }
+
+    #[test]
+    fn parse_bool_token_default_yes_no() {
+        let true_values = vec!["true".to_owned(), "yes".to_owned(), "on".to_owned()];
+        let false_values = vec!["false".to_owned(), "no".to_owned(), "off".to_owned()];
+        assert_eq!(parse_bool_token("Yes", &true_values, &false_values), Some(true));
+        assert_eq!(parse_bool_token("no", &true_values, &false_values), Some(false));
+    }
+
+    #[test]
+    fn parse_bool_token_default_on_off() {
+        let true_values = vec!["true".to_owned(), "yes".to_owned(), "on".to_owned()];
+        let false_values = vec!["false".to_owned(), "no".to_owned(), "off".to_owned()];
+        assert_eq!(parse_bool_token(" ON ", &true_values, &false_values), Some(true));
+        assert_eq!(parse_bool_token("OFF", &true_values, &false_values), Some(false));
+    }
+
+    #[test]
+    fn parse_bool_token_custom_tokens() {
+        // bool_true_values/bool_false_valuesでy/nのような追加のカスタムトークンを認識できる。
+        let true_values = vec!["y".to_owned()];
+        let false_values = vec!["n".to_owned()];
+        assert_eq!(parse_bool_token("Y", &true_values, &false_values), Some(true));
+        assert_eq!(parse_bool_token("n", &true_values, &false_values), Some(false));
+    }
+
+    #[test]
+    fn parse_bool_token_unrecognized_returns_none() {
+        let true_values = vec!["true".to_owned(), "yes".to_owned(), "on".to_owned()];
+        let false_values = vec!["false".to_owned(), "no".to_owned(), "off".to_owned()];
+        assert_eq!(parse_bool_token("maybe", &true_values, &false_values), None);
+    }
+
+    // --- debug = 'raw_body' ---
+
+    #[test]
+    fn truncate_body_for_debug_under_limit_is_untouched() {
+        let (body, truncated) = truncate_body_for_debug("hello", 65536);
+        assert_eq!(body, "hello");
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn truncate_body_for_debug_cuts_at_byte_limit() {
+        let (body, truncated) = truncate_body_for_debug("hello world", 5);
+        assert_eq!(body, "hello");
+        assert!(truncated);
+    }
+
+    #[test]
+    fn truncate_body_for_debug_does_not_split_a_multibyte_char() {
+        // "あ" is 3 bytes in UTF-8; a limit of 2 must back off to the previous char boundary.
+        let (body, truncated) = truncate_body_for_debug("あ", 2);
+        assert_eq!(body, "");
+        assert!(truncated);
+    }
+
+    // --- LIKE/ILIKE pushdown ---
+
+    #[test]
+    fn classify_like_pattern_contains() {
+        assert_eq!(classify_like_pattern("%foo%"), Some(("contains", "foo")));
+    }
+
+    #[test]
+    fn classify_like_pattern_starts_with() {
+        assert_eq!(classify_like_pattern("foo%"), Some(("starts with", "foo")));
+    }
+
+    #[test]
+    fn classify_like_pattern_ends_with() {
+        assert_eq!(classify_like_pattern("%foo"), Some(("ends with", "foo")));
+    }
+
+    #[test]
+    fn classify_like_pattern_no_wildcard_falls_back() {
+        assert_eq!(classify_like_pattern("foo"), None);
+    }
+
+    #[test]
+    fn classify_like_pattern_middle_wildcard_falls_back() {
+        assert_eq!(classify_like_pattern("foo%bar"), None);
+    }
+
+    #[test]
+    fn classify_like_pattern_single_char_wildcard_falls_back() {
+        assert_eq!(classify_like_pattern("fo_%"), None);
+    }
+
+    #[test]
+    fn classify_like_pattern_escape_char_falls_back() {
+        assert_eq!(classify_like_pattern("100\\%%"), None);
+    }
+
+    #[test]
+    fn classify_like_pattern_bare_percent_falls_back() {
+        assert_eq!(classify_like_pattern("%"), None);
+    }
+
+    // --- constant option ---
+
+    #[test]
+    fn coerce_constant_value_typed() {
+        assert_eq!(coerce_constant_value("us", TypeOid::String).unwrap(), Cell::String("us".to_owned()));
+        assert_eq!(coerce_constant_value("42", TypeOid::I64).unwrap(), Cell::I64(42));
+        assert_eq!(coerce_constant_value("true", TypeOid::Bool).unwrap(), Cell::Bool(true));
+        assert_eq!(coerce_constant_value("FALSE", TypeOid::Bool).unwrap(), Cell::Bool(false));
+    }
+
+    #[test]
+    fn coerce_constant_value_rejects_invalid_values() {
+        assert!(coerce_constant_value("not-a-number", TypeOid::I64).is_err());
+        assert!(coerce_constant_value("maybe", TypeOid::Bool).is_err());
+    }
+
+    #[test]
+    fn resolve_default_raw_prefers_column_level_over_table_level() {
+        let default_columns = vec![("qty".to_owned(), "99".to_owned())];
+        assert_eq!(
+            resolve_default_raw("qty", TypeOid::I64, &default_columns, Some("0"), None, None),
+            Some("99")
+        );
+    }
+
+    #[test]
+    fn resolve_default_raw_falls_back_to_table_level_by_type() {
+        let default_columns = vec![];
+        assert_eq!(
+            resolve_default_raw("qty", TypeOid::I64, &default_columns, Some("0"), None, None),
+            Some("0")
+        );
+        assert_eq!(
+            resolve_default_raw("name", TypeOid::String, &default_columns, Some("0"), Some(""), None),
+            Some("")
+        );
+        assert_eq!(
+            resolve_default_raw("active", TypeOid::Bool, &default_columns, None, None, Some("false")),
+            Some("false")
+        );
+    }
+
+    #[test]
+    fn resolve_default_raw_returns_none_when_nothing_configured() {
+        assert_eq!(resolve_default_raw("qty", TypeOid::I64, &[], None, None, None), None);
+    }
+
+    #[test]
+    fn pushdown_letter_ref_uses_source_column_letters() {
+        assert_eq!(pushdown_letter_ref(1, &[]), "A");
+        assert_eq!(pushdown_letter_ref(3, &[]), "C");
+        // column_orderで並べ替えられている場合は、実際のソース列インデックスのレターになる。
+        assert_eq!(pushdown_letter_ref(1, &[4, 0, 1]), "E");
+    }
+
+    #[test]
+    fn pushdown_label_ref_wraps_and_escapes_backticks() {
+        assert_eq!(pushdown_label_ref("Full Name"), "`Full Name`");
+        assert_eq!(pushdown_label_ref("weird`label"), "`weird\\`label`");
+    }
+
+    #[test]
+    fn ragged_row_shortfall_detects_short_row() {
+        let src_row = serde_json::json!({ "c": [{ "v": 1.0 }, { "v": "a" }] });
+        // 3列（1,2,3始まりの宣言順、ソースインデックス0,1,2）を要求するが、行は2列しか無い。
+        assert_eq!(
+            ragged_row_shortfall(&src_row, &[1, 2, 3], &[]),
+            Some((2, 3))
+        );
+    }
+
+    #[test]
+    fn ragged_row_shortfall_accepts_exact_width_row() {
+        let src_row = serde_json::json!({ "c": [{ "v": 1.0 }, { "v": "a" }, { "v": true }] });
+        assert_eq!(ragged_row_shortfall(&src_row, &[1, 2, 3], &[]), None);
+    }
+
+    #[test]
+    fn ragged_row_shortfall_accepts_longer_ragged_row() {
+        // 末尾に余分な列がある分には問題にしない。
+        let src_row =
+            serde_json::json!({ "c": [{ "v": 1.0 }, { "v": "a" }, { "v": true }, { "v": "extra" }] });
+        assert_eq!(ragged_row_shortfall(&src_row, &[1, 2], &[]), None);
+    }
+
+    #[test]
+    fn ragged_row_shortfall_handles_missing_c_array() {
+        let src_row = serde_json::json!({});
+        assert_eq!(ragged_row_shortfall(&src_row, &[1], &[]), Some((0, 1)));
+    }
+
+    #[test]
+    fn ragged_row_shortfall_respects_column_order_remapping() {
+        // column_orderで3番目のソース列を要求している場合、実際のソース列インデックスを
+        // 見て必要幅を判定する。
+        let src_row = serde_json::json!({ "c": [{ "v": 1.0 }, { "v": "a" }] });
+        assert_eq!(ragged_row_shortfall(&src_row, &[1], &[2]), Some((2, 3)));
+    }
+
+    // --- I64 ---
+
+    #[test]
+    fn resolve_nonfinite_i64_path_null_default() {
+        // "NaN"/"Infinity"のような表示文字列がf64::from_strを通ってしまった場合でも、
+        // as i64キャストの前にNULLへ落ちること（既定のnonfinite = 'null'）を確認する。
+        assert_eq!(resolve_nonfinite(f64::NAN, "amount", "null").unwrap(), None);
+        assert_eq!(resolve_nonfinite(f64::INFINITY, "amount", "null").unwrap(), None);
+        assert_eq!(resolve_nonfinite(42.0, "amount", "null").unwrap(), Some(42.0));
+    }
+
+    #[test]
+    fn resolve_nonfinite_errors_when_configured() {
+        assert!(resolve_nonfinite(f64::NAN, "amount", "error").is_err());
+    }
+
+    #[test]
+    fn apply_scale_offset_i64_column() {
+        let scale = vec![("amount".to_owned(), 100.0)];
+        let offset = vec![("amount".to_owned(), 1.0)];
+        assert_eq!(apply_scale_offset(2.0, "amount", &scale, &offset), 201.0);
+        assert_eq!(apply_scale_offset(2.0, "other", &scale, &offset), 2.0);
+    }
+
+    // --- round_to ---
+
+    #[test]
+    fn round_half_to_even_rounds_down_to_even_at_boundary() {
+        // 2.5 -> 2 (2は偶数なので切り下げ)
+        assert_eq!(round_half_to_even(2.5, 0), 2.0);
+    }
+
+    #[test]
+    fn round_half_to_even_rounds_up_to_even_at_boundary() {
+        // 3.5 -> 4 (4は偶数なので切り上げ)
+        assert_eq!(round_half_to_even(3.5, 0), 4.0);
+    }
+
+    #[test]
+    fn round_half_to_even_applies_to_fractional_digits() {
+        // 0.125 を小数点以下2桁に丸める場合、0.12/0.13の中間なので偶数の0.12へ丸める。
+        assert_eq!(round_half_to_even(0.125, 2), 0.12);
+    }
+
+    #[test]
+    fn round_half_to_even_leaves_non_finite_untouched() {
+        assert!(round_half_to_even(f64::NAN, 2).is_nan());
+        assert_eq!(round_half_to_even(f64::INFINITY, 2), f64::INFINITY);
+    }
+
+    #[test]
+    fn apply_round_to_only_affects_configured_columns() {
+        let round_to = vec![("amount".to_owned(), 2)];
+        assert_eq!(apply_round_to(1.005, "amount", &round_to), 1.0);
+        assert_eq!(apply_round_to(1.005, "other", &round_to), 1.005);
+    }
+
+    // --- ranges / range_columns ---
+
+    #[test]
+    fn parse_range_column_map_option_splits_ranges_and_pipe_separated_columns() {
+        let parsed = parse_range_column_map_option(Some("Sheet1!A1:C10=col_a|col_b|col_c,Sheet1!E1:F10=col_d|col_e")).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                ("Sheet1!A1:C10".to_owned(), vec!["col_a".to_owned(), "col_b".to_owned(), "col_c".to_owned()]),
+                ("Sheet1!E1:F10".to_owned(), vec!["col_d".to_owned(), "col_e".to_owned()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_range_column_map_option_rejects_entry_without_columns() {
+        assert!(parse_range_column_map_option(Some("Sheet1!A1:C10=")).is_err());
+    }
+
+    #[test]
+    fn resolve_range_column_positions_maps_named_columns_to_their_number() {
+        let tgt_columns = vec![("id".to_owned(), 1), ("name".to_owned(), 2), ("amount".to_owned(), 3)];
+        let mapping = vec!["amount".to_owned(), "id".to_owned()];
+        assert_eq!(resolve_range_column_positions(Some(&mapping), &tgt_columns).unwrap(), vec![3, 1]);
+    }
+
+    #[test]
+    fn resolve_range_column_positions_falls_back_to_positional_when_unmapped() {
+        let tgt_columns = vec![("id".to_owned(), 1), ("name".to_owned(), 2)];
+        assert_eq!(resolve_range_column_positions(None, &tgt_columns).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn resolve_range_column_positions_rejects_unknown_column_name() {
+        let tgt_columns = vec![("id".to_owned(), 1)];
+        let mapping = vec!["missing".to_owned()];
+        assert!(resolve_range_column_positions(Some(&mapping), &tgt_columns).is_err());
+    }
+
+    #[test]
+    fn realign_v4_range_row_places_cells_at_mapped_positions_and_nulls_the_rest() {
+        let src_row = vec![
+            V4Cell { formatted_value: Some("42".to_owned()), ..Default::default() },
+            V4Cell { formatted_value: Some("hi".to_owned()), ..Default::default() },
+        ];
+        // 範囲の1列目→対象3列目、範囲の2列目→対象1列目。対象2列目はどちらの範囲列にも
+        // 対応が無いため、V4Cell::default()（null相当）のまま残る。
+        let position_map = vec![3, 1];
+        let out = realign_v4_range_row(&src_row, &position_map, 3);
+        assert_eq!(out[0].formatted_value.as_deref(), Some("hi"));
+        assert_eq!(out[1].formatted_value, None);
+        assert_eq!(out[2].formatted_value.as_deref(), Some("42"));
+    }
+
+    // --- column_timezones ---
+
+    #[test]
+    fn resolve_column_timezone_falls_back_to_table_default() {
+        let overrides = vec![("local_ts".to_owned(), "+09:00".to_owned())];
+        assert_eq!(resolve_column_timezone("local_ts", &overrides, "+00:00"), "+09:00");
+        assert_eq!(resolve_column_timezone("utc_ts", &overrides, "+00:00"), "+00:00");
+    }
+
+    // time::parse_from_rfc3339はWITホストインポートの実体で、実ホストが無い環境ではリンク/実行
+    // できない。http_get/report_warningの各テストと同じ理由で、Howard HinnantのdtsアルゴリズムE
+    // （days_from_civil）に基づく純粋なRust実装に差し替えてテストする。
+    fn rfc3339_to_epoch_secs_for_test(s: &str) -> Result<i64, String> {
+        let date_time = &s[0..19];
+        let offset_str = &s[19..];
+        let year: i64 = date_time[0..4].parse().map_err(|_| "bad year".to_owned())?;
+        let month: i64 = date_time[5..7].parse().map_err(|_| "bad month".to_owned())?;
+        let day: i64 = date_time[8..10].parse().map_err(|_| "bad day".to_owned())?;
+        let hour: i64 = date_time[11..13].parse().map_err(|_| "bad hour".to_owned())?;
+        let minute: i64 = date_time[14..16].parse().map_err(|_| "bad minute".to_owned())?;
+        let second: i64 = date_time[17..19].parse().map_err(|_| "bad second".to_owned())?;
+        let offset_secs: i64 = if offset_str == "Z" {
+            0
+        } else {
+            let sign = if offset_str.starts_with('-') { -1 } else { 1 };
+            let oh: i64 = offset_str[1..3].parse().map_err(|_| "bad offset hour".to_owned())?;
+            let om: i64 = offset_str[4..6].parse().map_err(|_| "bad offset minute".to_owned())?;
+            sign * (oh * 3600 + om * 60)
+        };
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = if y >= 0 { y } else { y - 399 } / 400;
+        let yoe = y - era * 400;
+        let mp = (month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days_since_epoch = era * 146_097 + doe - 719_468;
+        Ok(days_since_epoch * 86400 + hour * 3600 + minute * 60 + second - offset_secs)
+    }
+
+    #[test]
+    fn column_timezones_produce_different_epoch_ms_for_the_same_wall_clock_value() {
+        // 同じ壁時計表記（"2024-01-01 00:00:00"）でも、列ごとのtimezone上書きが異なれば
+        // 対応するUTCエポックミリ秒も異なる。
+        set_time_parse_from_rfc3339_override(Box::new(rfc3339_to_epoch_secs_for_test));
+        let overrides = vec![("local_ts".to_owned(), "+09:00".to_owned()), ("utc_ts".to_owned(), "+00:00".to_owned())];
+        let local_tz = resolve_column_timezone("local_ts", &overrides, "+00:00");
+        let utc_tz = resolve_column_timezone("utc_ts", &overrides, "+00:00");
+        let local_epoch_ms = parse_date_cell_to_epoch_ms("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S", local_tz).unwrap();
+        let utc_epoch_ms = parse_date_cell_to_epoch_ms("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S", utc_tz).unwrap();
+        assert_ne!(local_epoch_ms, utc_epoch_ms);
+        assert_eq!(utc_epoch_ms - local_epoch_ms, 9 * 3600 * 1000);
+        unsafe { TIME_PARSE_FROM_RFC3339_OVERRIDE = None };
+    }
+
+    // --- include_header_row ---
+
+    #[test]
+    fn header_row_labels_follow_declared_column_order() {
+        let column_labels = vec!["Name".to_owned(), "Age".to_owned(), "City".to_owned()];
+        let labels = header_row_labels(&column_labels, &[], &[1, 2, 3]);
+        assert_eq!(labels, vec!["Name", "Age", "City"]);
+    }
+
+    #[test]
+    fn header_row_labels_follow_column_order_remap() {
+        // column_order = "C,A,B" は、宣言列1番目がソース列C(index 2)、2番目がA(index 0)、
+        // 3番目がB(index 1)を読むように並べ替える。ヘッダー行も同じ対応で並ぶべき。
+        let column_labels = vec!["A".to_owned(), "B".to_owned(), "C".to_owned()];
+        let column_order = vec![2, 0, 1];
+        let labels = header_row_labels(&column_labels, &column_order, &[1, 2, 3]);
+        assert_eq!(labels, vec!["C", "A", "B"]);
+    }
+
+    #[test]
+    fn header_row_precedes_data_rows_in_emission_order() {
+        // iter_scanは1呼び出し=1行なので、include_header_rowが立っている間の最初の呼び出しで
+        // ヘッダー行を、以降の呼び出しでは通常のデータ行を返す、という順序をシミュレートする。
+        let column_labels = vec!["Name".to_owned(), "Age".to_owned()];
+        let data_rows = vec![
+            vec!["Alice".to_owned(), "30".to_owned()],
+            vec!["Bob".to_owned(), "40".to_owned()],
+        ];
+        let mut emitted: Vec<Vec<String>> = Vec::new();
+        emitted.push(header_row_labels(&column_labels, &[], &[1, 2]));
+        emitted.extend(data_rows.clone());
+        assert_eq!(emitted[0], column_labels);
+        assert_eq!(&emitted[1..], data_rows.as_slice());
+    }
+
+    // --- F64 ---
+
+    #[test]
+    fn strip_currency_and_separators_us_style() {
+        assert_eq!(strip_currency_and_separators("$1,234.50", None, "en"), "1234.50");
+    }
+
+    #[test]
+    fn strip_currency_and_separators_european_style() {
+        assert_eq!(
+            strip_currency_and_separators("1.234,50 €", Some("#.##0,00€"), "de"),
+            "1234.50"
+        );
+    }
+
+    // --- use_formatted / grouped integers ---
+
+    #[test]
+    fn parse_grouped_integer_us_style() {
+        assert_eq!(parse_grouped_integer("1,234,567", None, "en", "id").unwrap(), 1_234_567);
+    }
+
+    #[test]
+    fn parse_grouped_integer_european_style() {
+        assert_eq!(parse_grouped_integer("1.234.567", Some("#.##0"), "de", "id").unwrap(), 1_234_567);
+    }
+
+    #[test]
+    fn parse_grouped_integer_errors_on_fractional_part() {
+        assert!(parse_grouped_integer("1,234.50", None, "en", "id").is_err());
+    }
+
+    // --- strip_prefix / strip_suffix ---
+
+    #[test]
+    fn apply_strip_affixes_removes_matching_affixes() {
+        let prefixes = vec![("name".to_owned(), "\"".to_owned())];
+        let suffixes = vec![("name".to_owned(), "\"".to_owned())];
+        assert_eq!(apply_strip_affixes("\"Erlich\"", "name", &prefixes, &suffixes), "Erlich");
+    }
+
+    #[test]
+    fn apply_strip_affixes_leaves_non_matching_values_untouched() {
+        let prefixes = vec![("name".to_owned(), "\"".to_owned())];
+        let suffixes = vec![("name".to_owned(), "\"".to_owned())];
+        // 接頭辞/接尾辞が実際には付いていない値はそのまま通す。
+        assert_eq!(apply_strip_affixes("Erlich", "name", &prefixes, &suffixes), "Erlich");
+        // 対象外の列はオプションがあっても無加工。
+        assert_eq!(apply_strip_affixes("\"Erlich\"", "other", &prefixes, &suffixes), "\"Erlich\"");
+    }
+
+    #[test]
+    fn strip_leading_apostrophe_removes_a_single_leading_apostrophe_when_enabled() {
+        assert_eq!(strip_leading_apostrophe("'007", true), "007");
+    }
+
+    #[test]
+    fn strip_leading_apostrophe_leaves_values_without_one_untouched() {
+        assert_eq!(strip_leading_apostrophe("007", true), "007");
+    }
+
+    #[test]
+    fn strip_leading_apostrophe_is_a_no_op_when_disabled() {
+        assert_eq!(strip_leading_apostrophe("'007", false), "'007");
+    }
+
+    // --- String / null handling ---
+
+    #[test]
+    fn is_null_sentinel_case_and_whitespace_insensitive() {
+        let sentinels = vec!["N/A".to_owned(), "NULL".to_owned()];
+        assert!(is_null_sentinel("  n/a  ", &sentinels));
+        assert!(is_null_sentinel("null", &sentinels));
+        assert!(!is_null_sentinel("present", &sentinels));
+    }
+
+    #[test]
+    fn gviz_literal_string_escapes_quotes() {
+        let cell = Cell::String("say \"hi\"".to_owned());
+        assert_eq!(gviz_literal(&cell).unwrap(), Some("\"say \\\"hi\\\"\"".to_owned()));
+    }
+
+    // --- formula-error cells (String/error path) ---
+
+    #[test]
+    fn gviz_cell_error_detects_known_error_codes() {
+        let src_row = json!({ "c": [ { "v": null, "f": "#DIV/0!" } ] });
+        assert_eq!(gviz_cell_error(&src_row, "/c/0/v", "/c/0/f"), Some("#DIV/0!".to_owned()));
+    }
+
+    #[test]
+    fn gviz_cell_error_ignores_ordinary_blank_cells() {
+        let src_row = json!({ "c": [ { "v": null } ] });
+        assert_eq!(gviz_cell_error(&src_row, "/c/0/v", "/c/0/f"), None);
+    }
+
+    // --- rows_pointer / cell_value_pointer ---
+
+    #[test]
+    fn resolve_cell_pointer_substitutes_index() {
+        assert_eq!(resolve_cell_pointer("/c/{i}/v", 3), "/c/3/v");
+    }
+
+    #[test]
+    fn resolve_cell_pointer_supports_custom_template() {
+        assert_eq!(resolve_cell_pointer("/cells/{i}/value", 0), "/cells/0/value");
+    }
+
+    // --- Date / Timestamp ---
+
+    #[test]
+    fn parse_gviz_date_value_date_only() {
+        // gvizはJavaScript流に月を0始まりで返すため、1始まりに変換されていることを確認する。
+        assert_eq!(parse_gviz_date_value("Date(2023,0,15)").unwrap(), (2023, 1, 15, 0, 0, 0));
+    }
+
+    #[test]
+    fn parse_gviz_date_value_with_time() {
+        assert_eq!(
+            parse_gviz_date_value("Date(2023,11,31,23,59,5)").unwrap(),
+            (2023, 12, 31, 23, 59, 5)
+        );
+    }
+
+    #[test]
+    fn parse_gviz_date_value_rejects_malformed_literal() {
+        assert!(parse_gviz_date_value("2023-01-15").is_err());
+    }
+
+    // --- duration_as ---
+
+    #[test]
+    fn parse_duration_string_to_seconds_within_a_day() {
+        assert_eq!(parse_duration_string_to_seconds("01:02:03"), Some(3723));
+    }
+
+    #[test]
+    fn parse_duration_string_to_seconds_allows_more_than_24_hours() {
+        // [h]:mm:ss は経過時間書式なので、24時間を超える値も表せる。
+        assert_eq!(parse_duration_string_to_seconds("25:04:00"), Some(90240));
+    }
+
+    #[test]
+    fn parse_duration_string_to_seconds_rejects_out_of_range_minutes_or_seconds() {
+        assert_eq!(parse_duration_string_to_seconds("1:60:00"), None);
+        assert_eq!(parse_duration_string_to_seconds("1:00:60"), None);
+    }
+
+    #[test]
+    fn parse_duration_string_to_seconds_rejects_wrong_shape() {
+        assert_eq!(parse_duration_string_to_seconds("01:02"), None);
+        assert_eq!(parse_duration_string_to_seconds("not a duration"), None);
+    }
+
+    // --- empty-sheet case ---
+
+    #[test]
+    fn select_gviz_table_handles_zero_row_sheet() {
+        let resp_json = json!({ "table": { "cols": [{"label": "a"}], "rows": [] } });
+        let selected = select_gviz_table(&resp_json, 0).unwrap();
+        let rows = selected.pointer("/table/rows").and_then(|v| v.as_array()).unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn validate_expected_labels_accepts_matching_zero_row_sheet() {
+        let resp_json = json!({ "table": { "cols": [{"label": "Name"}], "rows": [] } });
+        let expected = vec!["Name".to_owned()];
+        assert!(validate_expected_labels(&resp_json, &expected, false).is_ok());
+    }
+
+    // --- malformed-prefix case ---
+
+    #[test]
+    fn strip_gviz_prefix_accepts_valid_prefix() {
+        assert_eq!(strip_gviz_prefix(")]}'\n{\"status\":\"ok\"}").unwrap(), "{\"status\":\"ok\"}");
+    }
+
+    #[test]
+    fn strip_gviz_prefix_rejects_html_login_redirect() {
+        // 認証に失敗した公開URLはgvizのプレフィックス無しでHTMLのログインページ等を返すことがある。
+        let html = "<!DOCTYPE html><html><body>Sign in</body></html>";
+        assert!(strip_gviz_prefix(html).is_err());
+    }
+
+    // --- auth strategy selection ---
+
+    #[test]
+    fn build_auth_candidates_auto_orders_public_first() {
+        let headers_public = vec![("user-agent".to_owned(), "Sheets FDW".to_owned())];
+        let headers_token = headers_public.clone();
+        let candidates =
+            build_auth_candidates("https://example.com", "auto", &headers_public, &headers_token, None, None).unwrap();
+        assert_eq!(candidates[0].0, "public");
+    }
+
+    #[test]
+    fn build_auth_candidates_jwt_requires_service_account() {
+        let headers_public = vec![];
+        let headers_token = vec![];
+        let err = build_auth_candidates("https://example.com", "jwt", &headers_public, &headers_token, None, None)
+            .unwrap_err();
+        assert!(err.contains("service_account"));
+    }
+
+    // --- http injection hook ---
+
+    #[test]
+    fn get_following_redirects_follows_location_header_via_override() {
+        set_http_get_override(Box::new(|req| {
+            if req.url == "https://example.com/start" {
+                Ok(http::Response {
+                    url: req.url.clone(),
+                    status_code: 302,
+                    headers: vec![("location".to_owned(), "https://example.com/final".to_owned())],
+                    body: String::default(),
+                })
+            } else {
+                Ok(http::Response {
+                    url: req.url.clone(),
+                    status_code: 200,
+                    headers: vec![],
+                    body: "done".to_owned(),
+                })
+            }
+        }));
+        let req = http::Request {
+            method: http::Method::Get,
+            url: "https://example.com/start".to_owned(),
+            headers: vec![],
+            body: String::default(),
+        };
+        let resp = get_following_redirects(req, &[]).unwrap();
+        assert_eq!(resp.body, "done");
+        unsafe { HTTP_GET_OVERRIDE = None };
+    }
+
+    // --- total_retry_budget ---
+
+    #[test]
+    fn total_retry_budget_aborts_before_per_kind_retries_are_exhausted() {
+        start_retry_policy(5, 5, 1);
+        set_http_get_override(Box::new(|_req| Err("connection refused".to_owned())));
+        // fetch_with_classified_retriesはリトライのたびにreport_warningを呼ぶ。utils::report_warning
+        // は実体がWITホストインポートなので、ここで潰しておかないとテストがリンクできない。
+        set_report_warning_override(Box::new(|_msg| {}));
+        let req = http::Request {
+            method: http::Method::Get,
+            url: "https://example.com/flaky".to_owned(),
+            headers: vec![],
+            body: String::default(),
+        };
+        // transport_max_retriesは5回まで許すが、total_retry_budgetが1しかないため、
+        // 1回リトライした時点で予算を使い切り、まだ4回残っている個別上限より先に諦める。
+        let err = fetch_with_classified_retries(&req).unwrap_err();
+        assert!(err.contains("total_retry_budget exhausted"), "{}", err);
+        unsafe { HTTP_GET_OVERRIDE = None };
+        unsafe { REPORT_WARNING_OVERRIDE = None };
+        start_retry_policy(0, 0, 0);
+    }
+
+    // --- skip_rows / reverse_rows ---
+
+    #[test]
+    fn apply_skip_and_reverse_rows_skips_before_reversing() {
+        let mut src_rows: Vec<JsonValue> = (0..5).map(|i| json!({"v": i})).collect();
+        let mut ids: Vec<String> = (0..5).map(|i| format!("sheet-{}", i)).collect();
+        apply_skip_and_reverse_rows(&mut src_rows, &mut ids, 2, true);
+        // 元は [0,1,2,3,4] -> 先頭2件（0,1）を捨てて [2,3,4] -> 反転して [4,3,2]
+        let values: Vec<i64> = src_rows.iter().map(|r| r["v"].as_i64().unwrap()).collect();
+        assert_eq!(values, vec![4, 3, 2]);
+        assert_eq!(ids, vec!["sheet-4", "sheet-3", "sheet-2"]);
+    }
+
+    #[test]
+    fn apply_skip_and_reverse_rows_without_reverse_just_skips() {
+        let mut src_rows: Vec<JsonValue> = (0..3).map(|i| json!({"v": i})).collect();
+        let mut ids: Vec<String> = vec![];
+        apply_skip_and_reverse_rows(&mut src_rows, &mut ids, 1, false);
+        let values: Vec<i64> = src_rows.iter().map(|r| r["v"].as_i64().unwrap()).collect();
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    // --- stop_at_blank_rows ---
+
+    fn gviz_row_of(cells: &[Option<&str>]) -> JsonValue {
+        json!({
+            "c": cells
+                .iter()
+                .map(|c| match c {
+                    Some(v) => json!({"v": v}),
+                    None => JsonValue::Null,
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    #[test]
+    fn truncate_at_blank_run_stops_at_first_run_reaching_threshold() {
+        let mut rows = vec![
+            gviz_row_of(&[Some("a")]),
+            gviz_row_of(&[Some("b")]),
+            gviz_row_of(&[None]),
+            gviz_row_of(&[None]),
+            gviz_row_of(&[Some("c")]), // しきい値（3）未満の2行連続の空行を挟むだけなら末尾判定には数えない
+            gviz_row_of(&[None]),
+            gviz_row_of(&[None]),
+            gviz_row_of(&[None]), // ここで3連続に達し、この連続の先頭の空行（6行目）から後ろが切り捨てられる
+            gviz_row_of(&[Some("d")]),
+        ];
+        truncate_at_blank_run(&mut rows, 3);
+        assert_eq!(rows.len(), 5);
+    }
+
+    #[test]
+    fn truncate_at_blank_run_leaves_rows_untouched_when_threshold_not_reached() {
+        let mut rows = vec![
+            gviz_row_of(&[Some("a")]),
+            gviz_row_of(&[None]),
+            gviz_row_of(&[None]),
+            gviz_row_of(&[Some("b")]),
+        ];
+        truncate_at_blank_run(&mut rows, 3);
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn truncate_at_blank_run_zero_threshold_is_a_no_op() {
+        let mut rows = vec![gviz_row_of(&[None]), gviz_row_of(&[None])];
+        truncate_at_blank_run(&mut rows, 0);
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[test]
+    fn is_gviz_row_blank_treats_empty_string_and_missing_c_as_blank() {
+        assert!(is_gviz_row_blank(&gviz_row_of(&[None, None])));
+        assert!(is_gviz_row_blank(&json!({"c": [{"v": ""}]})));
+        assert!(is_gviz_row_blank(&json!({})));
+        assert!(!is_gviz_row_blank(&gviz_row_of(&[Some("x")])));
+    }
+
+    // --- _is_empty ---
+
+    #[test]
+    fn row_is_empty_over_columns_full_row_is_not_empty() {
+        let row = gviz_row_of(&[Some("a"), Some("b"), Some("c")]);
+        assert!(!row_is_empty_over_columns(&row, &[1, 2, 3], &[]));
+    }
+
+    #[test]
+    fn row_is_empty_over_columns_all_blank_is_empty() {
+        let row = gviz_row_of(&[None, Some(""), None]);
+        assert!(row_is_empty_over_columns(&row, &[1, 2, 3], &[]));
+    }
+
+    #[test]
+    fn row_is_empty_over_columns_partial_row_is_not_empty() {
+        let row = gviz_row_of(&[None, Some("b"), None]);
+        assert!(!row_is_empty_over_columns(&row, &[1, 2, 3], &[]));
+    }
+
+    #[test]
+    fn row_is_empty_over_columns_only_looks_at_selected_columns() {
+        // column 2 (index 1) holds real data, but it isn't among the selected target columns,
+        // so it must not count against the "all blank" verdict.
+        let row = gviz_row_of(&[None, Some("b"), None]);
+        assert!(row_is_empty_over_columns(&row, &[1, 3], &[]));
+    }
+
+    // --- introspect = 'protected_ranges' ---
+
+    #[test]
+    fn protected_range_to_a1_whole_sheet_when_range_is_none() {
+        assert_eq!(protected_range_to_a1("Sheet1", None), "Sheet1");
+    }
+
+    #[test]
+    fn protected_range_to_a1_full_rectangle() {
+        let range = json!({
+            "startRowIndex": 1,
+            "endRowIndex": 5,
+            "startColumnIndex": 0,
+            "endColumnIndex": 3,
+        });
+        assert_eq!(protected_range_to_a1("Sheet1", Some(&range)), "Sheet1!A2:C5");
+    }
+
+    #[test]
+    fn protected_range_to_a1_open_ended_range() {
+        // endColumnIndexが省略された場合、開始セルのみのA1になる。
+        let range = json!({"startRowIndex": 0, "startColumnIndex": 2});
+        assert_eq!(protected_range_to_a1("Sheet1", Some(&range)), "Sheet1!C1");
+    }
+
+    // --- _synthetic_key ---
+
+    #[test]
+    fn build_synthetic_key_unique_within_a_scan() {
+        use std::collections::HashSet;
+        let keys: HashSet<String> = (0..100)
+            .map(|row_idx| build_synthetic_key("abc123", "456", row_idx))
+            .collect();
+        assert_eq!(keys.len(), 100);
+    }
+
+    #[test]
+    fn build_synthetic_key_format() {
+        assert_eq!(build_synthetic_key("abc123", "456", 7), "abc123:456:7");
+        assert_eq!(build_synthetic_key("abc123", "", 0), "abc123::0");
+    }
+
+    // --- _row_hash ---
+
+    #[test]
+    fn hash_row_hex_is_stable_for_identical_rows() {
+        let row = json!({"c": [{"v": "a"}, {"v": 1}]});
+        assert_eq!(hash_row_hex(&row), hash_row_hex(&row.clone()));
+    }
+
+    #[test]
+    fn hash_row_hex_differs_for_different_rows() {
+        let a = json!({"c": [{"v": "a"}]});
+        let b = json!({"c": [{"v": "b"}]});
+        assert_ne!(hash_row_hex(&a), hash_row_hex(&b));
+    }
+
+    #[test]
+    fn hash_row_hex_is_independent_of_which_columns_are_selected() {
+        // _row_hashはtgt_col側の選択・並び順を一切見ず、ソース行のJSON表現だけから決まる。
+        let row = json!({"c": [{"v": "a"}, {"v": "b"}]});
+        assert_eq!(hash_row_hex(&row), hash_row_hex(&row));
+        assert_eq!(hash_row_hex(&row).len(), 32);
+    }
+
+    // --- allowed_values ---
+
+    #[test]
+    fn parse_column_value_set_option_multiple_columns() {
+        let parsed = parse_column_value_set_option(Some("status=active:inactive:pending,category=a:b"));
+        assert_eq!(
+            parsed,
+            vec![
+                ("status".to_owned(), vec!["active".to_owned(), "inactive".to_owned(), "pending".to_owned()]),
+                ("category".to_owned(), vec!["a".to_owned(), "b".to_owned()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn enforce_allowed_values_case_sensitive_by_default() {
+        let allowed = vec![("status".to_owned(), vec!["Active".to_owned(), "Inactive".to_owned()])];
+        let (cell, skip) = enforce_allowed_values(
+            Some(Cell::String("active".to_owned())),
+            "status",
+            0,
+            &allowed,
+            true,
+            "null",
+        )
+        .unwrap();
+        assert_eq!(cell, None);
+        assert!(!skip);
+    }
+
+    #[test]
+    fn enforce_allowed_values_case_insensitive_toggle() {
+        let allowed = vec![("status".to_owned(), vec!["Active".to_owned(), "Inactive".to_owned()])];
+        let (cell, skip) = enforce_allowed_values(
+            Some(Cell::String("active".to_owned())),
+            "status",
+            0,
+            &allowed,
+            false,
+            "null",
+        )
+        .unwrap();
+        assert_eq!(cell, Some(Cell::String("active".to_owned())));
+        assert!(!skip);
+    }
+
+    #[test]
+    fn enforce_allowed_values_error_policy_names_value_and_row() {
+        let allowed = vec![("status".to_owned(), vec!["active".to_owned()])];
+        let err = enforce_allowed_values(Some(Cell::String("bogus".to_owned())), "status", 3, &allowed, true, "error")
+            .unwrap_err();
+        assert!(err.contains("bogus"), "error should name the offending value: {}", err);
+        assert!(err.contains('3'), "error should name the offending row: {}", err);
+    }
+
+    #[test]
+    fn enforce_allowed_values_skip_policy() {
+        let allowed = vec![("status".to_owned(), vec!["active".to_owned()])];
+        let (cell, skip) =
+            enforce_allowed_values(Some(Cell::String("bogus".to_owned())), "status", 0, &allowed, true, "skip").unwrap();
+        assert_eq!(cell, None);
+        assert!(skip);
+    }
+
+    #[test]
+    fn enforce_allowed_values_ignores_columns_without_a_configured_set() {
+        let allowed = vec![("status".to_owned(), vec!["active".to_owned()])];
+        let (cell, skip) =
+            enforce_allowed_values(Some(Cell::String("anything".to_owned())), "other", 0, &allowed, true, "error").unwrap();
+        assert_eq!(cell, Some(Cell::String("anything".to_owned())));
+        assert!(!skip);
+    }
+
+    // --- query_params option ---
+
+    #[test]
+    fn parse_query_params_option_accepts_json_object() {
+        let parsed = parse_query_params_option(Some(r#"{"token": "abc", "trace": "1"}"#)).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.contains(&("token".to_owned(), "abc".to_owned())));
+        assert!(parsed.contains(&("trace".to_owned(), "1".to_owned())));
+    }
+
+    #[test]
+    fn parse_query_params_option_accepts_kv_string() {
+        let parsed = parse_query_params_option(Some("token=abc&trace=1")).unwrap();
+        assert_eq!(parsed, vec![("token".to_owned(), "abc".to_owned()), ("trace".to_owned(), "1".to_owned())]);
+    }
+
+    #[test]
+    fn parse_query_params_option_rejects_malformed_kv_segment() {
+        let err = parse_query_params_option(Some("token=abc&bogus")).unwrap_err();
+        assert!(err.contains("bogus"));
+    }
+
+    #[test]
+    fn parse_query_params_option_defaults_to_empty_when_absent() {
+        assert_eq!(parse_query_params_option(None).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn append_query_params_does_not_clobber_the_fdws_own_tqx_and_tq_params() {
+        let url = "https://example.com/gviz/tq?gid=0&tqx=out:json&tq=select+A";
+        let query_params = vec![
+            ("tqx".to_owned(), "out:csv".to_owned()),
+            ("tq".to_owned(), "select+B".to_owned()),
+            ("token".to_owned(), "abc".to_owned()),
+        ];
+        let appended = append_query_params(url, &query_params);
+        assert!(appended.starts_with(url), "existing tqx/tq params must be left untouched: {}", appended);
+        assert!(appended.ends_with("&token=abc"));
+    }
+
+    #[test]
+    fn append_query_params_url_encodes_keys_and_values() {
+        let appended = append_query_params("https://example.com/x", &[("a b".to_owned(), "c&d".to_owned())]);
+        assert_eq!(appended, format!("https://example.com/x?{}={}", gviz_url_encode("a b"), gviz_url_encode("c&d")));
+    }
+
+    #[test]
+    fn append_query_params_is_noop_for_empty_list() {
+        let url = "https://example.com/x?gid=0";
+        assert_eq!(append_query_params(url, &[]), url);
+    }
+
+    // --- validate_v4_only_options ---
+
+    #[test]
+    fn validate_v4_only_options_accepts_a_plain_gviz_table() {
+        validate_v4_only_options(
+            "gviz", false, false, false, false, false, "formatted", false, false, false, 0, false, false, false, false, false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_v4_only_options_accepts_a_fully_configured_v4_table() {
+        validate_v4_only_options(
+            "v4", true, true, true, true, true, "unformatted", false, false, false, 0, false, false, true, false, false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_v4_only_options_reports_every_gviz_incompatible_option_at_once() {
+        let err = validate_v4_only_options(
+            "gviz", false, true, true, false, false, "formula", true, false, false, 50, false, false, false, false, false,
+        )
+        .unwrap_err();
+        assert!(err.contains("note_of_columns"), "{}", err);
+        assert!(err.contains("hyperlink_of"), "{}", err);
+        assert!(err.contains("value_render"), "{}", err);
+        assert!(err.contains("sheet_index"), "{}", err);
+        assert!(err.contains("page_size"), "{}", err);
+        assert!(!err.contains("color_of"), "should not flag options that were not set: {}", err);
+    }
+
+    #[test]
+    fn validate_v4_only_options_reports_missing_service_account_for_v4() {
+        let err = validate_v4_only_options(
+            "v4", false, false, false, false, false, "formatted", false, false, false, 0, false, false, false, false, false,
+        )
+        .unwrap_err();
+        assert!(err.contains("service_account"), "{}", err);
+    }
+
+    #[test]
+    fn validate_v4_only_options_reports_mutually_exclusive_v4_options() {
+        let err = validate_v4_only_options(
+            "v4", true, false, false, false, false, "formatted", false, true, true, 0, false, false, false, false, false,
+        )
+        .unwrap_err();
+        assert!(err.contains("filter_view_id cannot be combined with start_cell"), "{}", err);
+    }
+
+    #[test]
+    fn validate_v4_only_options_reports_ranges_conflicts_independently_of_api_mode() {
+        let err = validate_v4_only_options(
+            "gviz", false, false, false, false, false, "formatted", false, false, false, 10, false, false, true, false, false,
+        )
+        .unwrap_err();
+        assert!(err.contains("ranges requires api_mode = 'v4'"), "{}", err);
+        assert!(err.contains("ranges cannot be combined with page_size"), "{}", err);
+    }
+
+    #[test]
+    fn validate_v4_only_options_reports_skip_and_reverse_rows_conflicts_with_page_size() {
+        let err = validate_v4_only_options(
+            "v4", true, false, false, false, false, "formatted", false, false, false, 50, false, false, false, true, true,
+        )
+        .unwrap_err();
+        assert!(err.contains("skip_rows cannot be combined with page_size"), "{}", err);
+        assert!(err.contains("reverse_rows cannot be combined with page_size"), "{}", err);
     }
 }
+// ⭐️ここまで パース・セルマッピングのユニットテスト
 
 // SpreadsheetsFdwをFDWとしてエクスポートしています。
 bindings::export!(SpreadsheetsFdw with_types_in bindings);